@@ -0,0 +1,176 @@
+#include <errno.h>
+#include <fcntl.h>
+#include <signal.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/syscall.h>
+#include <sys/utsname.h>
+#include <sys/wait.h>
+#include <time.h>
+#include <unistd.h>
+
+/*
+ * The system calls are made directly, as the C library may use their 64-bit
+ * time variants instead. The structures are the ones of the 32-bit ABI.
+ */
+
+struct timeval32 {
+    int32_t tv_sec;
+    int32_t tv_usec;
+};
+
+struct timespec32 {
+    int32_t tv_sec;
+    int32_t tv_nsec;
+};
+
+struct tms32 {
+    int32_t tms_utime;
+    int32_t tms_stime;
+    int32_t tms_cutime;
+    int32_t tms_cstime;
+};
+
+struct stat64_32 {
+    uint64_t st_dev;
+    uint32_t __pad1;
+    uint32_t __st_ino;
+    uint32_t st_mode;
+    uint32_t st_nlink;
+    uint32_t st_uid;
+    uint32_t st_gid;
+    uint64_t st_rdev;
+    uint32_t __pad2;
+    int64_t st_size;
+    uint32_t st_blksize;
+    uint64_t st_blocks;
+    uint32_t st_atime_sec;
+    uint32_t st_atime_nsec;
+    uint32_t st_mtime_sec;
+    uint32_t st_mtime_nsec;
+    uint32_t st_ctime_sec;
+    uint32_t st_ctime_nsec;
+    uint64_t st_ino;
+} __attribute__((packed));
+
+struct dirent64_32 {
+    uint64_t d_ino;
+    int64_t d_off;
+    uint16_t d_reclen;
+    uint8_t d_type;
+    char d_name[];
+};
+
+#define FILE_NAME "ia32_test"
+#define FILE_PATH "/dev/shm/" FILE_NAME
+
+/* Fork with the stack pointer of the parent, which the child gets a copy of.
+ * musl forks with `SYS_fork`, which is not supported. */
+static pid_t clone_child(void)
+{
+    long ret;
+    __asm__ volatile("mov %%esp, %%ecx\n\t"
+                     "int $0x80"
+                     : "=a"(ret)
+                     : "0"(SYS_clone), "b"(SIGCHLD), "d"(0), "S"(0), "D"(0)
+                     : "ecx", "memory");
+    return ret;
+}
+
+static int fail(const char *what)
+{
+    printf("ia32: %s failed\n", what);
+    return 1;
+}
+
+int main()
+{
+    struct utsname uts;
+    if (syscall(SYS_uname, &uts) != 0 || strcmp(uts.sysname, "Linux") != 0)
+        return fail("uname");
+
+    if (syscall(SYS_getppid) < 0)
+        return fail("getppid");
+
+    struct timeval32 tv;
+    if (syscall(SYS_gettimeofday, &tv, NULL) != 0 || tv.tv_sec <= 0 || tv.tv_usec < 0 ||
+        tv.tv_usec >= 1000000)
+        return fail("gettimeofday");
+
+    struct tms32 tms;
+    if (syscall(SYS_times, &tms) == -1)
+        return fail("times");
+
+    struct timespec32 ts;
+    if (syscall(SYS_clock_gettime, CLOCK_MONOTONIC, &ts) != 0)
+        return fail("clock_gettime");
+    ts.tv_nsec += 1000000;
+    if (ts.tv_nsec >= 1000000000) {
+        ts.tv_sec++;
+        ts.tv_nsec -= 1000000000;
+    }
+    if (syscall(SYS_clock_nanosleep, CLOCK_MONOTONIC, TIMER_ABSTIME, &ts, NULL) != 0)
+        return fail("clock_nanosleep");
+    if (syscall(SYS_clock_nanosleep, CLOCK_THREAD_CPUTIME_ID, 0, &ts, NULL) != -1 ||
+        errno != EINVAL)
+        return fail("clock_nanosleep on a CPU-time clock");
+
+    pid_t child = clone_child();
+    if (child == 0)
+        _exit(7);
+    int status;
+    if (child < 0 || syscall(SYS_waitpid, child, &status, 0) != child || !WIFEXITED(status) ||
+        WEXITSTATUS(status) != 7)
+        return fail("waitpid");
+    if (syscall(SYS_waitpid, -1, &status, 0) != -1 || errno != ECHILD)
+        return fail("waitpid without children");
+
+    int fds[2];
+    char buf[16];
+    if (syscall(SYS_pipe, fds) != 0 || write(fds[1], "pipe", 4) != 4 ||
+        read(fds[0], buf, sizeof(buf)) != 4 || memcmp(buf, "pipe", 4) != 0)
+        return fail("pipe");
+    close(fds[0]);
+    close(fds[1]);
+
+    int fd = open(FILE_PATH, O_CREAT | O_RDWR | O_TRUNC, 0644);
+    if (fd < 0 || write(fd, "0123456789", 10) != 10)
+        return fail("open");
+
+    uint64_t pos = 0;
+    if (syscall(SYS__llseek, fd, 0, 4, &pos, SEEK_SET) != 0 || pos != 4 ||
+        read(fd, buf, 2) != 2 || memcmp(buf, "45", 2) != 0)
+        return fail("_llseek");
+    if (syscall(SYS__llseek, fd, -1, -1, &pos, SEEK_END) != 0 || pos != 9)
+        return fail("_llseek to the end");
+
+    struct stat64_32 st;
+    if (syscall(SYS_stat64, FILE_PATH, &st) != 0 || st.st_size != 10)
+        return fail("stat64");
+    if (syscall(SYS_fstatat64, AT_FDCWD, FILE_PATH, &st, 0) != 0 || st.st_size != 10)
+        return fail("fstatat64");
+    close(fd);
+
+    int dir = open("/dev/shm", O_RDONLY | O_DIRECTORY);
+    if (dir < 0)
+        return fail("opening /dev/shm");
+    char dents[512];
+    int found = 0;
+    long len;
+    while ((len = syscall(SYS_getdents64, dir, dents, sizeof(dents))) > 0) {
+        for (long off = 0; off < len;) {
+            struct dirent64_32 *d = (struct dirent64_32 *)(dents + off);
+            if (strcmp(d->d_name, FILE_NAME) == 0)
+                found = 1;
+            off += d->d_reclen;
+        }
+    }
+    close(dir);
+    if (len < 0 || !found)
+        return fail("getdents64");
+    unlink(FILE_PATH);
+
+    printf("ia32 syscalls test passed!\n");
+    return 0;
+}
@@ -0,0 +1,87 @@
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/mman.h>
+#include <sys/personality.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "../include/test_fork.h"
+
+#define SELF "/initrd/bin/aslr_c"
+
+struct layout {
+    uintptr_t stack;
+    uintptr_t mmap;
+    uintptr_t brk;
+};
+
+static struct layout layout(void)
+{
+    int local;
+    struct layout layout = {
+        .stack = (uintptr_t)&local,
+        .mmap = (uintptr_t)mmap(NULL, 4096, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0),
+        .brk = syscall(SYS_brk, 0),
+    };
+    return layout;
+}
+
+/* Run this program again, with `ADDR_NO_RANDOMIZE` if `fixed`, and get its
+ * layout. */
+static int child_layout(int fixed, struct layout *out)
+{
+    int fds[2];
+    if (pipe(fds) != 0)
+        return -1;
+    pid_t child = test_fork();
+    if (child == 0) {
+        char fd[16];
+        snprintf(fd, sizeof(fd), "%d", fds[1]);
+        char *argv[] = {"aslr_c", fd, NULL};
+        if (fixed)
+            personality(ADDR_NO_RANDOMIZE);
+        execv(SELF, argv);
+        _exit(1);
+    }
+    int status;
+    int ok = child > 0 && waitpid(child, &status, 0) == child && WIFEXITED(status) &&
+             WEXITSTATUS(status) == 0 && read(fds[0], out, sizeof(*out)) == sizeof(*out);
+    close(fds[0]);
+    close(fds[1]);
+    return ok ? 0 : -1;
+}
+
+static int same(struct layout a, struct layout b)
+{
+    return a.stack == b.stack && a.mmap == b.mmap && a.brk == b.brk;
+}
+
+int main(int argc, char **argv)
+{
+    if (argc == 2) {
+        struct layout own = layout();
+        return write(atoi(argv[1]), &own, sizeof(own)) == sizeof(own) ? 0 : 1;
+    }
+
+    struct layout first, second;
+    if (child_layout(0, &first) != 0 || child_layout(0, &second) != 0) {
+        printf("ASLR: failed to run a child\n");
+        return 1;
+    }
+    if (same(first, second)) {
+        printf("ASLR: two runs got the same layout\n");
+        return 1;
+    }
+    if (child_layout(1, &first) != 0 || child_layout(1, &second) != 0) {
+        printf("ASLR: failed to run a child without randomization\n");
+        return 1;
+    }
+    if (!same(first, second)) {
+        printf("ASLR: the layout is randomized with ADDR_NO_RANDOMIZE\n");
+        return 1;
+    }
+    printf("ASLR test passed!\n");
+    return 0;
+}
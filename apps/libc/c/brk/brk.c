@@ -0,0 +1,63 @@
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/resource.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+#define SIZE (1UL << 20)
+#define PAGE_SIZE 4096
+
+static uintptr_t brk_to(uintptr_t addr)
+{
+    return syscall(SYS_brk, addr);
+}
+
+int main()
+{
+    uintptr_t start = brk_to(0);
+    if (brk_to(start + SIZE) != start + SIZE) {
+        printf("brk: failed to grow\n");
+        return 1;
+    }
+    memset((void *)start, 0xaa, SIZE);
+
+    /* The pages above the break are freed, and come back zero-filled. */
+    if (brk_to(start) != start) {
+        printf("brk: failed to shrink\n");
+        return 1;
+    }
+    if (brk_to(start + SIZE) != start + SIZE) {
+        printf("brk: failed to grow again\n");
+        return 1;
+    }
+    for (size_t i = 0; i < SIZE; i += PAGE_SIZE) {
+        if (((unsigned char *)start)[i] != 0) {
+            printf("brk: the pages above the break were kept\n");
+            return 1;
+        }
+    }
+    brk_to(start);
+
+    struct rlimit limit;
+    if (getrlimit(RLIMIT_DATA, &limit) != 0) {
+        printf("brk: getrlimit failed\n");
+        return 1;
+    }
+    limit.rlim_cur = SIZE / 2;
+    if (setrlimit(RLIMIT_DATA, &limit) != 0) {
+        printf("brk: setrlimit failed\n");
+        return 1;
+    }
+    if (brk_to(start + SIZE) != start) {
+        printf("brk: grew beyond RLIMIT_DATA\n");
+        return 1;
+    }
+    if (brk_to(start + SIZE / 4) != start + SIZE / 4) {
+        printf("brk: failed to grow within RLIMIT_DATA\n");
+        return 1;
+    }
+    brk_to(start);
+    printf("brk test passed!\n");
+    return 0;
+}
@@ -0,0 +1,76 @@
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "../include/test_fork.h"
+
+#define SELF "/initrd/bin/cloexec_c"
+#define PATH "/dev/shm/cloexec_test"
+
+/* Run after the exec: `keep` must still be open, and `closed` closed. */
+static int check_after_exec(int keep, int closed)
+{
+    if (fcntl(keep, F_GETFD) != 0)
+        return 1;
+    errno = 0;
+    if (fcntl(closed, F_GETFD) != -1 || errno != EBADF)
+        return 2;
+    return 0;
+}
+
+int main(int argc, char **argv)
+{
+    if (argc == 3)
+        return check_after_exec(atoi(argv[1]), atoi(argv[2]));
+
+    int keep = open(PATH, O_RDWR | O_CREAT, 0600);
+    int closed = open(PATH, O_RDWR | O_CLOEXEC);
+    if (keep < 0 || closed < 0) {
+        printf("cloexec: open failed\n");
+        return 1;
+    }
+    if (fcntl(keep, F_GETFD) != 0 || fcntl(closed, F_GETFD) != FD_CLOEXEC) {
+        printf("cloexec: F_GETFD does not report O_CLOEXEC\n");
+        return 1;
+    }
+
+    pid_t child = test_fork();
+    if (child == 0) {
+        char keep_arg[16], closed_arg[16];
+        snprintf(keep_arg, sizeof(keep_arg), "%d", keep);
+        snprintf(closed_arg, sizeof(closed_arg), "%d", closed);
+        char *args[] = {"cloexec_c", keep_arg, closed_arg, NULL};
+        execv(SELF, args);
+        _exit(3);
+    }
+    int status;
+    if (child < 0 || waitpid(child, &status, 0) != child) {
+        printf("cloexec: fork failed\n");
+        return 1;
+    }
+    if (status != 0) {
+        printf("cloexec: the exec'd child failed at step %d\n", WEXITSTATUS(status));
+        return 1;
+    }
+
+    /* The exec of the child closes nothing in the parent. */
+    if (fcntl(closed, F_GETFD) != FD_CLOEXEC || fcntl(keep, F_GETFD) != 0) {
+        printf("cloexec: the exec of the child changed the descriptors of the parent\n");
+        return 1;
+    }
+
+    /* F_SETFD changes the flag both ways. */
+    if (fcntl(keep, F_SETFD, FD_CLOEXEC) != 0 || fcntl(keep, F_GETFD) != FD_CLOEXEC ||
+        fcntl(closed, F_SETFD, 0) != 0 || fcntl(closed, F_GETFD) != 0) {
+        printf("cloexec: F_SETFD failed\n");
+        return 1;
+    }
+    close(keep);
+    close(closed);
+    unlink(PATH);
+    printf("CLOEXEC test passed!\n");
+    return 0;
+}
@@ -0,0 +1,84 @@
+#include <errno.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+
+/* Machine code for `return 42;` and `return 7;`. */
+#if defined(__x86_64__)
+static const uint8_t RETURN_42[] = {0xb8, 0x2a, 0x00, 0x00, 0x00, 0xc3};
+static const uint8_t RETURN_7[] = {0xb8, 0x07, 0x00, 0x00, 0x00, 0xc3};
+#elif defined(__aarch64__)
+static const uint32_t RETURN_42[] = {0x52800540, 0xd65f03c0};
+static const uint32_t RETURN_7[] = {0x528000e0, 0xd65f03c0};
+#elif defined(__riscv)
+static const uint32_t RETURN_42[] = {0x02a00513, 0x00008067};
+static const uint32_t RETURN_7[] = {0x00700513, 0x00008067};
+#endif
+
+#define PAGE_SIZE 4096
+
+typedef int (*code_fn)(void);
+
+/* Write `code` to the RW page `page`, make it executable and call it. */
+static int run(uint8_t *page, const void *code, size_t len)
+{
+    if (mprotect(page, PAGE_SIZE, PROT_READ | PROT_WRITE) != 0) {
+        printf("icache: mprotect to RW failed\n");
+        return -1;
+    }
+    memcpy(page, code, len);
+    if (mprotect(page, PAGE_SIZE, PROT_READ | PROT_EXEC) != 0) {
+        printf("icache: mprotect to RX failed\n");
+        return -1;
+    }
+    return ((code_fn)page)();
+}
+
+int main()
+{
+    uint8_t *page =
+        mmap(NULL, PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (page == MAP_FAILED) {
+        printf("icache: mmap failed\n");
+        return 1;
+    }
+
+    /* mprotect to executable must make the new code visible to the CPU. */
+    if (run(page, RETURN_42, sizeof(RETURN_42)) != 42) {
+        printf("icache: the first code did not run\n");
+        return 1;
+    }
+    if (run(page, RETURN_7, sizeof(RETURN_7)) != 7) {
+        printf("icache: stale code ran after rewriting it\n");
+        return 1;
+    }
+    munmap(page, PAGE_SIZE);
+
+    /* A writable and executable mapping works with an explicit cache flush,
+     * unless W^X is enforced. */
+    uint8_t *rwx = mmap(NULL, PAGE_SIZE, PROT_READ | PROT_WRITE | PROT_EXEC,
+                        MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (rwx == MAP_FAILED) {
+        if (errno != EACCES) {
+            printf("icache: RWX mmap failed with %d\n", errno);
+            return 1;
+        }
+    } else {
+        memcpy(rwx, RETURN_42, sizeof(RETURN_42));
+        __builtin___clear_cache((char *)rwx, (char *)rwx + sizeof(RETURN_42));
+        if (((code_fn)rwx)() != 42) {
+            printf("icache: the RWX code did not run\n");
+            return 1;
+        }
+        memcpy(rwx, RETURN_7, sizeof(RETURN_7));
+        __builtin___clear_cache((char *)rwx, (char *)rwx + sizeof(RETURN_7));
+        if (((code_fn)rwx)() != 7) {
+            printf("icache: stale RWX code ran after rewriting it\n");
+            return 1;
+        }
+        munmap(rwx, PAGE_SIZE);
+    }
+    printf("icache test passed!\n");
+    return 0;
+}
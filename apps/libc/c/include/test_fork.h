@@ -0,0 +1,55 @@
+#ifndef TEST_FORK_H
+#define TEST_FORK_H
+
+#include <signal.h>
+#include <sys/syscall.h>
+#include <sys/types.h>
+
+/* Fork with the stack pointer of the parent, which the child gets a copy of,
+ * so that the child goes on from here like after `fork`. musl forks with
+ * `SYS_fork` or with `clone` without a stack, which are not supported.
+ *
+ * The parent goes on once the child has exited. Returns the negated error
+ * number on failure. */
+static inline pid_t test_fork(void)
+{
+#if defined(__x86_64__)
+    register long r10 __asm__("r10") = 0;
+    register long r8 __asm__("r8") = 0;
+    long ret;
+    __asm__ volatile("mov %%rsp, %%rsi\n\t"
+                     "syscall"
+                     : "=a"(ret)
+                     : "0"(SYS_clone), "D"(SIGCHLD), "d"(0), "r"(r10), "r"(r8)
+                     : "rsi", "rcx", "r11", "memory");
+    return ret;
+#elif defined(__aarch64__)
+    register long x8 __asm__("x8") = SYS_clone;
+    register long x0 __asm__("x0") = SIGCHLD;
+    register long x2 __asm__("x2") = 0;
+    register long x3 __asm__("x3") = 0;
+    register long x4 __asm__("x4") = 0;
+    __asm__ volatile("mov x1, sp\n\t"
+                     "svc #0"
+                     : "+r"(x0)
+                     : "r"(x8), "r"(x2), "r"(x3), "r"(x4)
+                     : "x1", "memory");
+    return x0;
+#elif defined(__riscv)
+    register long a7 __asm__("a7") = SYS_clone;
+    register long a0 __asm__("a0") = SIGCHLD;
+    register long a2 __asm__("a2") = 0;
+    register long a3 __asm__("a3") = 0;
+    register long a4 __asm__("a4") = 0;
+    __asm__ volatile("mv a1, sp\n\t"
+                     "ecall"
+                     : "+r"(a0)
+                     : "r"(a7), "r"(a2), "r"(a3), "r"(a4)
+                     : "a1", "memory");
+    return a0;
+#else
+#error "test_fork is not implemented for this architecture"
+#endif
+}
+
+#endif
@@ -0,0 +1,61 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+#define SIZE 4096
+#define SHM_NAME "/memfd_test"
+
+static int fail(const char *what)
+{
+    printf("memfd: %s\n", what);
+    shm_unlink(SHM_NAME);
+    return 1;
+}
+
+int main()
+{
+    int fd = memfd_create("memfd_test", MFD_CLOEXEC | MFD_ALLOW_SEALING);
+    if (fd < 0 || ftruncate(fd, SIZE) != 0)
+        return fail("memfd_create failed");
+    char *map = mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    if (map == MAP_FAILED)
+        return fail("mmap failed");
+    strcpy(map, "memfd");
+    char buf[8] = {0};
+    if (read(fd, buf, 6) != 6 || strcmp(buf, "memfd") != 0)
+        return fail("read does not see a store to the mapping");
+
+    if (fcntl(fd, F_ADD_SEALS, F_SEAL_SHRINK | F_SEAL_GROW) != 0)
+        return fail("F_ADD_SEALS failed");
+    if (fcntl(fd, F_GET_SEALS) != (F_SEAL_SHRINK | F_SEAL_GROW))
+        return fail("F_GET_SEALS does not report the seals");
+    if (ftruncate(fd, 2 * SIZE) != -1 || errno != EPERM)
+        return fail("a sealed memfd grew");
+    munmap(map, SIZE);
+    close(fd);
+
+    /* POSIX shared memory lives in /dev/shm and outlives its descriptors. */
+    fd = shm_open(SHM_NAME, O_RDWR | O_CREAT | O_EXCL, 0600);
+    if (fd < 0 || ftruncate(fd, SIZE) != 0)
+        return fail("shm_open failed");
+    map = mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    if (map == MAP_FAILED)
+        return fail("mmap of the shared memory object failed");
+    strcpy(map, "posix");
+    munmap(map, SIZE);
+    close(fd);
+    fd = shm_open(SHM_NAME, O_RDONLY, 0);
+    if (fd < 0 || read(fd, buf, 6) != 6 || strcmp(buf, "posix") != 0)
+        return fail("the shared memory object lost its contents");
+    close(fd);
+    if (shm_unlink(SHM_NAME) != 0 || shm_open(SHM_NAME, O_RDONLY, 0) != -1) {
+        printf("memfd: shm_unlink failed\n");
+        return 1;
+    }
+    printf("memfd test passed!\n");
+    return 0;
+}
@@ -0,0 +1,59 @@
+#include <fcntl.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "../include/test_fork.h"
+
+/* More than the memory of the machine. */
+#define SIZE (1UL << 30)
+/* The exit code of a process killed by the OOM killer, as for SIGKILL. */
+#define OOM_KILL_EXIT_CODE (128 + 9)
+
+static int set_oom_score_adj(const char *adj)
+{
+    int fd = open("/proc/self/oom_score_adj", O_WRONLY);
+    if (fd < 0)
+        return -1;
+    ssize_t len = write(fd, adj, strlen(adj));
+    close(fd);
+    return len == (ssize_t)strlen(adj) ? 0 : -1;
+}
+
+int main()
+{
+    if (set_oom_score_adj("-1000") != 0) {
+        printf("OOM: failed to set oom_score_adj\n");
+        return 1;
+    }
+    pid_t child = test_fork();
+    if (child == 0) {
+        set_oom_score_adj("1000");
+        uint64_t *mem =
+            mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+        if (mem == MAP_FAILED)
+            _exit(1);
+        /* Random data does not compress, so the pages cannot be swapped
+         * out either. */
+        uint64_t seed = 1;
+        for (size_t i = 0; i < SIZE / sizeof(uint64_t); i++) {
+            seed = seed * 6364136223846793005ULL + 1442695040888963407ULL;
+            mem[i] = seed;
+        }
+        _exit(2);
+    }
+    int status;
+    if (child < 0 || waitpid(child, &status, 0) != child) {
+        printf("OOM: failed to wait for the child\n");
+        return 1;
+    }
+    if (!WIFEXITED(status) || WEXITSTATUS(status) != OOM_KILL_EXIT_CODE) {
+        printf("OOM: the child exited with status %#x\n", status);
+        return 1;
+    }
+    printf("OOM test passed!\n");
+    return 0;
+}
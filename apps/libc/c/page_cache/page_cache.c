@@ -0,0 +1,71 @@
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+#define PATH "/dev/shm/page_cache_test"
+#define SIZE 8192
+
+/* `pread` and `pwrite` are not supported. */
+static ssize_t read_at(int fd, void *buf, size_t len, off_t offset)
+{
+    return lseek(fd, offset, SEEK_SET) == offset ? read(fd, buf, len) : -1;
+}
+
+static ssize_t write_at(int fd, const void *buf, size_t len, off_t offset)
+{
+    return lseek(fd, offset, SEEK_SET) == offset ? write(fd, buf, len) : -1;
+}
+
+static int fail(const char *what)
+{
+    printf("Page cache: %s\n", what);
+    unlink(PATH);
+    return 1;
+}
+
+int main()
+{
+    int fd = open(PATH, O_RDWR | O_CREAT | O_TRUNC, 0600);
+    if (fd < 0)
+        return fail("open failed");
+    char buf[SIZE];
+    memset(buf, 'a', SIZE);
+    if (write(fd, buf, SIZE) != SIZE)
+        return fail("write failed");
+
+    char *shared = mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    char *other = mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    char *private = mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, 0);
+    if (shared == MAP_FAILED || other == MAP_FAILED || private == MAP_FAILED)
+        return fail("mmap failed");
+    if (memcmp(shared, buf, SIZE) != 0)
+        return fail("the mapping does not show what was written");
+
+    /* Stores to a shared mapping are seen by read and the other mappings. */
+    memcpy(shared + 4096, "mapped", 6);
+    if (read_at(fd, buf, 6, 4096) != 6 || memcmp(buf, "mapped", 6) != 0)
+        return fail("read does not see a store to the mapping");
+    if (memcmp(other + 4096, "mapped", 6) != 0)
+        return fail("another mapping does not see a store");
+
+    /* Writes are seen by the mappings. */
+    if (write_at(fd, "written", 7, 100) != 7)
+        return fail("write failed");
+    if (memcmp(shared + 100, "written", 7) != 0)
+        return fail("the mapping does not see a write");
+
+    /* Stores to a private mapping stay private. */
+    private[200] = 'p';
+    if (read_at(fd, buf, 1, 200) != 1 || buf[0] != 'a' || shared[200] != 'a')
+        return fail("a store to a private mapping reached the file");
+
+    munmap(shared, SIZE);
+    munmap(other, SIZE);
+    munmap(private, SIZE);
+    close(fd);
+    unlink(PATH);
+    printf("Page cache test passed!\n");
+    return 0;
+}
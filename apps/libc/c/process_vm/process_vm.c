@@ -0,0 +1,72 @@
+#define _GNU_SOURCE
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/uio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "../include/test_fork.h"
+
+/* Twice a page, so that the copies cross a page boundary. */
+static char buf[8192];
+
+/* Run in the child: read the buffer of the parent, and write it back changed. */
+static int child_main(void)
+{
+    static char copy[sizeof(buf)];
+    pid_t parent = getppid();
+    struct iovec local = {copy, sizeof(copy)};
+    struct iovec remote = {buf, sizeof(buf)};
+
+    /* The child has its own copy of the buffer, so clear it to be sure the
+     * data comes from the parent. */
+    memset(buf, 0, sizeof(buf));
+    if (process_vm_readv(parent, &local, 1, &remote, 1, 0) != sizeof(buf))
+        return 1;
+    if (strcmp(copy, "from the parent") != 0 || strcmp(copy + 4090, "across pages") != 0)
+        return 2;
+
+    strcpy(copy, "from the child");
+    if (process_vm_writev(parent, &local, 1, &remote, 1, 0) != sizeof(buf))
+        return 3;
+
+    /* Nothing can be copied from an unmapped remote buffer. */
+    struct iovec null_remote = {NULL, 16};
+    errno = 0;
+    if (process_vm_readv(parent, &local, 1, &null_remote, 1, 0) != -1 || errno != EFAULT)
+        return 4;
+    return 0;
+}
+
+int main()
+{
+    strcpy(buf, "from the parent");
+    strcpy(buf + 4090, "across pages");
+
+    struct iovec local = {buf, sizeof(buf)};
+    errno = 0;
+    if (process_vm_readv(-1, &local, 1, &local, 1, 0) != -1 || errno != ESRCH) {
+        printf("process_vm: an invalid pid does not fail with ESRCH\n");
+        return 1;
+    }
+
+    pid_t child = test_fork();
+    if (child == 0)
+        _exit(child_main());
+    int status;
+    if (child < 0 || waitpid(child, &status, 0) != child) {
+        printf("process_vm: fork failed\n");
+        return 1;
+    }
+    if (status != 0) {
+        printf("process_vm: the child failed at step %d\n", WEXITSTATUS(status));
+        return 1;
+    }
+    if (strcmp(buf, "from the child") != 0 || strcmp(buf + 4090, "across pages") != 0) {
+        printf("process_vm: the child did not write to the parent\n");
+        return 1;
+    }
+    printf("process_vm test passed!\n");
+    return 0;
+}
@@ -0,0 +1,81 @@
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/resource.h>
+#include <unistd.h>
+
+#define SIZE (8UL << 20)
+#define PAGE_SIZE 4096
+/* Allow for the pages used by the test itself. */
+#define SLACK_PAGES 256
+
+/* Get the size and the resident set size from /proc/self/statm, in pages. */
+static int statm(long *size, long *resident)
+{
+    char buf[128];
+    int fd = open("/proc/self/statm", O_RDONLY);
+    if (fd < 0)
+        return -1;
+    ssize_t len = read(fd, buf, sizeof(buf) - 1);
+    close(fd);
+    if (len <= 0)
+        return -1;
+    buf[len] = 0;
+    return sscanf(buf, "%ld %ld", size, resident) == 2 ? 0 : -1;
+}
+
+int main()
+{
+    long pages = SIZE / PAGE_SIZE;
+    long size, resident, mapped_size, touched_resident, unmapped_size, unmapped_resident;
+    struct rusage before, after;
+    if (statm(&size, &resident) != 0 || getrusage(RUSAGE_SELF, &before) != 0) {
+        printf("rusage: failed to get the memory usage\n");
+        return 1;
+    }
+
+    char *mem = mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (mem == MAP_FAILED || statm(&mapped_size, &touched_resident) != 0) {
+        printf("rusage: mmap failed\n");
+        return 1;
+    }
+    if (mapped_size < size + pages || touched_resident > resident + SLACK_PAGES) {
+        printf("rusage: mapping changed statm to %ld %ld from %ld %ld\n", mapped_size,
+               touched_resident, size, resident);
+        return 1;
+    }
+
+    memset(mem, 1, SIZE);
+    if (statm(&mapped_size, &touched_resident) != 0 || getrusage(RUSAGE_SELF, &after) != 0) {
+        printf("rusage: failed to get the memory usage\n");
+        return 1;
+    }
+    if (touched_resident < resident + pages) {
+        printf("rusage: resident set of %ld pages after touching %ld pages, from %ld\n",
+               touched_resident, pages, resident);
+        return 1;
+    }
+    if (after.ru_maxrss < before.ru_maxrss + (long)(SIZE / 1024)) {
+        printf("rusage: ru_maxrss went from %ld kB to %ld kB\n", before.ru_maxrss,
+               after.ru_maxrss);
+        return 1;
+    }
+
+    munmap(mem, SIZE);
+    if (statm(&unmapped_size, &unmapped_resident) != 0 || getrusage(RUSAGE_SELF, &after) != 0) {
+        printf("rusage: failed to get the memory usage\n");
+        return 1;
+    }
+    if (unmapped_size > size + SLACK_PAGES || unmapped_resident > resident + SLACK_PAGES) {
+        printf("rusage: statm is %ld %ld after munmap, from %ld %ld\n", unmapped_size,
+               unmapped_resident, size, resident);
+        return 1;
+    }
+    if (after.ru_maxrss < before.ru_maxrss + (long)(SIZE / 1024)) {
+        printf("rusage: ru_maxrss dropped after munmap\n");
+        return 1;
+    }
+    printf("rusage test passed!\n");
+    return 0;
+}
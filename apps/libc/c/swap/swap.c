@@ -0,0 +1,66 @@
+#include <stdint.h>
+#include <stdio.h>
+#include <sys/mman.h>
+
+/* More than the memory of the machine, so that most of it is swapped out. */
+#define SIZE (160UL << 20)
+#define PAGE_SIZE 4096
+#define WORDS (PAGE_SIZE / sizeof(uint64_t))
+
+static long vm_swap_kb(void)
+{
+    FILE *status = fopen("/proc/self/status", "r");
+    char line[64];
+    long kb = -1;
+    if (!status)
+        return -1;
+    while (fgets(line, sizeof(line), status)) {
+        if (sscanf(line, "VmSwap: %ld kB", &kb) == 1)
+            break;
+    }
+    fclose(status);
+    return kb;
+}
+
+/* The first word of each page differs from the rest, so that the page is
+ * compressed into a literal and runs. */
+static uint64_t pattern(size_t page)
+{
+    return page * 0x9e3779b97f4a7c15ULL;
+}
+
+int main()
+{
+    uint64_t *mem = mmap(NULL, SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    if (mem == MAP_FAILED) {
+        printf("Swap: mmap failed\n");
+        return 1;
+    }
+    size_t pages = SIZE / PAGE_SIZE;
+    for (size_t i = 0; i < pages; i++) {
+        uint64_t *page = mem + i * WORDS;
+        page[0] = i;
+        for (size_t j = 1; j < WORDS; j++)
+            page[j] = pattern(i);
+    }
+    if (vm_swap_kb() <= 0) {
+        printf("Swap: no pages were swapped out\n");
+        return 1;
+    }
+    for (size_t i = 0; i < pages; i++) {
+        uint64_t *page = mem + i * WORDS;
+        if (page[0] != i) {
+            printf("Swap: page %zu lost its first word\n", i);
+            return 1;
+        }
+        for (size_t j = 1; j < WORDS; j++) {
+            if (page[j] != pattern(i)) {
+                printf("Swap: page %zu is corrupted at word %zu\n", i, j);
+                return 1;
+            }
+        }
+    }
+    munmap(mem, SIZE);
+    printf("Swap test passed!\n");
+    return 0;
+}
@@ -0,0 +1,61 @@
+#include <stdio.h>
+#include <string.h>
+#include <sys/ipc.h>
+#include <sys/shm.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#include "../include/test_fork.h"
+
+#define SIZE 8192
+
+static int fail(int id, const char *what)
+{
+    printf("System V shared memory: %s\n", what);
+    shmctl(id, IPC_RMID, NULL);
+    return 1;
+}
+
+int main()
+{
+    int id = shmget(IPC_PRIVATE, SIZE, IPC_CREAT | 0600);
+    if (id < 0) {
+        printf("System V shared memory: shmget failed\n");
+        return 1;
+    }
+    char *first = shmat(id, NULL, 0);
+    char *second = shmat(id, NULL, 0);
+    if (first == (void *)-1 || second == (void *)-1 || first == second)
+        return fail(id, "shmat failed");
+    if (first[0] != 0 || first[SIZE - 1] != 0)
+        return fail(id, "the segment is not zero-filled");
+
+    strcpy(first + 4096, "attached twice");
+    if (strcmp(second + 4096, "attached twice") != 0)
+        return fail(id, "the attachments do not share the segment");
+
+    struct shmid_ds ds;
+    if (shmctl(id, IPC_STAT, &ds) != 0 || ds.shm_segsz != SIZE || ds.shm_nattch != 2)
+        return fail(id, "IPC_STAT reports the wrong size or attachments");
+
+    /* The child inherits the attachments, which stay shared. */
+    pid_t child = test_fork();
+    if (child == 0) {
+        strcpy(second, "written by the child");
+        _exit(0);
+    }
+    int status;
+    if (child < 0 || waitpid(child, &status, 0) != child || status != 0)
+        return fail(id, "the child failed");
+    if (strcmp(first, "written by the child") != 0)
+        return fail(id, "a store of the child is not seen by the parent");
+
+    if (shmdt(first) != 0 || shmdt(second) != 0)
+        return fail(id, "shmdt failed");
+    if (shmctl(id, IPC_RMID, NULL) != 0) {
+        printf("System V shared memory: IPC_RMID failed\n");
+        return 1;
+    }
+    printf("System V shared memory test passed!\n");
+    return 0;
+}
@@ -0,0 +1,27 @@
+#include <stdio.h>
+
+static __thread int initialized = 42;
+static __thread long zeroed[4];
+__thread const char *message = "TLS";
+
+int main()
+{
+    if (initialized != 42 || message[0] != 'T') {
+        printf("TLS: wrong initial data\n");
+        return 1;
+    }
+    for (int i = 0; i < 4; i++) {
+        if (zeroed[i] != 0) {
+            printf("TLS: .tbss is not zeroed\n");
+            return 1;
+        }
+    }
+    initialized++;
+    zeroed[3] = (long)&initialized;
+    if (initialized != 43 || zeroed[3] != (long)&initialized) {
+        printf("TLS: writes are lost\n");
+        return 1;
+    }
+    printf("TLS test passed!\n");
+    return 0;
+}
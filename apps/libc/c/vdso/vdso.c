@@ -0,0 +1,98 @@
+#include <elf.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/auxv.h>
+#include <sys/time.h>
+#include <time.h>
+
+#if defined(__aarch64__)
+#define CLOCK_GETTIME "__kernel_clock_gettime"
+#else
+#define CLOCK_GETTIME "__vdso_clock_gettime"
+#endif
+
+typedef int (*clock_gettime_fn)(clockid_t, struct timespec *);
+
+/* Look up the symbol `name` in the vDSO mapped at `base`. */
+static void *vdso_sym(uintptr_t base, const char *name)
+{
+    Elf64_Ehdr *ehdr = (Elf64_Ehdr *)base;
+    Elf64_Phdr *phdr = (Elf64_Phdr *)(base + ehdr->e_phoff);
+    uintptr_t bias = 0;
+    Elf64_Dyn *dyn = NULL;
+    for (int i = 0; i < ehdr->e_phnum; i++) {
+        if (phdr[i].p_type == PT_LOAD)
+            bias = base + phdr[i].p_offset - phdr[i].p_vaddr;
+        else if (phdr[i].p_type == PT_DYNAMIC)
+            dyn = (Elf64_Dyn *)(base + phdr[i].p_offset);
+    }
+    if (!dyn)
+        return NULL;
+    Elf64_Sym *symtab = NULL;
+    const char *strtab = NULL;
+    uint32_t *hash = NULL;
+    for (; dyn->d_tag != DT_NULL; dyn++) {
+        if (dyn->d_tag == DT_SYMTAB)
+            symtab = (Elf64_Sym *)(bias + dyn->d_un.d_ptr);
+        else if (dyn->d_tag == DT_STRTAB)
+            strtab = (const char *)(bias + dyn->d_un.d_ptr);
+        else if (dyn->d_tag == DT_HASH)
+            hash = (uint32_t *)(bias + dyn->d_un.d_ptr);
+    }
+    if (!symtab || !strtab || !hash)
+        return NULL;
+    /* The number of symbols is the number of chain entries. */
+    for (uint32_t i = 0; i < hash[1]; i++) {
+        if (symtab[i].st_shndx != SHN_UNDEF && strcmp(strtab + symtab[i].st_name, name) == 0)
+            return (void *)(bias + symtab[i].st_value);
+    }
+    return NULL;
+}
+
+static long long ns(struct timespec ts)
+{
+    return ts.tv_sec * 1000000000LL + ts.tv_nsec;
+}
+
+int main()
+{
+    uintptr_t base = getauxval(AT_SYSINFO_EHDR);
+    if (!base || memcmp((void *)base, ELFMAG, SELFMAG) != 0) {
+        printf("vDSO: no vDSO is mapped\n");
+        return 1;
+    }
+    clock_gettime_fn vdso_clock_gettime = (clock_gettime_fn)vdso_sym(base, CLOCK_GETTIME);
+    if (!vdso_clock_gettime) {
+        printf("vDSO: %s is missing\n", CLOCK_GETTIME);
+        return 1;
+    }
+
+    /* The vDSO agrees with the system call, and its clock advances. */
+    struct timespec vdso_ts, sys_ts, later;
+    if (vdso_clock_gettime(CLOCK_MONOTONIC, &vdso_ts) != 0 ||
+        clock_gettime(CLOCK_MONOTONIC, &sys_ts) != 0) {
+        printf("vDSO: clock_gettime failed\n");
+        return 1;
+    }
+    if (ns(sys_ts) < ns(vdso_ts) || ns(sys_ts) - ns(vdso_ts) > 1000000000LL) {
+        printf("vDSO: the monotonic clock disagrees with the kernel\n");
+        return 1;
+    }
+    struct timespec sleep = {0, 10000000};
+    nanosleep(&sleep, NULL);
+    if (vdso_clock_gettime(CLOCK_MONOTONIC, &later) != 0 || ns(later) - ns(vdso_ts) < 10000000) {
+        printf("vDSO: the monotonic clock did not advance over a sleep\n");
+        return 1;
+    }
+
+    struct timeval tv;
+    struct timespec realtime;
+    if (gettimeofday(&tv, NULL) != 0 || vdso_clock_gettime(CLOCK_REALTIME, &realtime) != 0 ||
+        realtime.tv_sec - tv.tv_sec > 1 || tv.tv_sec - realtime.tv_sec > 1) {
+        printf("vDSO: gettimeofday and CLOCK_REALTIME disagree\n");
+        return 1;
+    }
+    printf("vDSO test passed!\n");
+    return 0;
+}
@@ -0,0 +1,67 @@
+#include <fcntl.h>
+#include <stdio.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+#define PAGES 1024
+#define PAGE_SIZE 4096
+/* Pages the test itself may fault in besides the mapping. */
+#define SLACK_PAGES 64
+
+/* Get the resident set size from /proc/self/statm, in pages. */
+static int resident_pages(long *resident)
+{
+    char buf[128];
+    long size;
+    int fd = open("/proc/self/statm", O_RDONLY);
+    if (fd < 0)
+        return -1;
+    ssize_t len = read(fd, buf, sizeof(buf) - 1);
+    close(fd);
+    if (len <= 0)
+        return -1;
+    buf[len] = 0;
+    return sscanf(buf, "%ld %ld", &size, resident) == 2 ? 0 : -1;
+}
+
+int main()
+{
+    volatile char *mem =
+        mmap(NULL, PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    long before, after_read, after_write;
+    if (mem == MAP_FAILED || resident_pages(&before) != 0) {
+        printf("zero page: mmap failed\n");
+        return 1;
+    }
+
+    /* Reads map the shared zero page, which is not resident. */
+    for (int i = 0; i < PAGES; i++) {
+        if (mem[i * PAGE_SIZE] != 0) {
+            printf("zero page: a new page is not zero-filled\n");
+            return 1;
+        }
+    }
+    if (resident_pages(&after_read) != 0 || after_read - before >= SLACK_PAGES) {
+        printf("zero page: reading %d pages made %ld resident\n", PAGES, after_read - before);
+        return 1;
+    }
+
+    /* A write gives the page its own frame, and the other pages still read
+     * zero. */
+    for (int i = 0; i < PAGES; i += 256)
+        mem[i * PAGE_SIZE + 1] = 1;
+    for (int i = 0; i < PAGES; i++) {
+        char expected = i % 256 == 0;
+        if (mem[i * PAGE_SIZE] != 0 || mem[i * PAGE_SIZE + 1] != expected) {
+            printf("zero page: page %d reads the wrong data after the writes\n", i);
+            return 1;
+        }
+    }
+    if (resident_pages(&after_write) != 0 || after_write < after_read + PAGES / 256) {
+        printf("zero page: written pages are not resident\n");
+        return 1;
+    }
+    munmap((void *)mem, PAGES * PAGE_SIZE);
+    printf("Zero page test passed!\n");
+    return 0;
+}
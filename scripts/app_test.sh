@@ -111,6 +111,10 @@ test_list=(
     "nimbos"
     "libc"
 )
+# 32-bit x86 programs run on x86_64 with the `ia32` feature
+if [ "$ARCH" == "x86_64" ]; then
+    test_list+=("ia32")
+fi
 
 for t in ${test_list[@]}; do
     APP=$t
@@ -0,0 +1,250 @@
+//! Compatibility mode for 32-bit x86 programs on x86_64.
+//!
+//! 32-bit programs run in the 32-bit user code segment of the GDT and make
+//! system calls with `int 0x80`, which are translated to the 64-bit ones in
+//! the syscall layer. Their address space ends at
+//! [`crate::mm::COMPAT_TASK_SIZE`].
+//!
+//! The C libraries point `%gs` at the thread-local storage through a
+//! segment descriptor set up by `set_thread_area`. Each task keeps its
+//! descriptors, and they are installed in the TLS entries of the GDT of the
+//! current CPU before it returns to user space, as Linux does on a context
+//! switch.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::arch::{GdtStruct, TrapFrame, UspaceContext};
+use memory_addr::VirtAddr;
+use x86::dtables::{self, DescriptorTablePointer};
+use x86::msr::{wrmsr, IA32_KERNEL_GSBASE};
+
+use crate::mm::uaccess;
+
+/// The first GDT entry for thread-local storage, `GDT_ENTRY_TLS_MIN` in
+/// Linux. The entries from there on are not used by the kernel.
+const TLS_ENTRY_MIN: usize = 12;
+/// The number of GDT entries for thread-local storage.
+const TLS_ENTRIES: usize = 3;
+
+/// The `RFLAGS` a program starts with: interrupts enabled, and the bit that
+/// is always set.
+const USER_RFLAGS: u64 = 0x202;
+
+/// The segment is 32-bit.
+const SEG_32BIT: u32 = 1 << 0;
+/// The segment is read-only, or execute-only for code.
+const READ_EXEC_ONLY: u32 = 1 << 3;
+/// The limit is counted in pages.
+const LIMIT_IN_PAGES: u32 = 1 << 4;
+/// The segment is not present.
+const SEG_NOT_PRESENT: u32 = 1 << 5;
+/// The bit of the descriptor available to software.
+const USEABLE: u32 = 1 << 6;
+
+/// A segment descriptor as passed to `set_thread_area`, laid out as
+/// `struct user_desc`.
+///
+/// See <https://man7.org/linux/man-pages/man2/set_thread_area.2.html>
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct UserDesc {
+    /// The GDT entry, or `-1` to take a free one.
+    pub entry_number: u32,
+    /// The base address of the segment.
+    pub base_addr: u32,
+    /// The limit of the segment.
+    pub limit: u32,
+    /// The bit fields of `struct user_desc`: `seg_32bit`, `contents` (2
+    /// bits), `read_exec_only`, `limit_in_pages`, `seg_not_present` and
+    /// `useable`.
+    pub flags: u32,
+}
+
+impl UserDesc {
+    /// The type of the segment: 0 for data, 1 for stack, 2 and 3 for code.
+    fn contents(&self) -> u32 {
+        (self.flags >> 1) & 0x3
+    }
+
+    /// Whether the descriptor clears the entry, as `LDT_empty` and `LDT_zero`
+    /// in Linux.
+    fn is_empty(&self) -> bool {
+        let clear_flags = READ_EXEC_ONLY | SEG_NOT_PRESENT;
+        self.base_addr == 0
+            && self.limit == 0
+            && (self.flags & 0xff == clear_flags || self.flags == 0)
+    }
+
+    /// Encode the descriptor for the GDT. Only 32-bit data segments may be
+    /// set.
+    fn encode(&self) -> LinuxResult<u64> {
+        if self.is_empty() {
+            return Ok(0);
+        }
+        if self.flags & SEG_32BIT == 0 || self.contents() > 1 {
+            return Err(LinuxError::EINVAL);
+        }
+        let flag = |bit: u32| (self.flags & bit != 0) as u64;
+        let base = self.base_addr as u64;
+        let limit = self.limit as u64;
+        // Writable unless read-only, and accessed.
+        let ty = ((flag(READ_EXEC_ONLY) ^ 1) << 1) | ((self.contents() as u64) << 2) | 1;
+        Ok((limit & 0xffff)
+            | ((base & 0xff_ffff) << 16)
+            | (ty << 40)
+            | (1 << 44) // not a system segment
+            | (3 << 45) // DPL 3
+            | ((flag(SEG_NOT_PRESENT) ^ 1) << 47)
+            | (((limit >> 16) & 0xf) << 48)
+            | (flag(USEABLE) << 52)
+            | (1 << 54) // 32-bit
+            | (flag(LIMIT_IN_PAGES) << 55)
+            | ((base >> 24) << 56))
+    }
+}
+
+/// The base address of the segment of the GDT entry `desc`.
+fn descriptor_base(desc: u64) -> u64 {
+    ((desc >> 16) & 0xff_ffff) | ((desc >> 56) << 24)
+}
+
+/// The TLS entries of the GDT of the current CPU, or `None` if the GDT is
+/// too small to have them.
+fn gdt_tls_entries() -> Option<&'static mut [u64]> {
+    let mut gdtr = DescriptorTablePointer::<u64>::default();
+    unsafe { dtables::sgdt(&mut gdtr) };
+    let len = (gdtr.limit as usize + 1) / size_of::<u64>();
+    if len < TLS_ENTRY_MIN + TLS_ENTRIES {
+        return None;
+    }
+    Some(unsafe {
+        core::slice::from_raw_parts_mut((gdtr.base as *mut u64).add(TLS_ENTRY_MIN), TLS_ENTRIES)
+    })
+}
+
+/// Whether the system call or exception of `tf` comes from a 32-bit program.
+pub fn is_compat_frame(tf: &TrapFrame) -> bool {
+    tf.cs == GdtStruct::UCODE32_SELECTOR.0 as u64
+}
+
+/// The context that starts a 32-bit program at `entry` with the stack
+/// pointer `sp`.
+pub fn uspace_context(entry: VirtAddr, sp: VirtAddr) -> UspaceContext {
+    let tf = TrapFrame {
+        rip: entry.as_usize() as _,
+        rsp: sp.as_usize() as _,
+        cs: GdtStruct::UCODE32_SELECTOR.0 as _,
+        ss: GdtStruct::UDATA_SELECTOR.0 as _,
+        rflags: USER_RFLAGS,
+        ..Default::default()
+    };
+    UspaceContext::from(&tf)
+}
+
+/// The compatibility mode state of a task.
+pub struct CompatState {
+    /// Whether the task runs a 32-bit program.
+    compat: AtomicBool,
+    /// The TLS entries set by `set_thread_area`, zero if unused.
+    tls: [AtomicU64; TLS_ENTRIES],
+}
+
+impl CompatState {
+    /// The state of a 64-bit program.
+    pub const fn new() -> Self {
+        Self {
+            compat: AtomicBool::new(false),
+            tls: [const { AtomicU64::new(0) }; TLS_ENTRIES],
+        }
+    }
+
+    /// Whether the task runs a 32-bit program.
+    pub fn is_compat(&self) -> bool {
+        self.compat.load(Ordering::Relaxed)
+    }
+
+    /// Start a new program, which is 32-bit if `compat` is true. It has no
+    /// TLS entries yet.
+    pub fn reset(&self, compat: bool) {
+        self.compat.store(compat, Ordering::Relaxed);
+        for entry in &self.tls {
+            entry.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Copy the state of the parent of a new task.
+    pub fn inherit(&self, parent: &Self) {
+        self.compat.store(parent.is_compat(), Ordering::Relaxed);
+        for (entry, parent) in self.tls.iter().zip(&parent.tls) {
+            entry.store(parent.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    /// Set the TLS entry of `info`, or the first free one if `allocate` is
+    /// true and its number is `-1`. Returns the entry number.
+    fn set_entry(&self, info: &UserDesc, allocate: bool) -> LinuxResult<u32> {
+        if gdt_tls_entries().is_none() {
+            return Err(LinuxError::ENOSYS);
+        }
+        let index = if info.entry_number == u32::MAX && allocate {
+            self.tls
+                .iter()
+                .position(|entry| entry.load(Ordering::Relaxed) == 0)
+                .ok_or(LinuxError::ESRCH)?
+        } else {
+            (info.entry_number as usize)
+                .checked_sub(TLS_ENTRY_MIN)
+                .filter(|&index| index < TLS_ENTRIES)
+                .ok_or(LinuxError::EINVAL)?
+        };
+        self.tls[index].store(info.encode()?, Ordering::Relaxed);
+        Ok((TLS_ENTRY_MIN + index) as u32)
+    }
+
+    /// Set a TLS entry of the current task from `u_info`, as
+    /// `set_thread_area`. The number of the entry taken is written back if
+    /// it was `-1`.
+    pub fn set_thread_area(&self, u_info: *mut UserDesc) -> LinuxResult {
+        let mut info = uaccess::read_user(u_info)?;
+        let entry_number = self.set_entry(&info, true)?;
+        if info.entry_number != entry_number {
+            info.entry_number = entry_number;
+            uaccess::write_user(u_info, info)?;
+        }
+        self.load();
+        Ok(())
+    }
+
+    /// Set the TLS entry of a new task from `u_info`, as `clone` does with
+    /// `CLONE_SETTLS`. The entry must be given.
+    pub fn set_tls(&self, u_info: *const UserDesc) -> LinuxResult {
+        self.set_entry(&uaccess::read_user(u_info)?, false)?;
+        Ok(())
+    }
+
+    /// Install the TLS entries in the GDT of the current CPU before
+    /// returning to user space. The task must be the current one.
+    ///
+    /// `%gs` keeps the base it was loaded with, which is in
+    /// `IA32_KERNEL_GSBASE` while in the kernel. It is restored from the
+    /// first TLS entry, the one the C libraries use.
+    pub fn load(&self) {
+        let Some(entries) = gdt_tls_entries() else {
+            return;
+        };
+        for (entry, desc) in entries.iter_mut().zip(&self.tls) {
+            *entry = desc.load(Ordering::Relaxed);
+        }
+        if !self.is_compat() {
+            return;
+        }
+        let gs_base = self
+            .tls
+            .iter()
+            .map(|desc| desc.load(Ordering::Relaxed))
+            .find(|&desc| desc != 0)
+            .map_or(0, descriptor_base);
+        unsafe { wrmsr(IA32_KERNEL_GSBASE, gs_base) };
+    }
+}
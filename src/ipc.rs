@@ -0,0 +1,179 @@
+//! System V shared memory segments.
+//!
+//! A segment is an anonymous [`CachedFile`] shared by every process that
+//! attaches it, so its pages are mapped the same way as a `MAP_SHARED` file
+//! mapping. Attachments are tracked in [`VmAttrs`](crate::mm::VmAttrs) and
+//! are duplicated on fork like the mappings they describe.
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axsync::Mutex;
+use memory_addr::VirtAddr;
+
+use crate::mm::page_cache::{CachedFile, Seals};
+
+/// The key that always creates a new segment.
+pub const IPC_PRIVATE: i32 = 0;
+
+/// The permissions and bookkeeping of a segment that `shmctl` can read or
+/// change.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ShmInfo {
+    /// Owner's user ID.
+    pub uid: u32,
+    /// Owner's group ID.
+    pub gid: u32,
+    /// Creator's user ID.
+    pub cuid: u32,
+    /// Creator's group ID.
+    pub cgid: u32,
+    /// The lower 9 bits of `shmflg` given to `shmget`.
+    pub mode: u32,
+    /// Last attach time, in seconds.
+    pub atime: i64,
+    /// Last detach time, in seconds.
+    pub dtime: i64,
+    /// Last change time, in seconds.
+    pub ctime: i64,
+    /// ID of the creating process.
+    pub cpid: i32,
+    /// ID of the last process that attached or detached the segment.
+    pub lpid: i32,
+}
+
+/// A shared memory segment.
+pub struct ShmSegment {
+    /// The identifier returned by `shmget`.
+    pub id: i32,
+    /// The key the segment was created with.
+    pub key: i32,
+    /// The size requested by `shmget`, in bytes.
+    pub size: usize,
+    /// The memory of the segment.
+    pub file: Arc<CachedFile>,
+    /// The number of mappings attaching the segment.
+    nattch: AtomicUsize,
+    info: Mutex<ShmInfo>,
+}
+
+impl ShmSegment {
+    /// The number of mappings attaching the segment.
+    pub fn nattch(&self) -> usize {
+        self.nattch.load(Ordering::Relaxed)
+    }
+
+    /// The permissions and bookkeeping of the segment.
+    pub fn info(&self) -> ShmInfo {
+        *self.info.lock()
+    }
+
+    /// Change the owner and the permissions as done by `IPC_SET`.
+    pub fn set_owner(&self, uid: u32, gid: u32, mode: u32) {
+        let mut info = self.info.lock();
+        info.uid = uid;
+        info.gid = gid;
+        info.mode = (info.mode & !0o777) | (mode & 0o777);
+        info.ctime = now();
+    }
+
+    /// Record that process `pid` attached the segment.
+    pub fn record_attach(&self, pid: i32) {
+        let mut info = self.info.lock();
+        info.atime = now();
+        info.lpid = pid;
+    }
+
+    /// Record that process `pid` detached the segment.
+    pub fn record_detach(&self, pid: i32) {
+        let mut info = self.info.lock();
+        info.dtime = now();
+        info.lpid = pid;
+    }
+}
+
+/// The current wall clock time in seconds.
+fn now() -> i64 {
+    axhal::time::wall_time().as_secs() as i64
+}
+
+/// The segments that have not been removed, by ID.
+static SEGMENTS: Mutex<BTreeMap<i32, Arc<ShmSegment>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Find the segment created with `key`.
+pub fn find_segment(key: i32) -> Option<Arc<ShmSegment>> {
+    if key == IPC_PRIVATE {
+        return None;
+    }
+    SEGMENTS
+        .lock()
+        .values()
+        .find(|segment| segment.key == key)
+        .cloned()
+}
+
+/// Get the segment identified by `id`.
+pub fn get_segment(id: i32) -> Option<Arc<ShmSegment>> {
+    SEGMENTS.lock().get(&id).cloned()
+}
+
+/// Create a zero-filled segment of `size` bytes.
+pub fn create_segment(key: i32, size: usize, mode: u32, pid: i32) -> Arc<ShmSegment> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) as i32;
+    let segment = Arc::new(ShmSegment {
+        id,
+        key,
+        size,
+        file: Arc::new(CachedFile::new_anonymous(size, Seals::SEAL)),
+        nattch: AtomicUsize::new(0),
+        info: Mutex::new(ShmInfo {
+            mode: mode & 0o777,
+            ctime: now(),
+            cpid: pid,
+            ..Default::default()
+        }),
+    });
+    SEGMENTS.lock().insert(id, segment.clone());
+    segment
+}
+
+/// Remove the segment identified by `id`, so it can no longer be found by
+/// `shmget` or attached. Its memory is freed once the last attachment is
+/// gone.
+pub fn remove_segment(id: i32) -> Option<Arc<ShmSegment>> {
+    SEGMENTS.lock().remove(&id)
+}
+
+/// A mapping of a segment into the user address space.
+///
+/// Each live value counts as one attachment of the segment.
+pub struct ShmAttachment {
+    /// The attached segment.
+    pub segment: Arc<ShmSegment>,
+    /// The address the segment was attached at by `shmat`.
+    pub start: VirtAddr,
+}
+
+impl ShmAttachment {
+    pub fn new(segment: Arc<ShmSegment>, start: VirtAddr) -> Self {
+        segment.nattch.fetch_add(1, Ordering::Relaxed);
+        Self { segment, start }
+    }
+}
+
+impl Clone for ShmAttachment {
+    fn clone(&self) -> Self {
+        self.segment.nattch.fetch_add(1, Ordering::Relaxed);
+        Self {
+            segment: self.segment.clone(),
+            start: self.start,
+        }
+    }
+}
+
+impl Drop for ShmAttachment {
+    fn drop(&mut self) {
+        self.segment.nattch.fetch_sub(1, Ordering::Relaxed);
+    }
+}
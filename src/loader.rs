@@ -3,11 +3,25 @@
 //! It will read and parse ELF files.
 //!
 //! Now these apps are loaded into memory as a part of the kernel image.
-use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use alloc::{collections::btree_map::BTreeMap, string::String, vec, vec::Vec};
 use core::arch::global_asm;
 
+use axerrno::{LinuxError, LinuxResult};
 use axhal::paging::MappingFlags;
-use memory_addr::{MemoryAddr, VirtAddr};
+use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use crate::config;
+use crate::mm::{self, page_cache::CachedFile};
+
+mod check;
+
+use check::{Malformed, EHDR_SIZE, PATH_MAX};
+
+impl From<Malformed> for LinuxError {
+    fn from(_: Malformed) -> Self {
+        LinuxError::ENOEXEC
+    }
+}
 
 global_asm!(include_str!(concat!(env!("OUT_DIR"), "/link_app.S")));
 
@@ -55,70 +69,170 @@ pub(crate) fn get_app_data_by_name(name: &str) -> Option<&'static [u8]> {
         .map(get_app_data)
 }
 
-/// List all apps.
-pub(crate) fn list_apps() {
-    info!("/**** APPS ****");
-    let app_count = get_app_count();
-    for i in 0..app_count {
-        info!("{}", get_app_name(i));
-    }
-    info!("**************/");
-}
-
 /// The segment of the elf file, which is used to map the elf file to the memory space
-pub struct ELFSegment<'a> {
+pub struct ELFSegment {
     /// The start virtual address of the segment
     pub start_vaddr: VirtAddr,
     /// The size of the segment
     pub size: usize,
     /// The flags of the segment which is used to set the page table entry
     pub flags: MappingFlags,
-    /// The data of the segment
-    pub data: &'a [u8],
     /// The offset of the segment relative to the start of the page
     pub offset: usize,
+    /// The offset of the data of the segment in the file
+    pub file_offset: usize,
+    /// The size of the data of the segment in the file. The rest of the
+    /// segment is zero-filled.
+    pub file_size: usize,
 }
 
+/// The auxiliary vector entry with the address of the program headers.
+pub const AT_PHDR: u8 = 3;
+/// The auxiliary vector entry with the size of a program header.
+pub const AT_PHENT: u8 = 4;
+/// The auxiliary vector entry with the number of program headers.
+pub const AT_PHNUM: u8 = 5;
+/// The auxiliary vector entry with the base address of the interpreter.
+pub const AT_BASE: u8 = 7;
+/// The auxiliary vector entry with the entry point of the program.
+pub const AT_ENTRY: u8 = 9;
+/// The auxiliary vector entry that ends the vector.
+const AT_NULL: u8 = 0;
+/// The auxiliary vector entry with the page size.
+const AT_PAGESZ: u8 = 6;
+/// The auxiliary vector entry with the flags of the interpreter, always zero.
+const AT_FLAGS: u8 = 8;
+/// The auxiliary vector entry with the real user ID.
+const AT_UID: u8 = 11;
+/// The auxiliary vector entry with the effective user ID.
+const AT_EUID: u8 = 12;
+/// The auxiliary vector entry with the real group ID.
+const AT_GID: u8 = 13;
+/// The auxiliary vector entry with the effective group ID.
+const AT_EGID: u8 = 14;
+/// The auxiliary vector entry with the address of the platform string.
+const AT_PLATFORM: u8 = 15;
+/// The auxiliary vector entry with the hardware capabilities.
+const AT_HWCAP: u8 = 16;
+/// The auxiliary vector entry with the frequency of `times()`.
+const AT_CLKTCK: u8 = 17;
+/// The auxiliary vector entry telling whether the program runs with raised
+/// privileges.
+const AT_SECURE: u8 = 23;
+/// The auxiliary vector entry with the address of 16 random bytes.
+const AT_RANDOM: u8 = 25;
+/// The auxiliary vector entry with more hardware capabilities.
+const AT_HWCAP2: u8 = 26;
+/// The auxiliary vector entry with the address of the path of the program.
+const AT_EXECFN: u8 = 31;
+/// The auxiliary vector entry with the minimal size of a signal stack.
+const AT_MINSIGSTKSZ: u8 = 51;
+
+/// The frequency of the clock ticks reported by `times()`.
+pub(crate) const USER_HZ: usize = 100;
+/// The platform string given by `AT_PLATFORM`.
+const PLATFORM: Option<&str> = if cfg!(target_arch = "x86_64") {
+    Some("x86_64")
+} else if cfg!(target_arch = "aarch64") {
+    Some("aarch64")
+} else {
+    None
+};
+/// The platform string given by `AT_PLATFORM` to 32-bit x86 programs.
+const COMPAT_PLATFORM: &str = "i686";
+/// The minimal size of a signal stack, `MINSIGSTKSZ` in the uapi headers.
+const MINSIGSTKSZ: usize = if cfg!(target_arch = "aarch64") {
+    5120
+} else {
+    2048
+};
+
 /// The information of a given ELF file
-pub struct ELFInfo<'a> {
+pub struct ELFInfo {
     /// The entry point of the ELF file
     pub entry: VirtAddr,
+    /// The offset the ELF file is loaded at, zero unless it is position
+    /// independent
+    pub base: VirtAddr,
+    /// The path of the program interpreter (the dynamic linker) requested by
+    /// `PT_INTERP`, if any
+    pub interp: Option<String>,
+    /// The permissions of the stack, executable if requested by
+    /// `PT_GNU_STACK`
+    pub stack_flags: MappingFlags,
     /// The segments of the ELF file
-    pub segments: Vec<ELFSegment<'a>>,
+    pub segments: Vec<ELFSegment>,
     /// The auxiliary vectors of the ELF file
     pub auxv: BTreeMap<u8, usize>,
+    /// Whether it is a 32-bit x86 program, which runs in compatibility mode
+    pub compat: bool,
+}
+
+/// The segment type whose flags give the permissions of the stack.
+const PT_GNU_STACK: u32 = 0x6474_e551;
+/// The maximum size of the program headers, as in Linux.
+const MAX_PHDRS_SIZE: usize = 0x1_0000;
+
+/// Read the headers at the start of `file`: the ELF header and, if they are
+/// within [`MAX_PHDRS_SIZE`], the program headers.
+fn read_headers(file: &CachedFile) -> LinuxResult<Vec<u8>> {
+    let read = |len: usize| -> LinuxResult<Vec<u8>> {
+        let mut data = vec![0; len.min(file.size())];
+        let len = file.read_at(0, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    };
+    let data = read(PAGE_SIZE_4K)?;
+    if data.len() < EHDR_SIZE {
+        return Err(LinuxError::ENOEXEC);
+    }
+    let ph_end = check::headers_end(&data)?;
+    if ph_end > data.len() && ph_end <= MAX_PHDRS_SIZE {
+        return read(ph_end);
+    }
+    Ok(data)
+}
+
+/// Check the headers of `elf_data`, the start of a file of `file_size`
+/// bytes, and get the parsed file.
+///
+/// The file must be a 64-bit little-endian executable or shared object for
+/// the current architecture, see [`check`] for the rest. With the `ia32`
+/// feature, 32-bit x86 files are accepted as well.
+fn parse_elf(elf_data: &[u8], file_size: usize) -> LinuxResult<xmas_elf::ElfFile<'_>> {
+    let machine = if cfg!(target_arch = "x86_64") {
+        check::EM_X86_64
+    } else if cfg!(target_arch = "aarch64") {
+        check::EM_AARCH64
+    } else {
+        check::EM_RISCV
+    };
+    let compat_machine = cfg!(feature = "ia32").then_some(check::EM_386);
+    let header = check::check_header(elf_data, machine, compat_machine)?;
+    check::check_segments(elf_data, &header, file_size as u64)?;
+    xmas_elf::ElfFile::new(elf_data).map_err(|_| LinuxError::ENOEXEC)
 }
 
 /// Load the ELF files by the given app name and return
 /// the segments of the ELF file
 ///
+/// Only the headers are read, the segments are loaded from `file` when
+/// they are mapped. The file is validated first: a file that is not an ELF file for this
+/// architecture or whose headers are malformed fails with `ENOEXEC`, and
+/// segments that do not fit in the user address space fail with `EINVAL`.
+///
 /// # Arguments
-/// * `name` - The name of the app
+/// * `file` - The ELF file
 /// * `base_addr` - The minimal address of user space
 ///
 /// # Returns
 /// Entry and information about segments of the given ELF file
-pub(crate) fn load_elf(elf_data: &[u8], base_addr: VirtAddr) -> ELFInfo<'_> {
-    use xmas_elf::program::{Flags, SegmentData};
-    use xmas_elf::{header, ElfFile};
-
-    let elf = ElfFile::new(elf_data).expect("Invalid ELF file format");
-    assert_eq!(elf.header.pt1.magic, *b"\x7fELF", "invalid elf!");
+pub(crate) fn load_elf(file: &CachedFile, base_addr: VirtAddr) -> LinuxResult<ELFInfo> {
+    use xmas_elf::program::{Flags, Type};
 
-    let expect_arch = if cfg!(target_arch = "x86_64") {
-        header::Machine::X86_64
-    } else if cfg!(target_arch = "aarch64") {
-        header::Machine::AArch64
-    } else if cfg!(target_arch = "riscv64") {
-        header::Machine::RISC_V
-    } else {
-        panic!("Unsupported architecture!");
-    };
-    assert_eq!(
-        elf.header.pt2.machine().as_machine(),
-        expect_arch,
-        "invalid ELF arch"
-    );
+    let elf_data = read_headers(file)?;
+    let elf = parse_elf(&elf_data, file.size())?;
+    let compat = elf.header.pt1.class() == xmas_elf::header::Class::ThirtyTwo;
 
     fn into_mapflag(f: Flags) -> MappingFlags {
         let mut ret = MappingFlags::USER;
@@ -134,38 +248,322 @@ pub(crate) fn load_elf(elf_data: &[u8], base_addr: VirtAddr) -> ELFInfo<'_> {
         ret
     }
 
-    let mut segments = Vec::new();
-
-    let elf_offset = kernel_elf_parser::get_elf_base_addr(&elf, base_addr.as_usize()).unwrap();
-    assert!(
-        memory_addr::is_aligned_4k(elf_offset),
-        "ELF base address must be aligned to 4k"
-    );
-
-    elf.program_iter()
-        .filter(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Load))
-        .for_each(|ph| {
-            // align the segment to 4k
-            let st_vaddr = VirtAddr::from(ph.virtual_addr() as usize) + elf_offset;
-            let st_vaddr_align: VirtAddr = st_vaddr.align_down_4k();
-            let ed_vaddr_align = VirtAddr::from((ph.virtual_addr() + ph.mem_size()) as usize)
-                .align_up_4k()
-                + elf_offset;
-            let data = match ph.get_data(&elf).unwrap() {
-                SegmentData::Undefined(data) => data,
-                _ => panic!("failed to get ELF segment data"),
-            };
-            segments.push(ELFSegment {
-                start_vaddr: st_vaddr_align,
-                size: ed_vaddr_align.as_usize() - st_vaddr_align.as_usize(),
-                flags: into_mapflag(ph.flags()),
-                data,
-                offset: st_vaddr.align_offset_4k(),
-            });
+    let elf_offset = kernel_elf_parser::get_elf_base_addr(&elf, base_addr.as_usize())
+        .map_err(|_| LinuxError::EINVAL)?;
+    if !memory_addr::is_aligned_4k(elf_offset) {
+        return Err(LinuxError::EINVAL);
+    }
+    let user_space = if compat {
+        VirtAddrRange::new(
+            VirtAddr::from_usize(config::USER_SPACE_BASE),
+            VirtAddr::from_usize(mm::COMPAT_TASK_SIZE),
+        )
+    } else {
+        VirtAddrRange::from_start_size(
+            VirtAddr::from_usize(config::USER_SPACE_BASE),
+            config::USER_SPACE_SIZE,
+        )
+    };
+
+    let mut segments: Vec<ELFSegment> = Vec::new();
+    let mut interp = None;
+    let mut stack_flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+    for ph in elf.program_iter() {
+        match ph.get_type() {
+            Ok(Type::Load) => {}
+            Ok(Type::Interp) => {
+                let mut path = vec![0; (ph.file_size() as usize).min(PATH_MAX)];
+                let len = file.read_at(ph.offset() as usize, &mut path)?;
+                interp = Some(String::from(check::interp_path(&path[..len])?));
+                continue;
+            }
+            Ok(Type::OsSpecific(PT_GNU_STACK)) => {
+                if ph.flags().is_execute() {
+                    stack_flags |= MappingFlags::EXECUTE;
+                }
+                continue;
+            }
+            _ => continue,
+        }
+        // align the segment to 4k
+        let st_vaddr = (ph.virtual_addr() as usize)
+            .checked_add(elf_offset)
+            .map(VirtAddr::from)
+            .ok_or(LinuxError::EINVAL)?;
+        let ed_vaddr = st_vaddr
+            .as_usize()
+            .checked_add(ph.mem_size() as usize)
+            .and_then(|end| end.checked_add(PAGE_SIZE_4K - 1))
+            .map(|end| VirtAddr::from(end).align_down_4k())
+            .ok_or(LinuxError::EINVAL)?;
+        let range = VirtAddrRange::new(st_vaddr.align_down_4k(), ed_vaddr);
+        if !user_space.contains_range(range) {
+            return Err(LinuxError::EINVAL);
+        }
+        segments.push(ELFSegment {
+            start_vaddr: range.start,
+            size: range.size(),
+            flags: into_mapflag(ph.flags()),
+            offset: st_vaddr.align_offset_4k(),
+            file_offset: ph.offset() as usize,
+            file_size: ph.file_size() as usize,
         });
-    ELFInfo {
-        entry: VirtAddr::from(elf.header.pt2.entry_point() as usize + elf_offset),
+    }
+    let entry = (elf.header.pt2.entry_point() as usize)
+        .checked_add(elf_offset)
+        .filter(|&entry| user_space.contains(VirtAddr::from(entry)))
+        .ok_or(LinuxError::EINVAL)?;
+    let mut auxv = BTreeMap::new();
+    // The program headers are found through `PT_PHDR`, or else in the
+    // segment that loads them from the file.
+    let ph_offset = elf.header.pt2.ph_offset() as usize;
+    let phdr = elf
+        .program_iter()
+        .find(|ph| ph.get_type() == Ok(Type::Phdr))
+        .map(|ph| ph.virtual_addr() as usize)
+        .or_else(|| {
+            elf.program_iter()
+                .filter(|ph| ph.get_type() == Ok(Type::Load))
+                .find(|ph| {
+                    let offset = ph.offset() as usize;
+                    (offset..offset + ph.file_size() as usize).contains(&ph_offset)
+                })
+                .map(|ph| ph.virtual_addr() as usize + ph_offset - ph.offset() as usize)
+        });
+    if let Some(phdr) = phdr {
+        auxv.insert(AT_PHDR, phdr.wrapping_add(elf_offset));
+    }
+    auxv.insert(AT_PHENT, elf.header.pt2.ph_entry_size() as usize);
+    auxv.insert(AT_PHNUM, elf.header.pt2.ph_count() as usize);
+    auxv.insert(AT_ENTRY, entry);
+    auxv.insert(AT_BASE, 0);
+    auxv.insert(AT_FLAGS, 0);
+
+    Ok(ELFInfo {
+        entry: VirtAddr::from(entry),
+        base: VirtAddr::from(elf_offset),
+        interp,
+        stack_flags,
         segments,
-        auxv: kernel_elf_parser::get_auxv_vector(&elf, elf_offset),
+        auxv,
+        compat,
+    })
+}
+
+/// The hardware capabilities given by `AT_HWCAP` and `AT_HWCAP2`.
+///
+/// On x86_64 they are the feature bits of CPUID leaf 1 in EDX. The bits of
+/// `AT_HWCAP2` are for ring 3 `MWAIT` and `FSGSBASE`, which are not enabled.
+#[cfg(target_arch = "x86_64")]
+fn hwcaps() -> (usize, Option<usize>) {
+    let edx = unsafe { core::arch::x86_64::__cpuid(1) }.edx;
+    (edx as usize, Some(0))
+}
+
+/// The hardware capabilities given by `AT_HWCAP` and `AT_HWCAP2`.
+///
+/// On aarch64 they are read from the ID registers of the CPU.
+#[cfg(target_arch = "aarch64")]
+fn hwcaps() -> (usize, Option<usize>) {
+    const HWCAP_FP: usize = 1 << 0;
+    const HWCAP_ASIMD: usize = 1 << 1;
+    const HWCAP_AES: usize = 1 << 3;
+    const HWCAP_PMULL: usize = 1 << 4;
+    const HWCAP_SHA1: usize = 1 << 5;
+    const HWCAP_SHA2: usize = 1 << 6;
+    const HWCAP_CRC32: usize = 1 << 7;
+    const HWCAP_ATOMICS: usize = 1 << 8;
+    const HWCAP_FPHP: usize = 1 << 9;
+    const HWCAP_ASIMDHP: usize = 1 << 10;
+
+    let (pfr0, isar0): (u64, u64);
+    unsafe {
+        core::arch::asm!("mrs {}, id_aa64pfr0_el1", out(reg) pfr0);
+        core::arch::asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0);
+    }
+    let field = |reg: u64, shift: u32| (reg >> shift) & 0xf;
+
+    let mut hwcap = 0;
+    // 0 means implemented, 1 implemented with half precision, 0xf absent.
+    match field(pfr0, 16) {
+        0 => hwcap |= HWCAP_FP,
+        1 => hwcap |= HWCAP_FP | HWCAP_FPHP,
+        _ => {}
+    }
+    match field(pfr0, 20) {
+        0 => hwcap |= HWCAP_ASIMD,
+        1 => hwcap |= HWCAP_ASIMD | HWCAP_ASIMDHP,
+        _ => {}
+    }
+    match field(isar0, 4) {
+        0 => {}
+        1 => hwcap |= HWCAP_AES,
+        _ => hwcap |= HWCAP_AES | HWCAP_PMULL,
+    }
+    if field(isar0, 8) >= 1 {
+        hwcap |= HWCAP_SHA1;
     }
+    if field(isar0, 12) >= 1 {
+        hwcap |= HWCAP_SHA2;
+    }
+    if field(isar0, 16) >= 1 {
+        hwcap |= HWCAP_CRC32;
+    }
+    if field(isar0, 20) >= 2 {
+        hwcap |= HWCAP_ATOMICS;
+    }
+    (hwcap, Some(0))
+}
+
+/// The hardware capabilities given by `AT_HWCAP`. There is no `AT_HWCAP2`.
+///
+/// On riscv64 there is a bit for each single-letter extension. Supervisor
+/// mode cannot read `misa`, so these are the extensions the kernel is built
+/// for, which the platform must have.
+#[cfg(target_arch = "riscv64")]
+fn hwcaps() -> (usize, Option<usize>) {
+    let extensions = [
+        (b'i', true),
+        (b'm', cfg!(target_feature = "m")),
+        (b'a', cfg!(target_feature = "a")),
+        (b'f', cfg!(target_feature = "f")),
+        (b'd', cfg!(target_feature = "d")),
+        (b'c', cfg!(target_feature = "c")),
+    ];
+    let hwcap = extensions
+        .into_iter()
+        .filter(|&(_, present)| present)
+        .fold(0, |hwcap, (ext, _)| hwcap | (1 << (ext - b'a')));
+    (hwcap, None)
+}
+
+/// The initial stack of a program, built from the top down.
+struct StackBuilder {
+    /// The data pushed so far, in reverse order.
+    reversed: Vec<u8>,
+    top: usize,
+}
+
+impl StackBuilder {
+    fn sp(&self) -> usize {
+        self.top - self.reversed.len()
+    }
+
+    /// Push `data` and return its address.
+    fn push(&mut self, data: &[u8]) -> usize {
+        self.reversed.extend(data.iter().rev());
+        self.sp()
+    }
+
+    /// Push `s` with a terminating NUL and return its address.
+    fn push_str(&mut self, s: &str) -> usize {
+        self.push(&[0]);
+        self.push(s.as_bytes())
+    }
+
+    /// Push `words`, each truncated to 32 bits if `compat` is true.
+    fn push_words(&mut self, words: &[usize], compat: bool) {
+        for &word in words.iter().rev() {
+            if compat {
+                self.push(&(word as u32).to_ne_bytes());
+            } else {
+                self.push(&word.to_ne_bytes());
+            }
+        }
+    }
+
+    fn pad_to(&mut self, align: usize) {
+        let len = self.reversed.len() + self.sp() % align;
+        self.reversed.resize(len, 0);
+    }
+
+    /// The data of the stack, which starts at the returned stack pointer.
+    fn finish(mut self) -> (Vec<u8>, VirtAddr) {
+        let sp = VirtAddr::from(self.sp());
+        self.reversed.reverse();
+        (self.reversed, sp)
+    }
+}
+
+/// Build the initial stack of a program below `stack_top`, as laid out by
+/// Linux.
+///
+/// From the top down, it holds the path `execfn` the program was run as, the
+/// environment and argument strings, the platform string and the random
+/// bytes of `AT_RANDOM`. Then come `argc`, the `argv` and `envp` arrays and
+/// the auxiliary vector, which is `auxv` completed with the entries that do
+/// not depend on the ELF file. The stack pointer is 16-byte aligned.
+///
+/// The words are 32-bit for programs that run in compatibility mode, if
+/// `compat` is true.
+///
+/// Returns the data of the stack and the initial stack pointer, at which the
+/// data starts.
+pub fn init_stack(
+    args: &[String],
+    envs: &[String],
+    execfn: &str,
+    auxv: &BTreeMap<u8, usize>,
+    stack_top: VirtAddr,
+    compat: bool,
+) -> (Vec<u8>, VirtAddr) {
+    let mut stack = StackBuilder {
+        reversed: Vec::new(),
+        top: stack_top.as_usize(),
+    };
+    stack.push(&[0; core::mem::size_of::<usize>()]);
+    let execfn = stack.push_str(execfn);
+    let mut envp: Vec<_> = envs.iter().rev().map(|env| stack.push_str(env)).collect();
+    envp.reverse();
+    let mut argv: Vec<_> = args.iter().rev().map(|arg| stack.push_str(arg)).collect();
+    argv.reverse();
+    let platform = if compat {
+        Some(COMPAT_PLATFORM)
+    } else {
+        PLATFORM
+    };
+    let platform = platform.map(|platform| stack.push_str(platform));
+    let mut random = [0; 16];
+    crate::random::fill_bytes(&mut random);
+    let random = stack.push(&random);
+
+    let mut auxv = auxv.clone();
+    let (hwcap, hwcap2) = hwcaps();
+    auxv.insert(AT_HWCAP, hwcap);
+    if let Some(hwcap2) = hwcap2 {
+        auxv.insert(AT_HWCAP2, hwcap2);
+    }
+    auxv.insert(AT_PAGESZ, PAGE_SIZE_4K);
+    auxv.insert(AT_CLKTCK, USER_HZ);
+    auxv.insert(AT_MINSIGSTKSZ, MINSIGSTKSZ);
+    // Every process runs as root, without any change of privileges.
+    for id in [AT_UID, AT_EUID, AT_GID, AT_EGID, AT_SECURE] {
+        auxv.insert(id, 0);
+    }
+    auxv.insert(AT_RANDOM, random);
+    auxv.insert(AT_EXECFN, execfn);
+    if let Some(platform) = platform {
+        auxv.insert(AT_PLATFORM, platform);
+    }
+
+    let mut words = vec![args.len()];
+    words.extend(argv);
+    words.push(0);
+    words.extend(envp);
+    words.push(0);
+    for (&key, &value) in auxv.iter() {
+        words.extend([key as usize, value]);
+    }
+    words.extend([AT_NULL as usize, 0]);
+
+    stack.pad_to(16);
+    let word_size = if compat {
+        core::mem::size_of::<u32>()
+    } else {
+        core::mem::size_of::<usize>()
+    };
+    let padding = (16 - words.len() * word_size % 16) % 16;
+    stack.push(&vec![0; padding]);
+    stack.push_words(&words, compat);
+    stack.finish()
 }
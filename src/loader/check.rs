@@ -0,0 +1,473 @@
+//! Checks of the headers of ELF files.
+//!
+//! They only look at the bytes of the file, and reject anything the loader
+//! could not map as asked: a file for another machine, headers past the end
+//! of the file, segments larger in the file than in memory or overlapping
+//! each other. Nothing here depends on the kernel, so they are tested on the
+//! host.
+
+/// The ELF file is malformed or not for this machine.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Malformed;
+
+/// The machine type of 32-bit x86.
+pub const EM_386: u16 = 3;
+/// The machine type of x86_64.
+pub const EM_X86_64: u16 = 62;
+/// The machine type of aarch64.
+pub const EM_AARCH64: u16 = 183;
+/// The machine type of RISC-V.
+pub const EM_RISCV: u16 = 243;
+
+/// The segment type of loadable segments.
+const PT_LOAD: u32 = 1;
+/// The segment type of the path of the program interpreter.
+const PT_INTERP: u32 = 3;
+/// The segment type of the initial image of the thread-local storage.
+const PT_TLS: u32 = 7;
+
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const ET_DYN: u16 = 3;
+
+/// The size of the ELF header.
+pub const EHDR_SIZE: usize = 64;
+/// The size of the ELF header of a 32-bit ELF file.
+const EHDR32_SIZE: usize = 52;
+/// The size of a program header.
+const PHDR_SIZE: usize = 56;
+/// The size of a program header of a 32-bit ELF file.
+const PHDR32_SIZE: usize = 32;
+/// The maximum length of the path of the program interpreter.
+pub const PATH_MAX: usize = 4096;
+/// The granularity segments are mapped with.
+const PAGE_SIZE: u64 = 0x1000;
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// The fields of the ELF header the loader depends on.
+#[derive(Debug)]
+pub struct Header {
+    /// Whether it is a 32-bit file.
+    pub compat: bool,
+    ph_offset: usize,
+    ph_count: usize,
+}
+
+impl Header {
+    fn phdr_size(&self) -> usize {
+        if self.compat {
+            PHDR32_SIZE
+        } else {
+            PHDR_SIZE
+        }
+    }
+
+    /// The end of the program headers in the file, if it does not overflow.
+    fn ph_end(&self) -> Option<usize> {
+        (self.ph_count * self.phdr_size()).checked_add(self.ph_offset)
+    }
+}
+
+/// Read the ELF header of `data` without checking it beyond its size.
+fn read_header(data: &[u8]) -> Result<Header, Malformed> {
+    if !data.starts_with(b"\x7fELF") {
+        return Err(Malformed);
+    }
+    match data.get(4) {
+        Some(&ELFCLASS64) if data.len() >= EHDR_SIZE => Ok(Header {
+            compat: false,
+            ph_offset: u64_at(data, 32).try_into().map_err(|_| Malformed)?,
+            ph_count: u16_at(data, 56) as usize,
+        }),
+        Some(&ELFCLASS32) if data.len() >= EHDR32_SIZE => Ok(Header {
+            compat: true,
+            ph_offset: u32_at(data, 28) as usize,
+            ph_count: u16_at(data, 44) as usize,
+        }),
+        _ => Err(Malformed),
+    }
+}
+
+/// The end of the program headers of the ELF file starting with `data`, to
+/// find out how much of the file to read.
+pub fn headers_end(data: &[u8]) -> Result<usize, Malformed> {
+    read_header(data)?.ph_end().ok_or(Malformed)
+}
+
+/// Check the ELF header of `data`.
+///
+/// The file must be a little-endian executable or shared object, 64-bit for
+/// `machine` or, if `compat_machine` is given, 32-bit for it. Its program
+/// headers must be within `data`.
+pub fn check_header(
+    data: &[u8],
+    machine: u16,
+    compat_machine: Option<u16>,
+) -> Result<Header, Malformed> {
+    let header = read_header(data)?;
+    if data[5] != ELFDATA2LSB || data[6] != EV_CURRENT {
+        return Err(Malformed);
+    }
+    let expect_machine = if header.compat {
+        compat_machine.ok_or(Malformed)?
+    } else {
+        machine
+    };
+    let (phdr_size, ph_entry_size) = if header.compat {
+        (PHDR32_SIZE, u16_at(data, 42))
+    } else {
+        (PHDR_SIZE, u16_at(data, 54))
+    };
+    if u16_at(data, 18) != expect_machine
+        || !matches!(u16_at(data, 16), ET_EXEC | ET_DYN)
+        || ph_entry_size as usize != phdr_size
+        || header.ph_count == 0
+        || header.ph_end().is_none_or(|end| end > data.len())
+    {
+        return Err(Malformed);
+    }
+    Ok(header)
+}
+
+/// The fields of a program header the loader depends on.
+#[derive(Clone, Copy, Debug)]
+struct ProgramHeader {
+    p_type: u32,
+    offset: u64,
+    vaddr: u64,
+    file_size: u64,
+    mem_size: u64,
+    align: u64,
+}
+
+impl ProgramHeader {
+    /// Check that the data of the segment is within a file of `file_size`
+    /// bytes.
+    fn check_bounds(&self, file_size: u64) -> Result<(), Malformed> {
+        match self.offset.checked_add(self.file_size) {
+            Some(end) if end <= file_size => Ok(()),
+            _ => Err(Malformed),
+        }
+    }
+
+    /// The pages the segment is mapped to.
+    fn page_range(&self) -> Option<(u64, u64)> {
+        let start = self.vaddr & !(PAGE_SIZE - 1);
+        let end = self
+            .vaddr
+            .checked_add(self.mem_size)?
+            .checked_add(PAGE_SIZE - 1)?
+            & !(PAGE_SIZE - 1);
+        Some((start, end))
+    }
+}
+
+/// The program headers of `data`, whose ELF header is `header`.
+fn program_headers<'a>(
+    data: &'a [u8],
+    header: &'a Header,
+) -> impl Iterator<Item = ProgramHeader> + 'a {
+    (0..header.ph_count).map(move |i| {
+        let ph = &data[header.ph_offset + i * header.phdr_size()..];
+        if header.compat {
+            ProgramHeader {
+                p_type: u32_at(ph, 0),
+                offset: u32_at(ph, 4) as u64,
+                vaddr: u32_at(ph, 8) as u64,
+                file_size: u32_at(ph, 16) as u64,
+                mem_size: u32_at(ph, 20) as u64,
+                align: u32_at(ph, 28) as u64,
+            }
+        } else {
+            ProgramHeader {
+                p_type: u32_at(ph, 0),
+                offset: u64_at(ph, 8),
+                vaddr: u64_at(ph, 16),
+                file_size: u64_at(ph, 32),
+                mem_size: u64_at(ph, 40),
+                align: u64_at(ph, 48),
+            }
+        }
+    })
+}
+
+/// Check the program headers of `data`, whose ELF header has passed
+/// [`check_header`], for a file of `file_size` bytes.
+///
+/// There must be a loadable segment. The loadable segments and the path of
+/// the interpreter must be within the file, the loadable segments must not
+/// be larger in the file than in memory nor share a page, and the image of
+/// the thread-local storage must be within the data of one of them.
+pub fn check_segments(data: &[u8], header: &Header, file_size: u64) -> Result<(), Malformed> {
+    let mut loads = 0;
+    for (i, ph) in program_headers(data, header).enumerate() {
+        match ph.p_type {
+            PT_INTERP => ph.check_bounds(file_size)?,
+            PT_LOAD => {
+                ph.check_bounds(file_size)?;
+                if ph.file_size > ph.mem_size {
+                    return Err(Malformed);
+                }
+                let (start, end) = ph.page_range().ok_or(Malformed)?;
+                let overlaps = program_headers(data, header)
+                    .take(i)
+                    .filter(|other| other.p_type == PT_LOAD)
+                    .filter_map(|other| other.page_range())
+                    .any(|(other_start, other_end)| start < other_end && other_start < end);
+                if overlaps {
+                    return Err(Malformed);
+                }
+                loads += 1;
+            }
+            _ => {}
+        }
+    }
+    if loads == 0 {
+        return Err(Malformed);
+    }
+
+    // The C library finds the initial image of the thread-local storage
+    // through the program headers at `AT_PHDR`, and copies it from the
+    // loaded data of the program.
+    if let Some(tls) = program_headers(data, header).find(|ph| ph.p_type == PT_TLS) {
+        let start = tls.vaddr;
+        let end = start.saturating_add(tls.file_size);
+        let loaded = program_headers(data, header)
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .any(|ph| ph.vaddr <= start && end <= ph.vaddr.saturating_add(ph.file_size));
+        if !loaded
+            || tls.file_size > tls.mem_size
+            || (tls.align != 0 && !tls.align.is_power_of_two())
+        {
+            return Err(Malformed);
+        }
+    }
+    Ok(())
+}
+
+/// Get the path of the program interpreter from `data`, the first
+/// [`PATH_MAX`] bytes at most of the `PT_INTERP` segment. The path must be
+/// NUL-terminated and not empty.
+pub fn interp_path(data: &[u8]) -> Result<&str, Malformed> {
+    let len = data
+        .iter()
+        .position(|&c| c == 0)
+        .filter(|&len| len > 0 && len < PATH_MAX)
+        .ok_or(Malformed)?;
+    core::str::from_utf8(&data[..len]).map_err(|_| Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A program header of a 64-bit test file.
+    struct Phdr {
+        p_type: u32,
+        offset: u64,
+        vaddr: u64,
+        file_size: u64,
+        mem_size: u64,
+    }
+
+    const fn load(offset: u64, vaddr: u64, file_size: u64, mem_size: u64) -> Phdr {
+        Phdr {
+            p_type: PT_LOAD,
+            offset,
+            vaddr,
+            file_size,
+            mem_size,
+        }
+    }
+
+    /// Build a 64-bit x86_64 executable with the program headers `phdrs`
+    /// right after the ELF header, padded to `size` bytes.
+    fn elf64(phdrs: &[Phdr], size: usize) -> Vec<u8> {
+        let mut data = vec![0; EHDR_SIZE];
+        data[..4].copy_from_slice(b"\x7fELF");
+        data[4] = ELFCLASS64;
+        data[5] = ELFDATA2LSB;
+        data[6] = EV_CURRENT;
+        data[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        data[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[32..40].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        data[52..54].copy_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        data[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        data[56..58].copy_from_slice(&(phdrs.len() as u16).to_le_bytes());
+        for ph in phdrs {
+            let mut raw = [0; PHDR_SIZE];
+            raw[0..4].copy_from_slice(&ph.p_type.to_le_bytes());
+            raw[8..16].copy_from_slice(&ph.offset.to_le_bytes());
+            raw[16..24].copy_from_slice(&ph.vaddr.to_le_bytes());
+            raw[24..32].copy_from_slice(&ph.vaddr.to_le_bytes());
+            raw[32..40].copy_from_slice(&ph.file_size.to_le_bytes());
+            raw[40..48].copy_from_slice(&ph.mem_size.to_le_bytes());
+            raw[48..56].copy_from_slice(&PAGE_SIZE.to_le_bytes());
+            data.extend(raw);
+        }
+        data.resize(data.len().max(size), 0);
+        data
+    }
+
+    fn check(data: &[u8]) -> Result<(), Malformed> {
+        let header = check_header(data, EM_X86_64, None)?;
+        check_segments(data, &header, data.len() as u64)
+    }
+
+    #[test]
+    fn accepts_valid_file() {
+        let data = elf64(
+            &[
+                load(0, 0x40_0000, 0x1000, 0x1000),
+                load(0x1000, 0x40_1000, 0x800, 0x2000),
+            ],
+            0x2000,
+        );
+        assert_eq!(check(&data), Ok(()));
+        assert_eq!(headers_end(&data), Ok(EHDR_SIZE + 2 * PHDR_SIZE));
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let data = elf64(&[load(0, 0x40_0000, 0x100, 0x100)], 0x100);
+        for len in [0, 4, 16, EHDR_SIZE - 1] {
+            assert_eq!(headers_end(&data[..len]), Err(Malformed));
+            assert!(check_header(&data[..len], EM_X86_64, None).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_phdrs_past_eof() {
+        let data = elf64(&[load(0, 0x40_0000, 0x10, 0x10)], 0);
+        assert!(check_header(&data[..data.len() - 1], EM_X86_64, None).is_err());
+
+        let mut data = data;
+        data[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(check_header(&data, EM_X86_64, None).is_err());
+    }
+
+    #[test]
+    fn rejects_segment_past_eof() {
+        let data = elf64(&[load(0, 0x40_0000, 0x1001, 0x2000)], 0x1000);
+        assert_eq!(check(&data), Err(Malformed));
+        let data = elf64(&[load(u64::MAX, 0x40_0000, 2, 2)], 0x1000);
+        assert_eq!(check(&data), Err(Malformed));
+    }
+
+    #[test]
+    fn rejects_file_size_over_mem_size() {
+        let data = elf64(&[load(0, 0x40_0000, 0x100, 0x80)], 0x1000);
+        assert_eq!(check(&data), Err(Malformed));
+    }
+
+    #[test]
+    fn rejects_overlapping_loads() {
+        let data = elf64(
+            &[
+                load(0, 0x40_0000, 0x1000, 0x1000),
+                load(0, 0x40_0800, 0x1000, 0x1000),
+            ],
+            0x1000,
+        );
+        assert_eq!(check(&data), Err(Malformed));
+        // Segments sharing a page cannot be mapped with different data.
+        let data = elf64(
+            &[
+                load(0, 0x40_0000, 0x100, 0x100),
+                load(0x200, 0x40_0200, 0x100, 0x100),
+            ],
+            0x1000,
+        );
+        assert_eq!(check(&data), Err(Malformed));
+    }
+
+    #[test]
+    fn rejects_file_without_load() {
+        let data = elf64(&[], 0x100);
+        assert!(check_header(&data, EM_X86_64, None).is_err());
+        let interp = Phdr {
+            p_type: PT_INTERP,
+            ..load(0x100, 0, 0x10, 0x10)
+        };
+        let data = elf64(&[interp], 0x200);
+        assert_eq!(check(&data), Err(Malformed));
+    }
+
+    #[test]
+    fn rejects_wrong_class() {
+        let mut data = elf64(&[load(0, 0x40_0000, 0x100, 0x100)], 0x1000);
+        data[4] = ELFCLASS32;
+        assert!(check_header(&data, EM_X86_64, None).is_err());
+        assert!(check_header(&data, EM_X86_64, Some(EM_386)).is_err());
+        data[4] = 3;
+        assert!(check_header(&data, EM_X86_64, Some(EM_386)).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_endianness() {
+        let mut data = elf64(&[load(0, 0x40_0000, 0x100, 0x100)], 0x1000);
+        data[5] = 2;
+        assert!(check_header(&data, EM_X86_64, None).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_machine() {
+        let data = elf64(&[load(0, 0x40_0000, 0x100, 0x100)], 0x1000);
+        assert!(check_header(&data, EM_AARCH64, None).is_err());
+        assert!(check_header(&data, EM_RISCV, Some(EM_X86_64)).is_err());
+    }
+
+    #[test]
+    fn rejects_wrong_type() {
+        let mut data = elf64(&[load(0, 0x40_0000, 0x100, 0x100)], 0x1000);
+        data[16..18].copy_from_slice(&1u16.to_le_bytes());
+        assert!(check_header(&data, EM_X86_64, None).is_err());
+    }
+
+    #[test]
+    fn checks_tls_within_load() {
+        let tls = |vaddr| Phdr {
+            p_type: PT_TLS,
+            ..load(0x100, vaddr, 0x10, 0x20)
+        };
+        let data = elf64(
+            &[load(0, 0x40_0000, 0x1000, 0x1000), tls(0x40_0100)],
+            0x1000,
+        );
+        assert_eq!(check(&data), Ok(()));
+        let data = elf64(
+            &[load(0, 0x40_0000, 0x1000, 0x1000), tls(0x50_0000)],
+            0x1000,
+        );
+        assert_eq!(check(&data), Err(Malformed));
+    }
+
+    #[test]
+    fn interp_path_must_be_terminated() {
+        assert_eq!(
+            interp_path(b"/lib/ld-musl-x86_64.so.1\0"),
+            Ok("/lib/ld-musl-x86_64.so.1")
+        );
+        assert_eq!(interp_path(b"/lib/ld.so\0garbage"), Ok("/lib/ld.so"));
+        assert_eq!(interp_path(b"/lib/ld.so"), Err(Malformed));
+        assert_eq!(interp_path(b"\0"), Err(Malformed));
+        assert_eq!(interp_path(b""), Err(Malformed));
+        assert_eq!(interp_path(&[b'a'; PATH_MAX]), Err(Malformed));
+        assert_eq!(interp_path(b"\xff\0"), Err(Malformed));
+    }
+}
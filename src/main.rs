@@ -11,64 +11,132 @@ extern crate axstd;
 mod config {
     include!(concat!(env!("OUT_DIR"), "/uspace_config.rs"));
 }
+#[cfg(feature = "ia32")]
+mod ia32;
+mod ipc;
 mod loader;
 mod mm;
+mod procfs;
+mod random;
 mod syscall_imp;
 mod task;
+mod vdso;
+mod vfs;
+
+#[cfg(all(feature = "ia32", not(target_arch = "x86_64")))]
+compile_error!("the `ia32` feature is only available on x86_64");
 
 use alloc::vec;
-use alloc::{string::String, sync::Arc};
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 
-use axhal::arch::UspaceContext;
 use axsync::Mutex;
+use axtask::AxCpuMask;
+
+/// The environment the test programs are started with.
+const DEFAULT_ENVS: &[&str] = &["PATH=/bin:/usr/bin:/sbin:/usr/sbin:/initrd/bin", "HOME=/"];
+
+/// The test programs run when no list is given at build time.
+const DEFAULT_TESTCASES: &[&str] = &[
+    "mkdir_",
+    "open",
+    "openat",
+    "close",
+    "write",
+    "read",
+    "brk",
+    "dup",
+    "dup2",
+    "chdir",
+    "getcwd",
+    "getpid",
+    "clone",
+    "execve",
+    "exit",
+    "fork",
+    "fstat",
+    "getdents",
+    "getppid",
+    "gettimeofday",
+    "mmap",
+    "mount",
+    "munmap",
+    "pipe",
+    "times",
+    "umount",
+    "uname",
+    "unlink",
+    "wait",
+    "waitpid",
+    "yield",
+];
+
+/// The test programs to run, in order: the `testcase_list` of the test case
+/// the kernel is built with, passed by the Makefile in `AX_TESTCASES_LIST`.
+fn testcases() -> Vec<&'static str> {
+    match option_env!("AX_TESTCASES_LIST") {
+        Some(list) if !list.trim().is_empty() => list
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect(),
+        _ => DEFAULT_TESTCASES.to_vec(),
+    }
+}
+
+/// The path of the test program `name`: the file on the disk if there is one,
+/// or else the app built into the kernel image.
+fn app_path(name: &str) -> String {
+    if axstd::fs::metadata(name).is_err() && loader::get_app_data_by_name(name).is_some() {
+        format!("{}/{}", mm::page_cache::INITRD_DIR, name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Run `f` on every CPU and wait for it to finish everywhere.
+fn on_each_cpu(f: fn()) {
+    let tasks: Vec<_> = AxCpuMask::full()
+        .into_iter()
+        .map(|cpu| {
+            axtask::spawn(move || {
+                if axtask::set_current_affinity(AxCpuMask::one_shot(cpu)) {
+                    f();
+                } else {
+                    warn!("Failed to run on CPU {}", cpu);
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.join();
+    }
+}
 
 #[no_mangle]
 fn main() {
-    let names = [
-        "mkdir_",
-        "open",
-        "openat",
-        "close",
-        "write",
-        "read",
-        "brk",
-        "dup",
-        "dup2",
-        "chdir",
-        "getcwd",
-        "getpid",
-        "clone",
-        "execve",
-        "exit",
-        "fork",
-        "fstat",
-        "getdents",
-        "getppid",
-        "gettimeofday",
-        "mmap",
-        "mount",
-        "munmap",
-        "pipe",
-        "times",
-        "umount",
-        "uname",
-        "unlink",
-        "wait",
-        "waitpid",
-        "yield",
-    ];
-
-    for name in names.into_iter() {
-        let args = vec![];
-        let envs = vec![];
-
-        let user_app = mm::load_user_app(String::from(name), args, envs)
+    on_each_cpu(|| {
+        mm::icache::init_percpu();
+        vdso::init_percpu();
+    });
+    mm::swap::init();
+    vdso::init();
+
+    for name in testcases() {
+        let args = vec![name.to_string()];
+        let envs = DEFAULT_ENVS.iter().map(|env| env.to_string()).collect();
+
+        let (aspace, user_app) = mm::load_user_app(app_path(name), args, envs, mm::aslr_enabled(0))
             .expect("Testcase executable not found");
 
         let user_task = task::spawn_user_task(
-            Arc::new(Mutex::new(user_app.aspace)),
-            UspaceContext::new(user_app.entry.as_usize(), user_app.sp, 2333),
-            user_app.break_pos,
+            Arc::new(Mutex::new(aspace)),
+            task::uspace_context(&user_app, 2333),
+            user_app,
         );
 
         let exit_code = user_task.join();
@@ -79,4 +147,6 @@ fn main() {
             _ => {}
         }
     }
+
+    mm::page_cache::sync_all();
 }
@@ -0,0 +1,242 @@
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet, VecDeque},
+    sync::Arc,
+};
+
+use axhal::paging::MappingFlags;
+use memory_addr::{VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use super::page_cache::{CachedFile, CachedPage};
+use super::swap::SwapMap;
+use super::RangeMap;
+use crate::ipc::ShmAttachment;
+
+/// The access pattern hint given to a range by `madvise`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadAdvice {
+    /// Pages will be accessed in sequential order (`MADV_SEQUENTIAL`).
+    Sequential,
+    /// Pages will be accessed in random order (`MADV_RANDOM`).
+    Random,
+}
+
+/// A mapping of a file into the user address space.
+#[derive(Clone)]
+pub struct FileMapping {
+    /// The mapped file.
+    pub file: Arc<CachedFile>,
+    /// The start address of the whole mapping.
+    pub start: VirtAddr,
+    /// The file offset mapped at `start`.
+    pub offset: usize,
+    /// The permissions of the mapping.
+    pub flags: MappingFlags,
+    /// Whether the mapping shares the page cache (`MAP_SHARED`) rather than
+    /// working on a private copy.
+    pub shared: bool,
+}
+
+impl FileMapping {
+    /// The index of the file page mapped at `vaddr`.
+    pub fn page_index(&self, vaddr: VirtAddr) -> usize {
+        (self.offset + (vaddr - self.start)) / PAGE_SIZE_4K
+    }
+}
+
+/// The memory usage counters of a process, in pages.
+#[derive(Clone, Debug)]
+pub struct MemStats {
+    /// Mapped virtual memory.
+    pub total_vm: usize,
+    /// The peak of `total_vm`.
+    pub peak_vm: usize,
+    /// Resident anonymous pages, including private copies of file pages.
+    pub anon_pages: usize,
+    /// Resident page cache pages of shared mappings.
+    pub file_pages: usize,
+    /// The peak of the resident set size.
+    pub peak_rss: usize,
+    /// Faults served without swapping in.
+    pub minor_faults: usize,
+    /// Faults that swapped a page in.
+    pub major_faults: usize,
+    /// The 2 MiB regions that have had pages mapped. Each needs a last-level
+    /// page table, which is not freed when the pages are unmapped.
+    pt_regions: BTreeSet<usize>,
+}
+
+impl MemStats {
+    const fn new() -> Self {
+        Self {
+            total_vm: 0,
+            peak_vm: 0,
+            anon_pages: 0,
+            file_pages: 0,
+            peak_rss: 0,
+            minor_faults: 0,
+            major_faults: 0,
+            pt_regions: BTreeSet::new(),
+        }
+    }
+
+    /// The resident set size.
+    pub fn rss(&self) -> usize {
+        self.anon_pages + self.file_pages
+    }
+
+    /// The number of page table pages.
+    pub fn page_table_pages(&self) -> usize {
+        let tables_above = |shift: u32| {
+            self.pt_regions
+                .iter()
+                .map(|region| region >> shift)
+                .collect::<BTreeSet<_>>()
+                .len()
+        };
+        // The root, and the tables covering 1 GiB and, with 4 levels, 512 GiB
+        // each.
+        let upper = if cfg!(target_arch = "riscv64") {
+            tables_above(9)
+        } else {
+            tables_above(9) + tables_above(18)
+        };
+        1 + upper + self.pt_regions.len()
+    }
+
+    /// Start the counters of a forked child, which begins with the mappings
+    /// of its parent.
+    pub fn reset_for_child(&mut self) {
+        self.peak_vm = self.total_vm;
+        self.peak_rss = self.rss();
+        self.minor_faults = 0;
+        self.major_faults = 0;
+    }
+
+    fn map(&mut self, pages: usize) {
+        self.total_vm += pages;
+        self.peak_vm = self.peak_vm.max(self.total_vm);
+    }
+
+    fn unmap(&mut self, pages: usize) {
+        self.total_vm = self.total_vm.saturating_sub(pages);
+    }
+
+    fn page_in(&mut self, vaddr: VirtAddr, file: bool) {
+        if file {
+            self.file_pages += 1;
+        } else {
+            self.anon_pages += 1;
+        }
+        self.peak_rss = self.peak_rss.max(self.rss());
+        self.pt_regions.insert(vaddr.as_usize() >> 21);
+    }
+
+    fn page_out(&mut self, file: bool) {
+        if file {
+            self.file_pages = self.file_pages.saturating_sub(1);
+        } else {
+            self.anon_pages = self.anon_pages.saturating_sub(1);
+        }
+    }
+}
+
+/// Attributes of user mappings that are not tracked by `AddrSpace`.
+#[derive(Clone)]
+pub struct VmAttrs {
+    /// Access pattern hints. Ranges without an entry use the default policy.
+    pub read_advice: RangeMap<ReadAdvice>,
+    /// Ranges that are not inherited by child processes (`MADV_DONTFORK`).
+    pub dontfork: RangeMap<()>,
+    /// File-backed mappings. Their pages are populated from the page cache.
+    pub file_maps: RangeMap<FileMapping>,
+    /// Page cache pages mapped into shared file mappings, kept alive as long
+    /// as they are mapped.
+    pub shared_pages: BTreeMap<VirtAddr, Arc<CachedPage>>,
+    /// Attached System V shared memory segments. Their pages are also
+    /// described by shared entries in `file_maps`.
+    pub shm: RangeMap<ShmAttachment>,
+    /// Anonymous pages faulted in, the oldest first. They are the candidates
+    /// for swapping out. Entries of pages that are gone are skipped.
+    pub resident: VecDeque<VirtAddr>,
+    /// Pages that have been swapped out.
+    pub swapped: SwapMap,
+    /// The mapped ranges and their permissions, for accounting.
+    pub mapped: RangeMap<MappingFlags>,
+    /// Anonymous pages mapped read-only to the shared zero frame, see
+    /// [`super::zero_page`]. They are not counted as resident.
+    pub zero_pages: BTreeSet<VirtAddr>,
+    /// Ranges mapped to kernel memory, like the vDSO. Their frames are never
+    /// freed or swapped out.
+    pub kernel_maps: RangeMap<()>,
+    /// The memory usage counters.
+    pub stats: MemStats,
+}
+
+impl VmAttrs {
+    pub const fn new() -> Self {
+        Self {
+            read_advice: RangeMap::new(),
+            dontfork: RangeMap::new(),
+            file_maps: RangeMap::new(),
+            shared_pages: BTreeMap::new(),
+            shm: RangeMap::new(),
+            resident: VecDeque::new(),
+            swapped: SwapMap::new(),
+            mapped: RangeMap::new(),
+            zero_pages: BTreeSet::new(),
+            kernel_maps: RangeMap::new(),
+            stats: MemStats::new(),
+        }
+    }
+
+    /// Account for `range` being mapped with `flags`.
+    pub fn mapped(&mut self, range: VirtAddrRange, flags: MappingFlags) {
+        self.mapped.insert(range, flags);
+        self.stats.map(range.size() / PAGE_SIZE_4K);
+    }
+
+    /// Account for the page at `vaddr` being populated. Anonymous pages
+    /// become candidates for swapping out.
+    pub fn page_populated(&mut self, vaddr: VirtAddr, file: bool) {
+        // The queue grows when memory may be short, and a page that does not
+        // fit is never swapped out rather than failing the fault.
+        if !file && self.resident.try_reserve(1).is_ok() {
+            self.resident.push_back(vaddr);
+        }
+        self.stats.page_in(vaddr, file);
+    }
+
+    /// Account for the resident page at `vaddr` being freed.
+    pub fn page_freed(&mut self, vaddr: VirtAddr) {
+        if self.zero_pages.remove(&vaddr) {
+            return;
+        }
+        let file = self.shared_pages.contains_key(&vaddr);
+        self.stats.page_out(file);
+    }
+
+    /// The size of the mapped ranges that satisfy `filter`, in pages.
+    pub fn mapped_pages(&self, filter: impl Fn(VirtAddrRange, MappingFlags) -> bool) -> usize {
+        self.mapped
+            .iter()
+            .filter(|(range, flags)| filter(*range, **flags))
+            .map(|(range, _)| range.size() / PAGE_SIZE_4K)
+            .sum()
+    }
+
+    /// Drop all attributes of `range`, e.g. after it has been unmapped.
+    pub fn forget(&mut self, range: VirtAddrRange) {
+        self.read_advice.remove(range);
+        self.dontfork.remove(range);
+        self.file_maps.remove(range);
+        self.shared_pages.retain(|vaddr, _| !range.contains(*vaddr));
+        self.shm.remove(range);
+        self.resident.retain(|vaddr| !range.contains(*vaddr));
+        self.swapped.remove_range(range);
+        self.zero_pages.retain(|vaddr| !range.contains(*vaddr));
+        self.kernel_maps.remove(range);
+        let unmapped: usize = self.mapped.overlapping(range).map(|(r, _)| r.size()).sum();
+        self.stats.unmap(unmapped / PAGE_SIZE_4K);
+        self.mapped.remove(range);
+    }
+}
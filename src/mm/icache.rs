@@ -0,0 +1,80 @@
+//! Keeping the instruction cache coherent with the data written to
+//! executable pages.
+//!
+//! The kernel writes instructions through the data cache when it loads
+//! programs, copies pages on fork and pages them back in. On aarch64 the data
+//! cache lines of such pages are cleaned to the point of unification and the
+//! instruction cache is invalidated. On riscv64 a `fence.i` is issued, which
+//! only synchronizes the current hart. x86 keeps the caches coherent in
+//! hardware.
+use axmm::AddrSpace;
+use memory_addr::{PageIter4K, PhysAddr, VirtAddrRange};
+
+/// Allow user mode to maintain the caches itself, as done by `__clear_cache`
+/// on aarch64. The setting is per CPU, so this runs on every CPU.
+pub fn init_percpu() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        // SCTLR_EL1.UCI and SCTLR_EL1.UCT
+        core::arch::asm!(
+            "mrs {tmp}, sctlr_el1",
+            "orr {tmp}, {tmp}, #(1 << 26)",
+            "orr {tmp}, {tmp}, #(1 << 15)",
+            "msr sctlr_el1, {tmp}",
+            "isb",
+            tmp = out(reg) _,
+        );
+    }
+}
+
+/// Clean the data cache lines of the frame at `paddr` to the point of
+/// unification.
+#[cfg(target_arch = "aarch64")]
+fn clean_dcache(paddr: PhysAddr) {
+    use memory_addr::PAGE_SIZE_4K;
+
+    let ctr: usize;
+    unsafe { core::arch::asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    // CTR_EL0.DminLine is the log2 of the smallest line size in words.
+    let line_size = 4 << ((ctr >> 16) & 0xf);
+    let start = axhal::mem::phys_to_virt(paddr).as_usize();
+    for addr in (start..start + PAGE_SIZE_4K).step_by(line_size) {
+        unsafe { core::arch::asm!("dc cvau, {}", in(reg) addr) };
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn clean_dcache(_paddr: PhysAddr) {}
+
+/// Invalidate the instruction cache.
+pub fn flush_icache_all() {
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!("dsb ish", "ic ialluis", "dsb ish", "isb");
+    }
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!("fence.i");
+    }
+}
+
+/// Make the instructions written to the frame at `paddr` visible to
+/// instruction fetches.
+pub fn sync_frame(paddr: PhysAddr) {
+    clean_dcache(paddr);
+    flush_icache_all();
+}
+
+/// Make the instructions written to the resident pages of `range` visible
+/// to instruction fetches.
+pub fn sync_range(aspace: &AddrSpace, range: VirtAddrRange) {
+    if cfg!(target_arch = "x86_64") {
+        return;
+    }
+    for vaddr in PageIter4K::new(range.start, range.end).unwrap() {
+        if let Ok((paddr, _, _)) = aspace.page_table().query(vaddr) {
+            clean_dcache(paddr);
+        }
+    }
+    flush_icache_all();
+}
@@ -0,0 +1,947 @@
+mod attrs;
+pub mod icache;
+pub mod oom;
+pub mod page_cache;
+mod pte;
+mod range_map;
+pub mod swap;
+pub mod uaccess;
+pub mod zero_page;
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+
+use axerrno::{AxResult, LinuxError, LinuxResult};
+use axhal::{
+    mem::phys_to_virt,
+    paging::MappingFlags,
+    trap::{register_trap_handler, PAGE_FAULT},
+};
+use axmm::AddrSpace;
+use axtask::TaskExtRef;
+use memory_addr::{MemoryAddr, PageIter4K, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use crate::loader::{self, ELFInfo};
+use crate::task::{self, RlimitResource, TaskExt};
+use crate::{config, random, vdso};
+
+pub use self::attrs::{FileMapping, MemStats, ReadAdvice, VmAttrs};
+pub use self::range_map::RangeMap;
+
+/// The `personality` flag that disables address space layout randomization.
+pub const ADDR_NO_RANDOMIZE: u32 = 0x0040000;
+
+/// The size of the user space below the stack, where the executable, the
+/// program break and the mappings are placed.
+const LOWER_SPACE_SIZE: usize = config::USER_STACK_TOP - config::USER_SPACE_BASE;
+/// The range of the random offset added to the base of PIE executables.
+const ELF_RANDOM_RANGE: usize = LOWER_SPACE_SIZE / 64;
+/// The offset of the mmap base from the start of the user space. The room
+/// below it is left to the executable and the program break.
+const MMAP_BASE_OFFSET: usize = LOWER_SPACE_SIZE / 4;
+/// The range of the random offset added to the mmap base.
+const MMAP_RANDOM_RANGE: usize = LOWER_SPACE_SIZE / 64;
+/// The range of the random offset subtracted from the stack top.
+const STACK_RANDOM_RANGE: usize = if config::USER_STACK_TOP / 64 < 1 << 34 {
+    config::USER_STACK_TOP / 64
+} else {
+    1 << 34
+};
+/// The range of the random offset added to the program break, 32 MiB as in Linux.
+const BRK_RANDOM_RANGE: usize = 0x200_0000;
+
+/// The end of the address space of 32-bit programs, which run in
+/// compatibility mode. It is `TASK_SIZE` of 32-bit tasks in Linux.
+pub const COMPAT_TASK_SIZE: usize = 0xffff_e000;
+
+/// Where the parts of a program are placed in its address space.
+struct Layout {
+    /// The highest address of the user stack.
+    stack_top: usize,
+    /// The range of the random offset added to the base of PIE executables.
+    elf_random_range: usize,
+    /// The offset of the mmap base from the start of the user space, before
+    /// randomization.
+    mmap_base_offset: usize,
+    /// The range of the random offset added to the mmap base.
+    mmap_random_range: usize,
+    /// The range of the random offset subtracted from the stack top.
+    stack_random_range: usize,
+}
+
+impl Layout {
+    /// The layout of 64-bit programs.
+    const NATIVE: Self = Self {
+        stack_top: config::USER_STACK_TOP,
+        elf_random_range: ELF_RANDOM_RANGE,
+        mmap_base_offset: MMAP_BASE_OFFSET,
+        mmap_random_range: MMAP_RANDOM_RANGE,
+        stack_random_range: STACK_RANDOM_RANGE,
+    };
+
+    /// The layout of 32-bit programs. They have the 4 GiB below
+    /// [`COMPAT_TASK_SIZE`], so their offsets are much smaller.
+    const COMPAT: Self = Self {
+        stack_top: COMPAT_TASK_SIZE,
+        elf_random_range: 0x100_0000,
+        mmap_base_offset: 0x4000_0000,
+        mmap_random_range: 0x100_0000,
+        stack_random_range: 0x80_0000,
+    };
+
+    /// The layout of 64-bit programs, or of 32-bit ones if `compat` is true.
+    fn of(compat: bool) -> &'static Self {
+        if compat {
+            &Self::COMPAT
+        } else {
+            &Self::NATIVE
+        }
+    }
+}
+
+/// Whether a program loaded by a process with the given `personality` gets a
+/// randomized address space layout.
+pub fn aslr_enabled(personality: u32) -> bool {
+    config::RANDOMIZE_VA_SPACE != 0 && personality & ADDR_NO_RANDOMIZE == 0
+}
+
+/// Whether a mapping with `flags` is allowed by the W^X policy, which rejects
+/// memory that is both writable and executable when it is strict.
+pub fn wx_allowed(flags: MappingFlags) -> bool {
+    config::STRICT_WX == 0 || !flags.contains(MappingFlags::WRITE | MappingFlags::EXECUTE)
+}
+
+/// Get a random page-aligned offset in `[0, range)`.
+fn random_offset(range: usize) -> usize {
+    random::random_below(range / PAGE_SIZE_4K) * PAGE_SIZE_4K
+}
+
+pub struct UserApp {
+    /// The entry point of the user app.
+    pub entry: VirtAddr,
+    /// The top of the user stack.
+    pub sp: VirtAddr,
+    /// The program break position.
+    pub break_pos: VirtAddr,
+    /// The initially mapped range of the user stack.
+    pub stack: VirtAddrRange,
+    /// Where the search for free areas in `mmap` starts.
+    pub mmap_base: VirtAddr,
+    /// The attributes of the mappings of the user app.
+    pub vm_attrs: VmAttrs,
+    /// Whether the user app is a 32-bit x86 program.
+    pub compat: bool,
+}
+
+/// The maximum total size of the arguments and environment of a program.
+pub const ARG_MAX: usize = 0x2_0000;
+/// The maximum size of a single argument or environment string, including
+/// the terminating NUL.
+pub const MAX_ARG_STRLEN: usize = 32 * PAGE_SIZE_4K;
+
+/// Check that `args` and `envs` fit in the initial stack of a program.
+///
+/// As in Linux, the strings and the pointers to them may take up a quarter of
+/// the stack, and no more than [`ARG_MAX`].
+fn check_arg_size(args: &[String], envs: &[String], stack_size: usize) -> LinuxResult {
+    let mut total = 0usize;
+    for s in args.iter().chain(envs) {
+        let len = s.len() + 1;
+        if len > MAX_ARG_STRLEN {
+            return Err(LinuxError::E2BIG);
+        }
+        total += len + size_of::<usize>();
+    }
+    if total > ARG_MAX.min(stack_size / 4) {
+        return Err(LinuxError::E2BIG);
+    }
+    Ok(())
+}
+
+/// How many interpreters of scripts may be nested.
+const MAX_INTERP_DEPTH: usize = 4;
+/// The maximum length of the `#!` line of a script that is looked at.
+const SHEBANG_MAX: usize = 256;
+
+/// Get the interpreter of the script `name` with contents `data` and its
+/// optional argument, or `None` if it is not a script.
+///
+/// The interpreter is given by the `#!` line. Shell scripts without one are
+/// run by `busybox sh`.
+fn script_interpreter(name: &str, data: &[u8]) -> LinuxResult<Option<(String, Option<String>)>> {
+    let Some(line) = data.strip_prefix(b"#!") else {
+        if name.ends_with(".sh") && !data.starts_with(b"\x7fELF") {
+            return Ok(Some((String::from("busybox"), Some(String::from("sh")))));
+        }
+        return Ok(None);
+    };
+    let line = &line[..line.len().min(SHEBANG_MAX - 2)];
+    let line = match line.iter().position(|&c| c == b'\n') {
+        Some(end) => &line[..end],
+        None => line,
+    };
+    let line = core::str::from_utf8(line)
+        .map_err(|_| LinuxError::ENOEXEC)?
+        .trim();
+    // Everything after the interpreter is a single argument.
+    let (interp, arg) = match line.split_once([' ', '\t']) {
+        Some((interp, arg)) => (interp, Some(arg.trim()).filter(|arg| !arg.is_empty())),
+        None => (line, None),
+    };
+    if interp.is_empty() {
+        return Err(LinuxError::ENOEXEC);
+    }
+    Ok(Some((interp.to_string(), arg.map(ToString::to_string))))
+}
+
+/// A program that is ready to be loaded. Its files have been opened and
+/// checked, so that loading it fails only if memory runs short.
+pub struct Executable {
+    /// The path the program was run as.
+    execfn: String,
+    file: Arc<page_cache::CachedFile>,
+    elf_info: ELFInfo,
+    /// The path, the file and the ELF information of the interpreter.
+    interp: Option<(String, Arc<page_cache::CachedFile>, ELFInfo)>,
+    args: Vec<String>,
+    envs: Vec<String>,
+    randomize: bool,
+}
+
+/// Check that the segments and the stack of `elf_info` are not both writable
+/// and executable, unless this is allowed.
+fn check_wx(elf_info: &ELFInfo) -> LinuxResult {
+    let flags = elf_info.segments.iter().map(|segment| segment.flags);
+    if !flags.chain([elf_info.stack_flags]).all(wx_allowed) {
+        return Err(LinuxError::EACCES);
+    }
+    Ok(())
+}
+
+/// Open the program `name` and everything it needs to be loaded.
+///
+/// `file` is the program if it is already open, e.g. with `execveat`, and
+/// `name` is then only the path it runs as. `args` is the whole argument
+/// vector, starting with the program name. If it is empty, the program gets
+/// `name` as its only argument. Scripts starting with `#!` are run by their
+/// interpreter.
+///
+/// If `randomize` is true, the executable base (for PIE), the stack top, the
+/// mmap base and the program break are shifted by random offsets.
+pub fn open_executable(
+    mut name: String,
+    file: Option<Arc<page_cache::CachedFile>>,
+    args: Vec<String>,
+    envs: Vec<String>,
+    randomize: bool,
+) -> LinuxResult<Executable> {
+    let mut args = if args.is_empty() {
+        vec![name.clone()]
+    } else {
+        args
+    };
+    let mut file = match file {
+        Some(file) => file,
+        None => page_cache::open(&name)?,
+    };
+    let execfn = name.clone();
+    // A script runs in its interpreter, with the interpreter, its argument
+    // and the path of the script in place of the program name. The
+    // interpreter may be a script itself.
+    let mut depth = 0;
+    while let Some((interp, interp_arg)) = script_interpreter(&name, &file_head(&file)?)? {
+        if depth == MAX_INTERP_DEPTH {
+            return Err(LinuxError::ELOOP);
+        }
+        depth += 1;
+        let mut script_args = vec![interp.clone()];
+        script_args.extend(interp_arg);
+        script_args.push(name);
+        script_args.extend(args.drain(1..));
+        args = script_args;
+        file = page_cache::open(&interp)?;
+        name = interp;
+    }
+    check_arg_size(&args, &envs, config::USER_STACK_SIZE)?;
+
+    let user_base = VirtAddr::from_usize(config::USER_SPACE_BASE);
+    let mut elf_info = loader::load_elf(&file, user_base)?;
+    // How far a PIE executable may be moved depends on whether it is
+    // 32-bit, which is known once it is parsed.
+    if randomize && elf_info.base.as_usize() != 0 {
+        let elf_offset = random_offset(Layout::of(elf_info.compat).elf_random_range);
+        elf_info = loader::load_elf(&file, user_base + elf_offset)?;
+    }
+    check_wx(&elf_info)?;
+    let interp = match &elf_info.interp {
+        Some(path) => {
+            let interp_file = page_cache::open(path)?;
+            // Where it goes is known only once the program is mapped.
+            let interp_info = loader::load_elf(&interp_file, user_base)?;
+            if interp_info.interp.is_some() || interp_info.compat != elf_info.compat {
+                return Err(LinuxError::ELIBBAD);
+            }
+            check_wx(&interp_info)?;
+            Some((path.clone(), interp_file, interp_info))
+        }
+        None => None,
+    };
+    Ok(Executable {
+        execfn,
+        file,
+        elf_info,
+        interp,
+        args,
+        envs,
+        randomize,
+    })
+}
+
+/// Load the program `exe` into the empty user address space `uspace`.
+pub fn load_executable(uspace: &mut AddrSpace, exe: Executable) -> LinuxResult<UserApp> {
+    let Executable {
+        execfn,
+        file,
+        mut elf_info,
+        interp,
+        args,
+        envs,
+        randomize,
+    } = exe;
+    let aslr_offset = |range| if randomize { random_offset(range) } else { 0 };
+    let compat = elf_info.compat;
+    let layout = Layout::of(compat);
+
+    let mut vm_attrs = VmAttrs::new();
+    map_segments(uspace, &mut vm_attrs, &file, &elf_info)?;
+    let break_pos = elf_info
+        .segments
+        .iter()
+        .fold(VirtAddr::from_usize(0), |cur, seg| {
+            let end = seg.start_vaddr + seg.size;
+            let end = end.align_up_4k();
+
+            cur.max(end)
+        });
+    let break_pos = if config::RANDOMIZE_VA_SPACE >= 2 {
+        break_pos + aslr_offset(BRK_RANDOM_RANGE)
+    } else {
+        break_pos
+    };
+    // The executable and the program break stay below the mmap window.
+    let mmap_base = uspace.base() + layout.mmap_base_offset + aslr_offset(layout.mmap_random_range);
+
+    // The user stack is divided into two parts:
+    // `ustack_start` -> `ustack_pointer`: It is the stack space that users actually read and write.
+    // `ustack_pointer` -> `ustack_end`: It is the space that contains the arguments, environment variables and auxv passed to the app.
+    //  When the app starts running, the stack pointer points to `ustack_pointer`.
+    let ustack_end =
+        VirtAddr::from_usize(layout.stack_top) - aslr_offset(layout.stack_random_range);
+    let ustack_size = config::USER_STACK_SIZE;
+    let ustack_start = ustack_end - ustack_size;
+    debug!(
+        "Mapping user stack: {:#x?} -> {:#x?}",
+        ustack_start, ustack_end
+    );
+    // Keep the area below the stack free for it to grow.
+    let limit = VirtAddrRange::new(
+        uspace.base(),
+        stack_guard_start(VirtAddrRange::new(ustack_start, ustack_end)),
+    );
+
+    // Dynamically linked programs start in their interpreter, which is
+    // loaded at a free address like a shared library and loads the rest.
+    let entry = match interp {
+        Some((interp, interp_file, interp_info)) => {
+            let (start, end) = segments_span(&interp_info);
+            let base = uspace
+                .find_free_area(mmap_base, end - start, limit)
+                .ok_or(LinuxError::ENOMEM)?;
+            let interp_info = loader::load_elf(&interp_file, base - (start - uspace.base()))?;
+            debug!("Loading interpreter {} at {:#x?}", interp, interp_info.base);
+            map_segments(uspace, &mut vm_attrs, &interp_file, &interp_info)?;
+            elf_info
+                .auxv
+                .insert(loader::AT_BASE, interp_info.base.as_usize());
+            interp_info.entry
+        }
+        None => elf_info.entry,
+    };
+
+    // The vDSO is 64-bit code, which 32-bit programs cannot call.
+    if !compat {
+        let vdso_base = vdso::map(uspace, &mut vm_attrs, mmap_base, limit)?;
+        elf_info
+            .auxv
+            .insert(vdso::AT_SYSINFO_EHDR, vdso_base.as_usize());
+    }
+
+    let (stack_data, ustack_pointer) =
+        loader::init_stack(&args, &envs, &execfn, &elf_info.auxv, ustack_end, compat);
+    let stack_flags = elf_info.stack_flags;
+    uspace.map_alloc(ustack_start, ustack_size, stack_flags, true)?;
+    map_populated(
+        &mut vm_attrs,
+        VirtAddrRange::from_start_size(ustack_start, ustack_size),
+        stack_flags,
+    );
+
+    uspace.write(ustack_pointer, stack_data.as_slice())?;
+    Ok(UserApp {
+        entry,
+        sp: ustack_pointer,
+        break_pos,
+        stack: VirtAddrRange::new(ustack_start, ustack_end),
+        mmap_base,
+        vm_attrs,
+        compat,
+    })
+}
+
+/// Load a user app into a new address space. See [`open_executable`] for
+/// the arguments.
+pub fn load_user_app(
+    name: String,
+    args: Vec<String>,
+    envs: Vec<String>,
+    randomize: bool,
+) -> LinuxResult<(AddrSpace, UserApp)> {
+    let exe = open_executable(name, None, args, envs, randomize)?;
+    let mut uspace = axmm::new_user_aspace(
+        VirtAddr::from_usize(config::USER_SPACE_BASE),
+        config::USER_SPACE_SIZE,
+    )?;
+    let app = load_executable(&mut uspace, exe)?;
+    Ok((uspace, app))
+}
+
+/// Read the first bytes of `file`, enough to tell scripts from other files.
+fn file_head(file: &page_cache::CachedFile) -> AxResult<Vec<u8>> {
+    let mut head = vec![0; SHEBANG_MAX];
+    let len = file.read_at(0, &mut head)?;
+    head.truncate(len);
+    Ok(head)
+}
+
+/// Whether `file` is a script with a `#!` line.
+pub fn is_script(file: &page_cache::CachedFile) -> LinuxResult<bool> {
+    Ok(file_head(file)?.starts_with(b"#!"))
+}
+
+/// Map the loadable segments of the ELF file `file`.
+///
+/// The whole pages of file data are mapped privately from the file and
+/// loaded on demand, and the rest of a segment is zero-filled on demand.
+/// Only the page with the end of the file data, which is followed by zeros
+/// rather than the rest of the file, is populated right away. Segments whose
+/// file offset is not congruent with their address are copied in at once.
+fn map_segments(
+    uspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    file: &Arc<page_cache::CachedFile>,
+    elf_info: &ELFInfo,
+) -> LinuxResult {
+    for segment in elf_info.segments.iter() {
+        debug!(
+            "Mapping ELF segment: [{:#x?}, {:#x?}) flags: {:#x?}",
+            segment.start_vaddr,
+            segment.start_vaddr + segment.size,
+            segment.flags
+        );
+        let range = VirtAddrRange::from_start_size(segment.start_vaddr, segment.size);
+        let data_start = segment.start_vaddr + segment.offset;
+        let data_end = data_start + segment.file_size;
+
+        if segment.file_offset % PAGE_SIZE_4K != segment.offset {
+            uspace.map_alloc(range.start, range.size(), segment.flags, true)?;
+            map_populated(vm_attrs, range, segment.flags);
+            let mut data = vec![0; segment.file_size];
+            file.read_at(segment.file_offset, &mut data)?;
+            uspace.write(data_start, &data)?;
+        } else {
+            // The pages up to `file_end` hold file data only.
+            let file_end = data_end.align_down_4k().max(range.start);
+            let tail_end = data_end.align_up_4k();
+            for (start, end, populate) in [
+                (range.start, file_end, false),
+                (file_end, tail_end, true),
+                (tail_end, range.end, false),
+            ] {
+                if end > start {
+                    uspace.map_alloc(start, end - start, segment.flags, populate)?;
+                }
+            }
+            vm_attrs.mapped(range, segment.flags);
+            if file_end > range.start {
+                vm_attrs.file_maps.insert(
+                    VirtAddrRange::new(range.start, file_end),
+                    FileMapping {
+                        file: file.clone(),
+                        start: range.start,
+                        offset: segment.file_offset - segment.offset,
+                        flags: segment.flags,
+                        shared: false,
+                    },
+                );
+            }
+            if tail_end > file_end {
+                let tail_start = file_end.max(data_start);
+                let mut data = vec![0; data_end - tail_start];
+                file.read_at(segment.file_offset + (tail_start - data_start), &mut data)?;
+                uspace.write(tail_start, &data)?;
+                vm_attrs.page_populated(file_end, false);
+            }
+        }
+
+        if segment.flags.contains(MappingFlags::EXECUTE) {
+            icache::sync_range(uspace, range);
+        }
+    }
+    Ok(())
+}
+
+/// The range of addresses covered by the segments of an ELF file.
+fn segments_span(elf_info: &ELFInfo) -> (VirtAddr, VirtAddr) {
+    let start = elf_info.segments.iter().map(|seg| seg.start_vaddr).min();
+    let end = elf_info
+        .segments
+        .iter()
+        .map(|seg| seg.start_vaddr + seg.size)
+        .max();
+    (
+        start.unwrap_or(VirtAddr::from_usize(0)),
+        end.unwrap_or(VirtAddr::from_usize(0)),
+    )
+}
+
+/// Account for `range` being mapped with `flags` and populated with
+/// anonymous pages.
+fn map_populated(vm_attrs: &mut VmAttrs, range: VirtAddrRange, flags: MappingFlags) {
+    vm_attrs.mapped(range, flags);
+    for vaddr in PageIter4K::new(range.start, range.end).unwrap() {
+        vm_attrs.page_populated(vaddr, false);
+    }
+}
+
+/// Unmap `range` and drop the attributes and the accounting of its pages.
+pub fn unmap_range(
+    aspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    range: VirtAddrRange,
+) -> AxResult {
+    let resident: Vec<_> = PageIter4K::new(range.start, range.end)
+        .unwrap()
+        .filter(|vaddr| is_page_resident(aspace, *vaddr))
+        .collect();
+    zero_page::unmap(aspace, vm_attrs, range);
+    aspace.unmap(range.start, range.size())?;
+    for vaddr in resident {
+        vm_attrs.page_freed(vaddr);
+    }
+    vm_attrs.forget(range);
+    Ok(())
+}
+
+/// Unmap all of the user space, and take its attributes.
+pub fn clear_user_space(aspace: &mut AddrSpace, vm_attrs: &mut VmAttrs) -> VmAttrs {
+    let range = VirtAddrRange::from_start_size(aspace.base(), aspace.size());
+    zero_page::unmap(aspace, vm_attrs, range);
+    aspace.clear();
+    core::mem::replace(vm_attrs, VmAttrs::new())
+}
+
+/// Drop the resident pages in `range`.
+///
+/// The pages stay mapped with their original permissions, but their frames
+/// are freed. The next access faults in a fresh zero-filled page, or the
+/// file contents for file-backed mappings. The data of shared mappings stays
+/// in the page cache, and mappings of kernel memory are left alone.
+pub fn discard_pages(
+    aspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    range: VirtAddrRange,
+) -> AxResult {
+    for vaddr in PageIter4K::new(range.start, range.end).unwrap() {
+        let Ok((_, flags, _)) = aspace.page_table().query(vaddr) else {
+            continue;
+        };
+        if vm_attrs.kernel_maps.get(vaddr).is_some() {
+            continue;
+        }
+        if vm_attrs.shared_pages.contains_key(&vaddr) {
+            // These frames are mapped in an area of their own, which goes
+            // back to being populated on demand.
+            aspace.unmap(vaddr, PAGE_SIZE_4K)?;
+            aspace.map_alloc(vaddr, PAGE_SIZE_4K, flags, false)?;
+        } else if vm_attrs.zero_pages.contains(&vaddr) {
+            // The zero frame is not freed.
+            pte::unmap(aspace, vaddr);
+        } else if let Some(paddr) = pte::unmap(aspace, vaddr) {
+            // Anonymous pages are freed within their area, which is left as
+            // it is.
+            pte::free_frame(paddr);
+        }
+        vm_attrs.page_freed(vaddr);
+        vm_attrs.shared_pages.remove(&vaddr);
+    }
+    vm_attrs.swapped.remove_range(range);
+    Ok(())
+}
+
+/// Fault in the pages in `range` that are not resident yet.
+///
+/// Pages that cannot be populated (e.g. not mapped at all) are skipped.
+pub fn prefault_pages(aspace: &mut AddrSpace, vm_attrs: &mut VmAttrs, range: VirtAddrRange) {
+    for vaddr in PageIter4K::new(range.start, range.end).unwrap() {
+        if aspace.page_table().query(vaddr).is_err() {
+            swap::swap_in(aspace, vm_attrs, vaddr, MappingFlags::READ)
+                .or_else(|| populate_file_page(aspace, vm_attrs, vaddr, MappingFlags::READ))
+                .unwrap_or_else(|| aspace.handle_page_fault(vaddr, MappingFlags::READ));
+        }
+    }
+}
+
+/// Populate the page containing `vaddr` of a file-backed mapping from the
+/// page cache.
+///
+/// Returns `None` if `vaddr` is not in a file-backed mapping or its page is
+/// already resident, so that the fault is left to the other handlers.
+fn populate_file_page(
+    aspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> Option<bool> {
+    let vaddr = vaddr.align_down_4k();
+    let (_, mapping) = vm_attrs.file_maps.get(vaddr)?;
+    if is_page_resident(aspace, vaddr) {
+        // The clean pages of shared mappings are mapped read-only, and the
+        // first write marks them dirty, see `page_cache`.
+        let page = vm_attrs.shared_pages.get(&vaddr)?;
+        if !access_flags.contains(MappingFlags::WRITE)
+            || !mapping.flags.contains(MappingFlags::WRITE)
+        {
+            return None;
+        }
+        page.mark_dirty();
+        return Some(pte::protect(aspace, vaddr, mapping.flags));
+    }
+    if !mapping.flags.contains(access_flags) {
+        return Some(false);
+    }
+    let sequential = matches!(
+        vm_attrs.read_advice.get(vaddr),
+        Some((_, ReadAdvice::Sequential))
+    );
+    let Ok(page) = mapping.file.get_page(mapping.page_index(vaddr), sequential) else {
+        return Some(false);
+    };
+
+    if mapping.shared {
+        // Files without a backing file are never written back, so their
+        // pages need no dirty tracking.
+        let flags = if access_flags.contains(MappingFlags::WRITE) || mapping.file.is_anonymous() {
+            mapping.flags
+        } else {
+            mapping.flags - MappingFlags::WRITE
+        };
+        if aspace.unmap(vaddr, PAGE_SIZE_4K).is_err()
+            || aspace
+                .map_linear(vaddr, page.paddr(), PAGE_SIZE_4K, flags)
+                .is_err()
+        {
+            return Some(false);
+        }
+        if flags.contains(MappingFlags::WRITE) {
+            page.mark_dirty();
+        }
+        if flags.contains(MappingFlags::EXECUTE) {
+            icache::sync_frame(page.paddr());
+        }
+        vm_attrs.shared_pages.insert(vaddr, page);
+        vm_attrs.page_populated(vaddr, true);
+    } else {
+        if !aspace.handle_page_fault(vaddr, access_flags) {
+            return Some(false);
+        }
+        let (paddr, _, _) = aspace.page_table().query(vaddr).unwrap();
+        let frame = unsafe {
+            core::slice::from_raw_parts_mut(phys_to_virt(paddr).as_mut_ptr(), PAGE_SIZE_4K)
+        };
+        page.read_at(0, frame);
+        if mapping.flags.contains(MappingFlags::EXECUTE) {
+            icache::sync_frame(paddr);
+        }
+        vm_attrs.page_populated(vaddr, false);
+    }
+    Some(true)
+}
+
+/// Populate the page containing `vaddr` of an anonymous mapping with a
+/// zero-filled frame.
+///
+/// `AddrSpace` does not populate the pages of areas that were populated when
+/// they were mapped, like the stack, but their pages may have been discarded
+/// since.
+fn populate_anonymous(
+    aspace: &mut AddrSpace,
+    vm_attrs: &VmAttrs,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool {
+    let vaddr = vaddr.align_down_4k();
+    let Some((_, &flags)) = vm_attrs.mapped.get(vaddr) else {
+        return false;
+    };
+    if !flags.contains(access_flags)
+        || is_page_resident(aspace, vaddr)
+        || vm_attrs.kernel_maps.get(vaddr).is_some()
+    {
+        return false;
+    }
+    let Some(paddr) = pte::alloc_frame() else {
+        return false;
+    };
+    if !pte::map(aspace, vaddr, paddr, flags) {
+        pte::free_frame(paddr);
+        return false;
+    }
+    true
+}
+
+/// Handle a page fault in user memory.
+pub fn handle_user_fault(
+    task_ext: &TaskExt,
+    aspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool {
+    // Without hardware updates of the accessed bit, an access to a page
+    // whose bit has been cleared by the swap faults although it is allowed.
+    if let Ok((_, flags, _)) = aspace.page_table().query(vaddr.align_down_4k()) {
+        if flags.contains(access_flags) {
+            return pte::mark_accessed(aspace, vaddr.align_down_4k());
+        }
+    }
+    // Swapped out pages take precedence over the file, as a private file
+    // mapping may have modified them.
+    if let Some(handled) = swap::swap_in(aspace, vm_attrs, vaddr, access_flags) {
+        if handled {
+            vm_attrs.stats.major_faults += 1;
+        }
+        return handled;
+    }
+    let handled = populate_file_page(aspace, vm_attrs, vaddr, access_flags).unwrap_or_else(|| {
+        if zero_page::fault(aspace, vm_attrs, vaddr, access_flags) {
+            return true;
+        }
+        let handled = aspace.handle_page_fault(vaddr, access_flags)
+            || populate_anonymous(aspace, vm_attrs, vaddr, access_flags)
+            || (grow_stack(task_ext, aspace, vm_attrs, vaddr)
+                && aspace.handle_page_fault(vaddr, access_flags));
+        if handled {
+            vm_attrs.page_populated(vaddr.align_down_4k(), false);
+        }
+        handled
+    });
+    if handled {
+        vm_attrs.stats.minor_faults += 1;
+    }
+    handled
+}
+
+/// Whether the page containing `vaddr` is resident in memory.
+pub fn is_page_resident(aspace: &AddrSpace, vaddr: VirtAddr) -> bool {
+    aspace.page_table().query(vaddr.align_down_4k()).is_ok()
+}
+
+/// The gap kept free below the user stack so that it can grow.
+///
+/// Other mappings placed by the kernel never enter the gap, and the stack
+/// never grows closer than this to the mapping below it.
+pub const STACK_GUARD_GAP: usize = 256 * PAGE_SIZE_4K;
+
+/// The lowest address that the kernel may use for other mappings.
+pub fn stack_guard_start(stack: VirtAddrRange) -> VirtAddr {
+    VirtAddr::from(stack.start.as_usize().saturating_sub(STACK_GUARD_GAP))
+}
+
+/// Try to extend the user stack downwards so that it covers `vaddr`.
+///
+/// It fails if the stack would grow beyond `RLIMIT_STACK` or into the guard
+/// gap of the mapping below it. The faulting task is then killed as by
+/// `SIGSEGV`.
+pub fn grow_stack(
+    task_ext: &TaskExt,
+    aspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    vaddr: VirtAddr,
+) -> bool {
+    let mut stack = task_ext.stack.lock();
+    if vaddr >= stack.start {
+        return false;
+    }
+    let stack_limit = task_ext.rlimits.lock().cur(RlimitResource::Stack);
+    let stack_limit = usize::try_from(stack_limit).unwrap_or(usize::MAX);
+    let new_start = vaddr.align_down_4k();
+    if stack.end.as_usize() - new_start.as_usize() > stack_limit {
+        return false;
+    }
+
+    let gap_start = VirtAddr::from(new_start.as_usize().saturating_sub(STACK_GUARD_GAP));
+    if aspace.overlap(VirtAddrRange::new(
+        gap_start.max(aspace.base()),
+        stack.start,
+    )) {
+        return false;
+    }
+    // The stack may be executable, see `PT_GNU_STACK`.
+    let flags = vm_attrs
+        .mapped
+        .get(stack.start)
+        .map(|(_, flags)| *flags)
+        .unwrap_or(MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER);
+    if aspace
+        .map_alloc(new_start, stack.start - new_start, flags, false)
+        .is_err()
+    {
+        return false;
+    }
+    vm_attrs.mapped(VirtAddrRange::new(new_start, stack.start), flags);
+    debug!("Grow user stack: {:#x?} -> {:#x?}", stack.start, new_start);
+    stack.start = new_start;
+    true
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FaultOutcome {
+    Handled,
+    /// The fault failed because the frames ran out.
+    OutOfMemory,
+    /// The access is not allowed.
+    Invalid,
+}
+
+/// Handle a fault of the process of `task_ext`, reclaiming memory if needed.
+fn fault_in(task_ext: &TaskExt, vaddr: VirtAddr, access_flags: MappingFlags) -> FaultOutcome {
+    let mut aspace = task_ext.aspace.lock();
+    let mut vm_attrs = task_ext.vm_attrs.lock();
+    let handle = |aspace: &mut AddrSpace, vm_attrs: &mut VmAttrs| {
+        handle_user_fault(task_ext, aspace, vm_attrs, vaddr, access_flags)
+    };
+    // The fault may have failed because frames ran out. Give back the unused
+    // page cache pages, then swap out anonymous pages, and retry.
+    if handle(&mut aspace, &mut vm_attrs)
+        || (page_cache::shrink() > 0 && handle(&mut aspace, &mut vm_attrs))
+        || (swap::reclaim(&mut aspace, &mut vm_attrs, swap::SWAP_CLUSTER) > 0
+            && handle(&mut aspace, &mut vm_attrs))
+    {
+        FaultOutcome::Handled
+    } else if oom::frames_exhausted()
+        && aspace.overlap(VirtAddrRange::from_start_size(
+            vaddr.align_down_4k(),
+            PAGE_SIZE_4K,
+        ))
+    {
+        FaultOutcome::OutOfMemory
+    } else {
+        FaultOutcome::Invalid
+    }
+}
+
+/// Access the user page at `vaddr` of the process of `task_ext`, which need
+/// not be the current one, faulting it in if needed.
+///
+/// `f` is called with the frame of the page while the address space is
+/// locked, so it must not touch user memory. Returns `None` if the page does
+/// not allow `access_flags`, or if it cannot be faulted in even after the OOM
+/// killer has run.
+pub fn with_user_page<R>(
+    task_ext: &TaskExt,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+    f: impl FnOnce(&mut [u8]) -> R,
+) -> Option<R> {
+    let vaddr = vaddr.align_down_4k();
+    let access_flags = access_flags | MappingFlags::USER;
+    let accessible = |aspace: &AddrSpace| match aspace.page_table().query(vaddr) {
+        Ok((paddr, flags, _)) if flags.contains(access_flags) => Some(paddr),
+        _ => None,
+    };
+    if accessible(&task_ext.aspace.lock()).is_none() {
+        let mut outcome = fault_in(task_ext, vaddr, access_flags);
+        if outcome == FaultOutcome::OutOfMemory && oom::out_of_memory() {
+            outcome = fault_in(task_ext, vaddr, access_flags);
+        }
+        if outcome != FaultOutcome::Handled {
+            return None;
+        }
+    }
+    // The page may have been swapped out again in between.
+    let aspace = task_ext.aspace.lock();
+    let paddr = accessible(&aspace)?;
+    let frame =
+        unsafe { core::slice::from_raw_parts_mut(phys_to_virt(paddr).as_mut_ptr(), PAGE_SIZE_4K) };
+    let result = f(frame);
+    if access_flags.contains(MappingFlags::WRITE) {
+        // The write did not go through the page table, so neither the dirty
+        // state of a page cache page nor the instruction cache follow it.
+        if let Some(page) = task_ext.vm_attrs.lock().shared_pages.get(&vaddr) {
+            page.mark_dirty();
+        }
+        let (_, flags, _) = aspace.page_table().query(vaddr).unwrap();
+        if flags.contains(MappingFlags::EXECUTE) {
+            icache::sync_frame(paddr);
+        }
+    }
+    Some(result)
+}
+
+#[register_trap_handler(PAGE_FAULT)]
+fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool) -> bool {
+    // The kernel also faults on user pages that have been swapped out or not
+    // populated yet when a system call accesses them.
+    let user_space = VirtAddrRange::from_start_size(
+        VirtAddr::from_usize(config::USER_SPACE_BASE),
+        config::USER_SPACE_SIZE,
+    );
+    if is_user || user_space.contains(vaddr) {
+        let curr = axtask::current();
+        let task_ext = curr.task_ext();
+        let mut outcome = fault_in(task_ext, vaddr, access_flags);
+        if outcome == FaultOutcome::OutOfMemory && oom::out_of_memory() {
+            outcome = fault_in(task_ext, vaddr, access_flags);
+        }
+        if task_ext.is_killed() {
+            warn!("{}: killed by the OOM killer, exit!", curr.id_name());
+            task::exit(oom::OOM_KILL_EXIT_CODE);
+        }
+        match outcome {
+            FaultOutcome::Handled => true,
+            _ if is_user => {
+                warn!(
+                    "{}: segmentation fault at {:#x}, exit!",
+                    curr.id_name(),
+                    vaddr
+                );
+                task::exit(task::SIGSEGV_EXIT_CODE);
+            }
+            // A system call ran out of memory in the middle of copying, and
+            // no process could be killed to make room.
+            FaultOutcome::OutOfMemory => {
+                warn!("{}: out of memory, exit!", curr.id_name());
+                task::exit(oom::OOM_KILL_EXIT_CODE);
+            }
+            // System calls check user pointers with `uaccess` and fail with
+            // `EFAULT`, so this is an unchecked access, a bug of the kernel.
+            FaultOutcome::Invalid => false,
+        }
+    } else {
+        false
+    }
+}
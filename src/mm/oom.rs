@@ -0,0 +1,173 @@
+//! The out-of-memory killer.
+//!
+//! When a fault cannot be satisfied even after the page cache has been shrunk
+//! and anonymous pages have been swapped out, a victim process is chosen by
+//! its badness, which grows with the memory it uses and is adjusted by its
+//! `oom_score_adj`. The victim is killed and its memory is freed at once, so
+//! that the allocation can be retried.
+//!
+//! The victim exits the next time it runs in the kernel. Victims asleep in
+//! [`killable_sleep`] or waiting for a child in [`killable_wait`] are woken
+//! for that. Reads and writes of pipes block within `arceos_posix_api`, so
+//! victims blocked on a pipe exit when the call returns, but their memory is
+//! gone already. There are no futexes yet.
+//!
+//! The memory of the victim is freed by the task that ran out of memory,
+//! while the victim is not running: the kernel runs on a single CPU
+//! (`smp = 1`), as it has no TLB shootdown, for this or for any other change
+//! of the page tables. The victim loads its page tables anew when it is
+//! switched back in, finds them empty and exits.
+use core::time::Duration;
+
+use axtask::{AxTaskRef, TaskExtRef, WaitQueue};
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::task::{self, TaskExt};
+
+/// The lowest `oom_score_adj`, which exempts a process from the OOM killer.
+pub const OOM_SCORE_ADJ_MIN: i32 = -1000;
+/// The highest `oom_score_adj`.
+pub const OOM_SCORE_ADJ_MAX: i32 = 1000;
+
+/// The exit code of a process killed by the OOM killer.
+///
+/// There are no signals yet, so the process exits with the code a shell
+/// reports for `SIGKILL`.
+pub const OOM_KILL_EXIT_CODE: i32 = 128 + 9;
+
+/// The tasks in [`killable_sleep`] and [`killable_wait`], woken when a
+/// process is killed or exits.
+static KILLABLE_SLEEPERS: WaitQueue = WaitQueue::new();
+
+/// Sleep for `dur`, or until the process of the current task is killed.
+/// Returns whether the process has been killed.
+pub fn killable_sleep(dur: Duration) -> bool {
+    let curr = axtask::current();
+    let task_ext = curr.task_ext();
+    KILLABLE_SLEEPERS.wait_timeout_until(dur, || task_ext.is_killed());
+    task_ext.is_killed()
+}
+
+/// Wait until `condition` holds, or until the process of the current task is
+/// killed. Returns whether the process has been killed.
+///
+/// The waiters are woken when a process exits, so `condition` must only
+/// change when one does.
+pub fn killable_wait(condition: impl Fn() -> bool) -> bool {
+    let curr = axtask::current();
+    let task_ext = curr.task_ext();
+    KILLABLE_SLEEPERS.wait_until(|| condition() || task_ext.is_killed());
+    task_ext.is_killed()
+}
+
+/// Wake the tasks in [`killable_wait`] after a process has exited.
+pub fn wake_killable() {
+    KILLABLE_SLEEPERS.notify_all(true);
+}
+
+/// Whether the physical frames have run out.
+pub fn frames_exhausted() -> bool {
+    axalloc::global_allocator().available_pages() == 0
+}
+
+/// The number of physical pages that can be used by processes.
+fn total_pages() -> usize {
+    let allocator = axalloc::global_allocator();
+    allocator.used_pages() + allocator.available_pages()
+}
+
+/// The memory used by a process, in pages.
+struct MemoryUsage {
+    rss: usize,
+    swapents: usize,
+}
+
+/// Get the memory used by a process, waiting for it if it is in use.
+fn memory_usage(task_ext: &TaskExt) -> MemoryUsage {
+    let vm_attrs = task_ext.vm_attrs.lock();
+    MemoryUsage {
+        rss: vm_attrs.stats.rss(),
+        swapents: vm_attrs.swapped.count(),
+    }
+}
+
+/// The badness of a process with `usage`, or `None` if it must not be
+/// killed.
+fn badness_of(task_ext: &TaskExt, usage: &MemoryUsage) -> Option<usize> {
+    let adj = task_ext.oom_score_adj();
+    if adj == OOM_SCORE_ADJ_MIN {
+        return None;
+    }
+    // Each point of `oom_score_adj` counts as a thousandth of the memory.
+    let points =
+        (usage.rss + usage.swapents) as isize + adj as isize * (total_pages() / 1000) as isize;
+    Some(points.max(1) as usize)
+}
+
+/// The `oom_score` of a process: its badness normalized to `0..=1000`.
+pub fn oom_score(task_ext: &TaskExt) -> usize {
+    badness_of(task_ext, &memory_usage(task_ext))
+        .map_or(0, |points| (points * 1000 / total_pages().max(1)).min(1000))
+}
+
+/// Kill the process with the highest badness and free its memory.
+///
+/// Returns whether any memory was freed. The caller must not hold the memory
+/// locks of any process.
+pub fn out_of_memory() -> bool {
+    error!("Out of memory: {} pages in total", total_pages());
+    error!("[  pid  ]   rss_kB swap_kB oom_score_adj name");
+    let mut victim: Option<(AxTaskRef, usize, MemoryUsage)> = None;
+    for task in task::processes() {
+        let task_ext = task.task_ext();
+        if task_ext.is_killed() {
+            continue;
+        }
+        let usage = memory_usage(task_ext);
+        error!(
+            "[{:>7}] {:>8} {:>7} {:>13} {}",
+            task_ext.proc_id,
+            usage.rss * PAGE_SIZE_4K / 1024,
+            usage.swapents * PAGE_SIZE_4K / 1024,
+            task_ext.oom_score_adj(),
+            task.id_name(),
+        );
+        let Some(points) = badness_of(task_ext, &usage) else {
+            continue;
+        };
+        if victim
+            .as_ref()
+            .is_none_or(|(_, victim_points, _)| points > *victim_points)
+        {
+            victim = Some((task, points, usage));
+        }
+    }
+
+    let Some((victim, _, usage)) = victim else {
+        error!("Out of memory and no killable processes");
+        return false;
+    };
+    let victim_ext = victim.task_ext();
+    victim_ext.kill();
+    KILLABLE_SLEEPERS.notify_all(true);
+    error!(
+        "Out of memory: killed process {} ({}) anon-rss:{}kB swap:{}kB oom_score_adj:{}",
+        victim_ext.proc_id,
+        victim.id_name(),
+        usage.rss * PAGE_SIZE_4K / 1024,
+        usage.swapents * PAGE_SIZE_4K / 1024,
+        victim_ext.oom_score_adj(),
+    );
+
+    // Free the memory right away. The victim faults on its next access to
+    // user memory and exits. Its memory may be in use by a fault or a system
+    // call, which does not wait for anything while holding the locks, so
+    // waiting for them does not deadlock as the caller holds none.
+    let old_attrs = {
+        let mut aspace = victim_ext.aspace.lock();
+        let mut vm_attrs = victim_ext.vm_attrs.lock();
+        super::clear_user_space(&mut aspace, &mut vm_attrs)
+    };
+    drop(old_attrs);
+    true
+}
@@ -0,0 +1,664 @@
+//! The page cache.
+//!
+//! File data is cached in page-sized frames keyed by the file and the page
+//! index. `read`, `write`, file-backed `mmap` and the ELF loader all go
+//! through the same pages, so a file is read from the disk only once while
+//! it is open or mapped, and shared mappings see the data written by `write`.
+//! A cached file reads and writes its backing file through a handle of its
+//! own, so it stays the same file if the path is unlinked and reused. It goes
+//! away with its last file descriptor and mapping.
+//!
+//! Dirty pages are written back on `fsync`, when memory runs short and when
+//! the file goes away. The clean pages of shared writable mappings are mapped
+//! read-only, and the first write to one faults, marks it dirty and makes it
+//! writable. Writing a page back clears its dirty flag first and then
+//! write-protects it in all processes, so that a write after the data has
+//! been copied out faults and marks the page dirty again.
+//!
+//! Files without a backing file (e.g. shared memory segments, memfds and the
+//! files in [`TMPFS_DIR`]) keep their data only in the cache. The files in
+//! [`INITRD_DIR`] are the apps built into the kernel image, which can be run
+//! without any disk.
+use alloc::{
+    collections::btree_map::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::{AxError, AxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use axstd::fs::{File, OpenOptions};
+use axstd::io::{Read, Seek, SeekFrom, Write};
+use axsync::Mutex;
+use axtask::TaskExtRef;
+use memory_addr::{PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use super::{pte, VmAttrs};
+use crate::vfs::absolute_path;
+
+/// The number of pages read ahead once sequential access is detected.
+const READAHEAD_PAGES: usize = 8;
+
+/// A page of file data.
+pub struct CachedPage {
+    ptr: NonNull<u8>,
+    dirty: AtomicBool,
+}
+
+unsafe impl Send for CachedPage {}
+unsafe impl Sync for CachedPage {}
+
+impl CachedPage {
+    const LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(PAGE_SIZE_4K, PAGE_SIZE_4K) };
+
+    /// Allocate a zero-filled page.
+    fn new() -> AxResult<Self> {
+        let ptr = unsafe { alloc::alloc::alloc_zeroed(Self::LAYOUT) };
+        NonNull::new(ptr)
+            .map(|ptr| Self {
+                ptr,
+                dirty: AtomicBool::new(false),
+            })
+            .ok_or(AxError::NoMemory)
+    }
+
+    /// The physical address of the page frame.
+    pub fn paddr(&self) -> PhysAddr {
+        axhal::mem::virt_to_phys(VirtAddr::from(self.ptr.as_ptr() as usize))
+    }
+
+    /// Copy data out of the page, starting at `offset` within the page.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) {
+        assert!(offset + buf.len() <= PAGE_SIZE_4K);
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                self.ptr.as_ptr().add(offset),
+                buf.as_mut_ptr(),
+                buf.len(),
+            )
+        };
+    }
+
+    /// Copy data into the page, starting at `offset` within the page.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) {
+        assert!(offset + buf.len() <= PAGE_SIZE_4K);
+        unsafe {
+            core::ptr::copy_nonoverlapping(buf.as_ptr(), self.ptr.as_ptr().add(offset), buf.len())
+        };
+    }
+
+    /// Mark the page as modified, so that it is written back to the file.
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::AcqRel)
+    }
+
+    /// Whether the page has been modified since it was last written back.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for CachedPage {
+    fn drop(&mut self) {
+        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), Self::LAYOUT) };
+    }
+}
+
+bitflags::bitflags! {
+    /// Seals restricting the changes to a file, set with `F_ADD_SEALS`.
+    ///
+    /// See <https://github.com/torvalds/linux/blob/master/include/uapi/linux/fcntl.h>
+    #[derive(Clone, Copy, Debug)]
+    pub struct Seals: u32 {
+        /// Prevent further seals from being set.
+        const SEAL = 0x0001;
+        /// Prevent the file from shrinking.
+        const SHRINK = 0x0002;
+        /// Prevent the file from growing.
+        const GROW = 0x0004;
+        /// Prevent writes.
+        const WRITE = 0x0008;
+        /// Prevent future writes while mapped.
+        const FUTURE_WRITE = 0x0010;
+    }
+}
+
+struct CachedFileInner {
+    /// The cached pages, by page index.
+    pages: BTreeMap<usize, Arc<CachedPage>>,
+    /// The size of the file in bytes.
+    size: usize,
+    /// The index of the last page returned by `read_at`.
+    last_read: Option<usize>,
+    /// The seals of a file without a backing file.
+    seals: Seals,
+}
+
+/// Where the data of a cached file comes from.
+enum Backing {
+    /// Nowhere: the data lives only in memory.
+    Memory,
+    /// The backing file, through a handle that stays open as long as the
+    /// cached file exists.
+    File(Mutex<File>),
+    /// Data in the kernel image, which never changes.
+    Image(&'static [u8]),
+}
+
+impl Backing {
+    /// Write `pages`, whose dirty flags have been taken, back to the backing
+    /// file of `size` bytes. The pages that could not be written are marked
+    /// dirty again.
+    fn write_pages(&self, size: usize, pages: Vec<(usize, Arc<CachedPage>)>) -> AxResult {
+        let Backing::File(file) = self else {
+            return Ok(());
+        };
+        let mut file = file.lock();
+        let mut pages = pages.into_iter();
+        while let Some((index, page)) = pages.next() {
+            let offset = index * PAGE_SIZE_4K;
+            if offset >= size {
+                continue;
+            }
+            let len = (size - offset).min(PAGE_SIZE_4K);
+            let mut buf = [0; PAGE_SIZE_4K];
+            page.read_at(0, &mut buf[..len]);
+            let written = file
+                .seek(SeekFrom::Start(offset as u64))
+                .and_then(|_| file.write_all(&buf[..len]));
+            if let Err(e) = written {
+                page.mark_dirty();
+                pages.for_each(|(_, page)| page.mark_dirty());
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Take the dirty flags of the `pages` that satisfy `filter`. Returns the
+/// pages that were dirty.
+fn take_dirty_pages(
+    pages: &BTreeMap<usize, Arc<CachedPage>>,
+    filter: impl Fn(&Arc<CachedPage>) -> bool,
+) -> Vec<(usize, Arc<CachedPage>)> {
+    pages
+        .iter()
+        .filter(|(_, page)| filter(page) && page.take_dirty())
+        .map(|(&index, page)| (index, page.clone()))
+        .collect()
+}
+
+/// A file whose data is cached in memory.
+pub struct CachedFile {
+    backing: Backing,
+    inner: Mutex<CachedFileInner>,
+}
+
+impl CachedFile {
+    fn new(backing: Backing, size: usize, seals: Seals) -> Self {
+        Self {
+            backing,
+            inner: Mutex::new(CachedFileInner {
+                pages: BTreeMap::new(),
+                size,
+                last_read: None,
+                seals,
+            }),
+        }
+    }
+
+    fn open(path: &str) -> AxResult<Self> {
+        // The file may be read-only, in which case it is never written back.
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .or_else(|_| File::open(path))?;
+        let size = file.metadata()?.len() as usize;
+        Ok(Self::new(
+            Backing::File(Mutex::new(file)),
+            size,
+            Seals::empty(),
+        ))
+    }
+
+    /// Create a read-only file with the data of the kernel image at `data`.
+    /// The file is sealed against writes and resizing.
+    fn new_image(data: &'static [u8]) -> Self {
+        let seals = Seals::SEAL | Seals::SHRINK | Seals::GROW | Seals::WRITE;
+        Self::new(Backing::Image(data), data.len(), seals)
+    }
+
+    /// Create a zero-filled file of `size` bytes that has no backing file.
+    pub fn new_anonymous(size: usize, seals: Seals) -> Self {
+        Self::new(Backing::Memory, size, seals)
+    }
+
+    /// Whether the file has no backing file. Only such files can be sealed.
+    pub fn is_anonymous(&self) -> bool {
+        matches!(self.backing, Backing::Memory)
+    }
+
+    /// Whether the file is in the kernel image and cannot be changed.
+    pub fn is_image(&self) -> bool {
+        matches!(self.backing, Backing::Image(_))
+    }
+
+    /// The seals of the file.
+    pub fn seals(&self) -> Seals {
+        self.inner.lock().seals
+    }
+
+    /// Add `seals` to the file. Returns false if the file is sealed against
+    /// new seals.
+    pub fn add_seals(&self, seals: Seals) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.seals.contains(Seals::SEAL) {
+            return false;
+        }
+        inner.seals |= seals;
+        true
+    }
+
+    /// The size of the file in bytes.
+    pub fn size(&self) -> usize {
+        self.inner.lock().size
+    }
+
+    /// Get the page at `index`, reading it from the file if it is not cached.
+    ///
+    /// If `sequential` is true, the following pages are read ahead.
+    pub fn get_page(&self, index: usize, sequential: bool) -> AxResult<Arc<CachedPage>> {
+        let mut inner = self.inner.lock();
+        let page = self.page_locked(&mut inner, index)?;
+        if sequential {
+            self.readahead(&mut inner, index + 1);
+        }
+        Ok(page)
+    }
+
+    /// Read data starting at `offset`. Returns the number of bytes read.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> AxResult<usize> {
+        let mut inner = self.inner.lock();
+        let end = inner.size.min(offset.saturating_add(buf.len()));
+        if offset >= end {
+            return Ok(0);
+        }
+
+        let first_page = offset / PAGE_SIZE_4K;
+        let last_page = (end - 1) / PAGE_SIZE_4K;
+        let sequential = inner
+            .last_read
+            .is_some_and(|last| first_page == last || first_page == last + 1);
+
+        let mut pos = offset;
+        while pos < end {
+            let page = self.page_locked(&mut inner, pos / PAGE_SIZE_4K)?;
+            let page_offset = pos % PAGE_SIZE_4K;
+            let len = (PAGE_SIZE_4K - page_offset).min(end - pos);
+            page.read_at(page_offset, &mut buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+
+        inner.last_read = Some(last_page);
+        if sequential {
+            self.readahead(&mut inner, last_page + 1);
+        }
+        Ok(end - offset)
+    }
+
+    /// Read the whole file.
+    pub fn read_to_vec(&self) -> AxResult<Vec<u8>> {
+        let mut data = vec![0; self.size()];
+        let len = self.read_at(0, &mut data)?;
+        data.truncate(len);
+        Ok(data)
+    }
+
+    /// Write data starting at `offset`, extending the file if needed.
+    /// Returns the number of bytes written.
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> AxResult<usize> {
+        let mut inner = self.inner.lock();
+        let end = offset.checked_add(buf.len()).ok_or(AxError::InvalidInput)?;
+
+        let mut pos = offset;
+        while pos < end {
+            let page = self.page_locked(&mut inner, pos / PAGE_SIZE_4K)?;
+            let page_offset = pos % PAGE_SIZE_4K;
+            let len = (PAGE_SIZE_4K - page_offset).min(end - pos);
+            page.write_at(page_offset, &buf[pos - offset..pos - offset + len]);
+            page.mark_dirty();
+            pos += len;
+        }
+
+        inner.size = inner.size.max(end);
+        Ok(buf.len())
+    }
+
+    /// Set the size of the file, dropping the data beyond `size`.
+    pub fn truncate(&self, size: usize) -> AxResult {
+        let mut inner = self.inner.lock();
+        if let Backing::File(file) = &self.backing {
+            file.lock().set_len(size as u64)?;
+        }
+        let first_dropped = size.div_ceil(PAGE_SIZE_4K);
+        inner.pages.split_off(&first_dropped);
+        if size % PAGE_SIZE_4K != 0 {
+            if let Some(page) = inner.pages.get(&(size / PAGE_SIZE_4K)) {
+                let tail = size % PAGE_SIZE_4K;
+                page.write_at(tail, &[0; PAGE_SIZE_4K][tail..]);
+            }
+        }
+        inner.size = size;
+        inner.last_read = None;
+        Ok(())
+    }
+
+    /// Write all dirty pages back to the file.
+    ///
+    /// The caller must not hold the memory locks of any process.
+    pub fn sync(&self) -> AxResult {
+        if !matches!(self.backing, Backing::File(_)) {
+            return Ok(());
+        }
+        let dirty = take_dirty_pages(&self.inner.lock().pages, |_| true);
+        self.write_protect_mappings();
+        let inner = self.inner.lock();
+        self.backing.write_pages(inner.size, dirty)
+    }
+
+    /// Write-protect the pages of the file in the shared writable mappings
+    /// of all processes, so that the next write to each marks it dirty.
+    fn write_protect_mappings(&self) {
+        for task in crate::task::processes() {
+            let task_ext = task.task_ext();
+            let mut aspace = task_ext.aspace.lock();
+            let vm_attrs = task_ext.vm_attrs.lock();
+            for (range, mapping) in vm_attrs.file_maps.iter() {
+                if !mapping.shared
+                    || !mapping.flags.contains(MappingFlags::WRITE)
+                    || !core::ptr::eq(Arc::as_ptr(&mapping.file), self)
+                {
+                    continue;
+                }
+                for (&vaddr, _) in vm_attrs.shared_pages.range(range.start..range.end) {
+                    pte::protect(&mut aspace, vaddr, mapping.flags - MappingFlags::WRITE);
+                }
+            }
+        }
+    }
+
+    /// Whether any process maps the file shared and writable.
+    pub fn has_writable_mappings(&self) -> bool {
+        crate::task::processes().iter().any(|task| {
+            task.task_ext()
+                .vm_attrs
+                .lock()
+                .file_maps
+                .iter()
+                .any(|(_, mapping)| {
+                    mapping.shared
+                        && mapping.flags.contains(MappingFlags::WRITE)
+                        && core::ptr::eq(Arc::as_ptr(&mapping.file), self)
+                })
+        })
+    }
+
+    fn page_locked(&self, inner: &mut CachedFileInner, index: usize) -> AxResult<Arc<CachedPage>> {
+        if let Some(page) = inner.pages.get(&index) {
+            return Ok(page.clone());
+        }
+        let page = Arc::new(CachedPage::new()?);
+        let offset = index * PAGE_SIZE_4K;
+        let len = inner.size.saturating_sub(offset).min(PAGE_SIZE_4K);
+        match &self.backing {
+            _ if len == 0 => {}
+            Backing::Memory => {}
+            Backing::File(file) => {
+                let mut buf = [0; PAGE_SIZE_4K];
+                let mut file = file.lock();
+                file.seek(SeekFrom::Start(offset as u64))?;
+                let mut read = 0;
+                while read < len {
+                    match file.read(&mut buf[read..len])? {
+                        0 => break,
+                        n => read += n,
+                    }
+                }
+                page.write_at(0, &buf[..read]);
+            }
+            Backing::Image(data) => page.write_at(0, &data[offset..offset + len]),
+        }
+        inner.pages.insert(index, page.clone());
+        Ok(page)
+    }
+
+    fn readahead(&self, inner: &mut CachedFileInner, start: usize) {
+        let end = inner
+            .size
+            .div_ceil(PAGE_SIZE_4K)
+            .min(start + READAHEAD_PAGES);
+        for index in start..end {
+            if self.page_locked(inner, index).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Write back and drop the pages that are not mapped anywhere.
+    /// Returns the number of pages dropped.
+    fn shrink(&self) -> usize {
+        if self.is_anonymous() {
+            return 0;
+        }
+        let mut inner = self.inner.lock();
+        let unused = |page: &Arc<CachedPage>| Arc::strong_count(page) == 1;
+        let dirty = take_dirty_pages(&inner.pages, unused);
+        if self.backing.write_pages(inner.size, dirty).is_err() {
+            return 0;
+        }
+        let count = inner.pages.len();
+        inner
+            .pages
+            .retain(|_, page| page.is_dirty() || !unused(page));
+        count - inner.pages.len()
+    }
+}
+
+impl Drop for CachedFile {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut();
+        let dirty = take_dirty_pages(&inner.pages, |_| true);
+        if let Err(e) = self.backing.write_pages(inner.size, dirty) {
+            warn!("Failed to write back a closed file: {:?}", e);
+        }
+    }
+}
+
+/// Take the write permission back from the clean pages of shared mappings
+/// in `range`, e.g. after `mprotect` has granted it to the whole range, so
+/// that the next write to each marks it dirty.
+pub fn write_protect_clean(aspace: &mut AddrSpace, vm_attrs: &VmAttrs, range: VirtAddrRange) {
+    for (&vaddr, page) in vm_attrs.shared_pages.range(range.start..range.end) {
+        let Some((_, mapping)) = vm_attrs.file_maps.get(vaddr) else {
+            continue;
+        };
+        if !mapping.file.is_anonymous() && !page.is_dirty() {
+            pte::protect(aspace, vaddr, mapping.flags - MappingFlags::WRITE);
+        }
+    }
+}
+
+/// The cached files that are open or mapped, by the absolute path they were
+/// opened with. Entries of files that have gone away are dropped lazily.
+static CACHED_FILES: Mutex<BTreeMap<String, Weak<CachedFile>>> = Mutex::new(BTreeMap::new());
+
+/// The files in [`TMPFS_DIR`], which exist until they are removed.
+static TMPFS_FILES: Mutex<BTreeMap<String, Arc<CachedFile>>> = Mutex::new(BTreeMap::new());
+
+/// Get the cached file at `path`, creating the cache entry if needed.
+pub fn open(path: &str) -> AxResult<Arc<CachedFile>> {
+    if in_tmpfs(path) {
+        return open_tmpfs(path, false, false);
+    }
+    let path = absolute_path(path)?;
+    let mut files = CACHED_FILES.lock();
+    if let Some(file) = files.get(&path).and_then(Weak::upgrade) {
+        return Ok(file);
+    }
+    let file = match initrd_app(&path) {
+        Some(name) => {
+            let data = crate::loader::get_app_data_by_name(name).ok_or(AxError::NotFound)?;
+            CachedFile::new_image(data)
+        }
+        None => CachedFile::open(&path)?,
+    };
+    let file = Arc::new(file);
+    files.retain(|_, file| file.strong_count() > 0);
+    files.insert(path, Arc::downgrade(&file));
+    Ok(file)
+}
+
+/// Get the cached file at the normalized absolute `path` if it is open or
+/// mapped.
+pub fn lookup(path: &str) -> Option<Arc<CachedFile>> {
+    CACHED_FILES.lock().get(path).and_then(Weak::upgrade)
+}
+
+/// The read-only directory with the apps built into the kernel image.
+pub const INITRD_DIR: &str = "/initrd/bin";
+
+/// The app name in the normalized absolute `path`, if it is in
+/// [`INITRD_DIR`].
+fn initrd_app(path: &str) -> Option<&str> {
+    path.strip_prefix(INITRD_DIR)?
+        .strip_prefix('/')
+        .filter(|name| !name.is_empty() && !name.contains('/'))
+}
+
+/// The names of the files in [`INITRD_DIR`].
+pub fn initrd_names() -> Vec<String> {
+    (0..crate::loader::get_app_count())
+        .map(|i| crate::loader::get_app_name(i).into())
+        .collect()
+}
+
+/// Whether `path` names a file in [`INITRD_DIR`].
+pub fn in_initrd(path: &str) -> bool {
+    absolute_path(path).is_ok_and(|path| initrd_app(&path).is_some())
+}
+
+/// The directory whose files have no backing file, like a tmpfs.
+pub const TMPFS_DIR: &str = "/dev/shm";
+
+/// Whether `path` names a file in [`TMPFS_DIR`].
+pub fn in_tmpfs(path: &str) -> bool {
+    absolute_path(path).is_ok_and(|path| {
+        path.strip_prefix(TMPFS_DIR)
+            .is_some_and(|name| name.len() > 1 && name.starts_with('/'))
+    })
+}
+
+/// Get the file at `path` in [`TMPFS_DIR`]. If it does not exist and `create`
+/// is true, an empty file is created.
+pub fn open_tmpfs(path: &str, create: bool, exclusive: bool) -> AxResult<Arc<CachedFile>> {
+    let path = absolute_path(path)?;
+    let mut files = TMPFS_FILES.lock();
+    match files.get(&path) {
+        Some(_) if exclusive => Err(AxError::AlreadyExists),
+        Some(file) => Ok(file.clone()),
+        None if create => {
+            let file = Arc::new(CachedFile::new_anonymous(0, Seals::SEAL));
+            files.insert(path, file.clone());
+            Ok(file)
+        }
+        None => Err(AxError::NotFound),
+    }
+}
+
+/// The names of the files in [`TMPFS_DIR`].
+pub fn tmpfs_names() -> Vec<String> {
+    TMPFS_FILES
+        .lock()
+        .keys()
+        .filter_map(|path| Some(path.strip_prefix(TMPFS_DIR)?.strip_prefix('/')?.into()))
+        .collect()
+}
+
+/// Drop the cache entry of `path`, e.g. after the file has been removed, so
+/// that a new file at `path` gets an entry of its own. Files that are still
+/// open or mapped keep their data. Returns whether there was an entry.
+pub fn remove(path: &str) -> bool {
+    let Ok(path) = absolute_path(path) else {
+        return false;
+    };
+    let tmpfs = TMPFS_FILES.lock().remove(&path);
+    let cached = CACHED_FILES.lock().remove(&path);
+    tmpfs.is_some() || cached.is_some_and(|file| file.strong_count() > 0)
+}
+
+/// Get the cached files that are open or mapped, with their paths.
+fn cached_files() -> Vec<(String, Arc<CachedFile>)> {
+    CACHED_FILES
+        .lock()
+        .iter()
+        .filter_map(|(path, file)| Some((path.clone(), file.upgrade()?)))
+        .collect()
+}
+
+/// Write all dirty pages back to their files.
+///
+/// The caller must not hold the memory locks of any process.
+pub fn sync_all() {
+    for (path, file) in cached_files() {
+        if let Err(e) = file.sync() {
+            warn!("Failed to write back {}: {:?}", path, e);
+        }
+    }
+}
+
+/// Give back the memory of the cached pages that are not mapped anywhere.
+/// Returns the number of pages freed.
+pub fn shrink() -> usize {
+    let freed = cached_files().iter().map(|(_, file)| file.shrink()).sum();
+    debug!("Page cache: {} pages freed", freed);
+    freed
+}
+
+/// A cached file opened through a file descriptor.
+#[derive(Clone)]
+pub struct OpenFile {
+    /// The cached file.
+    pub file: Arc<CachedFile>,
+    /// Whether every write appends to the end of the file (`O_APPEND`).
+    pub append: bool,
+}
+
+/// The cached files opened through file descriptors.
+static OPEN_FILES: Mutex<BTreeMap<i32, OpenFile>> = Mutex::new(BTreeMap::new());
+
+/// Route the reads and writes of `fd` through the page cache.
+pub fn bind_fd(fd: i32, file: OpenFile) {
+    OPEN_FILES.lock().insert(fd, file);
+}
+
+/// Stop routing the reads and writes of `fd` through the page cache.
+pub fn unbind_fd(fd: i32) {
+    OPEN_FILES.lock().remove(&fd);
+}
+
+/// Get the cached file opened as `fd`, if any.
+pub fn fd_file(fd: i32) -> Option<OpenFile> {
+    OPEN_FILES.lock().get(&fd).cloned()
+}
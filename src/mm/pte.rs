@@ -0,0 +1,210 @@
+//! Changes to the entries of single pages.
+//!
+//! `AddrSpace` splits an area whenever part of it is unmapped or mapped
+//! again, so unmapping and remapping single pages leaves one area per page.
+//! The functions here change the page table entries of single pages within
+//! the area they are in instead.
+//!
+//! The frames of an area of anonymous memory belong to it and are freed when
+//! it is unmapped. Frames that belong to something else and are only mapped
+//! there must be unmapped with [`unmap`] before the area goes away.
+//!
+//! The accessed bits of the entries are read and cleared directly, to find
+//! the pages that have not been used lately.
+use axhal::{
+    mem::{phys_to_virt, virt_to_phys},
+    paging::{MappingFlags, PageSize},
+};
+use axmm::AddrSpace;
+use core::sync::atomic::{AtomicU64, Ordering};
+use memory_addr::{PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+/// Allocate a zero-filled frame for an anonymous page, as the areas of
+/// `AddrSpace` do.
+pub fn alloc_frame() -> Option<PhysAddr> {
+    let vaddr = axalloc::global_allocator()
+        .alloc_pages(1, PAGE_SIZE_4K)
+        .ok()?;
+    unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, PAGE_SIZE_4K) };
+    Some(virt_to_phys(VirtAddr::from(vaddr)))
+}
+
+/// Free the frame of an anonymous page.
+pub fn free_frame(paddr: PhysAddr) {
+    axalloc::global_allocator().dealloc_pages(phys_to_virt(paddr).as_usize(), 1);
+}
+
+/// Map the page at `vaddr`, which must not be mapped, to the frame `paddr`.
+pub fn map(aspace: &mut AddrSpace, vaddr: VirtAddr, paddr: PhysAddr, flags: MappingFlags) -> bool {
+    aspace
+        .page_table_mut()
+        .map(vaddr, paddr, PageSize::Size4K, flags)
+        .map(|tlb| tlb.flush())
+        .is_ok()
+}
+
+/// Unmap the page at `vaddr`. Returns the frame it was mapped to, which is
+/// not freed.
+pub fn unmap(aspace: &mut AddrSpace, vaddr: VirtAddr) -> Option<PhysAddr> {
+    let (paddr, _, tlb) = aspace.page_table_mut().unmap(vaddr).ok()?;
+    tlb.flush();
+    Some(paddr)
+}
+
+/// Map the page at `vaddr` to the frame `paddr` instead of the frame it is
+/// mapped to. Returns the old frame, which is not freed.
+pub fn remap(
+    aspace: &mut AddrSpace,
+    vaddr: VirtAddr,
+    paddr: PhysAddr,
+    flags: MappingFlags,
+) -> Option<PhysAddr> {
+    let old = unmap(aspace, vaddr)?;
+    if !map(aspace, vaddr, paddr, flags) {
+        // Put the old frame back, so that it is not lost.
+        map(aspace, vaddr, old, flags);
+        return None;
+    }
+    Some(old)
+}
+
+/// Change the permissions of the page at `vaddr`.
+pub fn protect(aspace: &mut AddrSpace, vaddr: VirtAddr, flags: MappingFlags) -> bool {
+    aspace
+        .page_table_mut()
+        .protect(vaddr, flags)
+        .map(|(_, tlb)| tlb.flush())
+        .is_ok()
+}
+
+/// The layout of the page table entries of the architecture, as far as the
+/// accessed bit is concerned. `page_table_multiarch` does not expose it.
+#[cfg(target_arch = "x86_64")]
+mod arch {
+    pub const LEVELS: usize = 4;
+    pub const ACCESSED: u64 = 1 << 5;
+
+    /// Whether `entry` maps a page or a block at a level above the last.
+    pub fn is_leaf(entry: u64, level: usize) -> bool {
+        level == 0 || entry & (1 << 7) != 0
+    }
+
+    pub fn is_valid(entry: u64) -> bool {
+        entry & 1 != 0
+    }
+
+    pub fn next_table(entry: u64) -> u64 {
+        entry & 0x000f_ffff_ffff_f000
+    }
+
+    /// Whether the hardware sets the accessed bit.
+    pub fn tracks_access() -> bool {
+        true
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+mod arch {
+    pub const LEVELS: usize = 3;
+    pub const ACCESSED: u64 = 1 << 6;
+
+    pub fn is_leaf(entry: u64, level: usize) -> bool {
+        // Any of R, W and X.
+        level == 0 || entry & 0b1110 != 0
+    }
+
+    pub fn is_valid(entry: u64) -> bool {
+        entry & 1 != 0
+    }
+
+    pub fn next_table(entry: u64) -> u64 {
+        ((entry >> 10) & ((1 << 44) - 1)) << 12
+    }
+
+    /// Whether the hardware sets the accessed bit. Without Svadu, accesses
+    /// to pages whose bit is clear fault, and the fault sets it.
+    pub fn tracks_access() -> bool {
+        true
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod arch {
+    pub const LEVELS: usize = 4;
+    /// The access flag, AF.
+    pub const ACCESSED: u64 = 1 << 10;
+
+    pub fn is_leaf(entry: u64, level: usize) -> bool {
+        // Blocks have bit 1 clear above the last level.
+        level == 0 || entry & 0b10 == 0
+    }
+
+    pub fn is_valid(entry: u64) -> bool {
+        entry & 1 != 0
+    }
+
+    pub fn next_table(entry: u64) -> u64 {
+        entry & 0x0000_ffff_ffff_f000
+    }
+
+    /// Whether the hardware sets the access flag (TCR_EL1.HA). Otherwise an
+    /// access to a page with the flag clear raises an access flag fault,
+    /// which is not delivered as a page fault.
+    pub fn tracks_access() -> bool {
+        let tcr: u64;
+        unsafe { core::arch::asm!("mrs {}, tcr_el1", out(reg) tcr) };
+        tcr & (1 << 39) != 0
+    }
+}
+
+/// Get the last-level entry that maps the page at `vaddr`.
+fn leaf_entry(aspace: &AddrSpace, vaddr: VirtAddr) -> Option<&AtomicU64> {
+    let mut table = aspace.page_table().root_paddr();
+    for level in (0..arch::LEVELS).rev() {
+        let index = (vaddr.as_usize() >> (12 + 9 * level)) & 0x1ff;
+        let entries = phys_to_virt(table).as_ptr() as *const AtomicU64;
+        // Entries are changed by the hardware, so they are accessed
+        // atomically.
+        let entry = unsafe { &*entries.add(index) };
+        let bits = entry.load(Ordering::Acquire);
+        if !arch::is_valid(bits) {
+            return None;
+        }
+        if arch::is_leaf(bits, level) {
+            // Huge pages are not tracked.
+            return (level == 0).then_some(entry);
+        }
+        table = PhysAddr::from(arch::next_table(bits) as usize);
+    }
+    None
+}
+
+/// Clear the accessed bit of the page at `vaddr`. Returns whether the page
+/// has been accessed since the bit was last cleared.
+///
+/// Returns false for all pages if the bit cannot be tracked.
+pub fn take_accessed(aspace: &mut AddrSpace, vaddr: VirtAddr) -> bool {
+    if !arch::tracks_access() {
+        return false;
+    }
+    let Some(entry) = leaf_entry(aspace, vaddr) else {
+        return false;
+    };
+    let accessed = entry.fetch_and(!arch::ACCESSED, Ordering::AcqRel) & arch::ACCESSED != 0;
+    if accessed {
+        // The bit is set again only when the translation is loaded anew.
+        axhal::arch::flush_tlb(Some(vaddr));
+    }
+    accessed
+}
+
+/// Set the accessed bit of the page at `vaddr`, for a fault the hardware
+/// raised instead of setting it. Returns whether the page is mapped.
+pub fn mark_accessed(aspace: &mut AddrSpace, vaddr: VirtAddr) -> bool {
+    let Some(entry) = leaf_entry(aspace, vaddr) else {
+        return false;
+    };
+    entry.fetch_or(arch::ACCESSED, Ordering::AcqRel);
+    axhal::arch::flush_tlb(Some(vaddr));
+    true
+}
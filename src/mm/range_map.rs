@@ -0,0 +1,92 @@
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+
+use memory_addr::{VirtAddr, VirtAddrRange};
+
+/// A map from non-overlapping virtual address ranges to values.
+///
+/// It is used to attach attributes to parts of the user address space that
+/// `AddrSpace` itself does not keep track of. Inserting or removing a range
+/// splits the existing entries at the range boundaries.
+#[derive(Clone)]
+pub struct RangeMap<T> {
+    /// Map from the start of each range to its end and value.
+    map: BTreeMap<VirtAddr, (VirtAddr, T)>,
+}
+
+impl<T: Clone> RangeMap<T> {
+    /// Create an empty map.
+    pub const fn new() -> Self {
+        Self {
+            map: BTreeMap::new(),
+        }
+    }
+
+    /// Whether the map contains no ranges.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Set the value of `range`, replacing whatever was there before.
+    pub fn insert(&mut self, range: VirtAddrRange, value: T) {
+        if range.is_empty() {
+            return;
+        }
+        self.remove(range);
+        self.map.insert(range.start, (range.end, value));
+    }
+
+    /// Remove `range` from the map, trimming the entries it overlaps.
+    pub fn remove(&mut self, range: VirtAddrRange) {
+        if range.is_empty() {
+            return;
+        }
+        let overlapped: Vec<VirtAddr> = self
+            .map
+            .range(..range.end)
+            .rev()
+            .take_while(|(_, (end, _))| *end > range.start)
+            .map(|(start, _)| *start)
+            .collect();
+        for start in overlapped {
+            let (end, value) = self.map.remove(&start).unwrap();
+            if start < range.start {
+                self.map.insert(start, (range.start, value.clone()));
+            }
+            if end > range.end {
+                self.map.insert(range.end, (end, value));
+            }
+        }
+    }
+
+    /// Get the range containing `vaddr` and its value.
+    pub fn get(&self, vaddr: VirtAddr) -> Option<(VirtAddrRange, &T)> {
+        self.map
+            .range(..=vaddr)
+            .next_back()
+            .filter(|(_, (end, _))| vaddr < *end)
+            .map(|(start, (end, value))| (VirtAddrRange::new(*start, *end), value))
+    }
+
+    /// Iterate over the parts of the entries that lie within `range`.
+    pub fn overlapping(&self, range: VirtAddrRange) -> impl Iterator<Item = (VirtAddrRange, &T)> {
+        let first = self
+            .map
+            .range(..range.start)
+            .next_back()
+            .filter(|(_, (end, _))| *end > range.start);
+        first
+            .into_iter()
+            .chain(self.map.range(range.start..range.end))
+            .map(move |(start, (end, value))| {
+                let clipped = VirtAddrRange::new((*start).max(range.start), (*end).min(range.end));
+                (clipped, value)
+            })
+    }
+
+    /// Iterate over all ranges and their values.
+    pub fn iter(&self) -> impl Iterator<Item = (VirtAddrRange, &T)> {
+        self.map
+            .iter()
+            .map(|(start, (end, value))| (VirtAddrRange::new(*start, *end), value))
+    }
+}
@@ -0,0 +1,435 @@
+//! Compressed swap for anonymous memory.
+//!
+//! When frames run out, the anonymous pages of the faulting process that have
+//! not been accessed lately are compressed into a pool of memory reserved at
+//! boot and their frames are freed. The candidates are scanned in the order
+//! they were faulted in, and pages whose accessed bit is set get a second
+//! chance.
+//!
+//! The swap entries are kept in [`VmAttrs`], not in the page tables.
+//! `page_table_multiarch` takes any entry that is not zero for a mapping:
+//! `query` would report a swap entry as mapped to some frame, `map` would
+//! fail on it, and `AddrSpace` would free it as a frame when its area is
+//! unmapped. A swapped out page is left unmapped within its area instead,
+//! which is the state `AddrSpace` itself leaves the pages of areas populated
+//! on demand in, so that unmapping, protecting and cloning the area need no
+//! help from here. The page is unmapped with [`pte::unmap`] because
+//! `AddrSpace::unmap` would split the area, which allocates. The next access
+//! faults, `AddrSpace` maps a fresh frame in the area with the permissions
+//! it has now, and the page is decompressed into it. Whatever unmaps the
+//! area drops the entries with it, see [`VmAttrs::forget`].
+//!
+//! Reclaim runs when memory is short, so it does not allocate from the heap:
+//! pages are compressed on the stack and stored in the pool, and the entries
+//! are recorded in space reserved beforehand. When either runs out, reclaim
+//! stops and the caller goes on to the OOM killer.
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use axhal::{mem::phys_to_virt, paging::MappingFlags};
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use super::{icache, pte, VmAttrs};
+use crate::config;
+
+/// The number of pages reclaimed at a time.
+pub const SWAP_CLUSTER: usize = 32;
+
+/// Pages that do not compress below this size are not worth swapping out.
+const MAX_COMPRESSED_SIZE: usize = PAGE_SIZE_4K * 3 / 4;
+
+const WORD_SIZE: usize = size_of::<u64>();
+/// The longest run or literal sequence described by one header byte.
+const MAX_TOKEN_WORDS: usize = 0x80;
+/// The header bit of a run of repeated words.
+const RUN_FLAG: u8 = 0x80;
+
+/// The unit the pool is allocated in, in bytes.
+const CHUNK_SIZE: usize = 64;
+/// The size of the header of a slot: the reference count and the length of
+/// the data.
+const SLOT_HEADER_SIZE: usize = 2 * size_of::<u32>();
+
+/// The number of pages swapped out.
+static SWAPPED_PAGES: AtomicUsize = AtomicUsize::new(0);
+/// The size of the compressed data of the pages swapped out, in bytes.
+static SWAPPED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// The memory reserved for compressed pages, divided into chunks.
+struct SwapPool {
+    /// The start address of the pool.
+    base: usize,
+    /// The number of chunks in the pool.
+    chunks: usize,
+    /// One bit per chunk, set if the chunk is in use.
+    used: Vec<u64>,
+    /// The chunk where the search for free chunks starts.
+    next: usize,
+}
+
+static POOL: Mutex<Option<SwapPool>> = Mutex::new(None);
+
+impl SwapPool {
+    fn is_used(&self, chunk: usize) -> bool {
+        self.used[chunk / 64] & (1 << (chunk % 64)) != 0
+    }
+
+    fn set_used(&mut self, start: usize, count: usize, used: bool) {
+        for chunk in start..start + count {
+            if used {
+                self.used[chunk / 64] |= 1 << (chunk % 64);
+            } else {
+                self.used[chunk / 64] &= !(1 << (chunk % 64));
+            }
+        }
+    }
+
+    /// Find `count` free chunks in a row, starting the search after the last
+    /// allocation. Returns the index of the first one.
+    fn alloc(&mut self, count: usize) -> Option<usize> {
+        let mut run = 0;
+        for i in 0..self.chunks {
+            let chunk = (self.next + i) % self.chunks;
+            if chunk == 0 {
+                // Runs do not wrap around.
+                run = 0;
+            }
+            if self.is_used(chunk) {
+                run = 0;
+                continue;
+            }
+            run += 1;
+            if run == count {
+                let start = chunk + 1 - count;
+                self.set_used(start, count, true);
+                self.next = (chunk + 1) % self.chunks;
+                return Some(start);
+            }
+        }
+        None
+    }
+
+    fn slot(&self, chunk: usize) -> *mut u8 {
+        (self.base + chunk * CHUNK_SIZE) as *mut u8
+    }
+}
+
+/// Reserve the memory for compressed pages.
+pub fn init() {
+    let pages = config::SWAP_POOL_SIZE / PAGE_SIZE_4K;
+    if pages == 0 {
+        return;
+    }
+    let Ok(base) = axalloc::global_allocator().alloc_pages(pages, PAGE_SIZE_4K) else {
+        warn!(
+            "Failed to reserve {:#x} bytes for swap",
+            config::SWAP_POOL_SIZE
+        );
+        return;
+    };
+    let chunks = pages * PAGE_SIZE_4K / CHUNK_SIZE;
+    *POOL.lock() = Some(SwapPool {
+        base,
+        chunks,
+        used: vec![0; chunks.div_ceil(64)],
+        next: 0,
+    });
+}
+
+/// The number of chunks a slot for `len` bytes of data takes.
+fn slot_chunks(len: usize) -> usize {
+    (SLOT_HEADER_SIZE + len).div_ceil(CHUNK_SIZE)
+}
+
+/// The compressed contents of a page that has been swapped out, stored in the
+/// pool.
+///
+/// It is shared by the processes forked after the page was swapped out: the
+/// slot counts the entries that refer to it and is freed with the last one.
+pub struct SwapEntry {
+    /// The pointer to the slot in the pool.
+    slot: *mut u8,
+}
+
+unsafe impl Send for SwapEntry {}
+unsafe impl Sync for SwapEntry {}
+
+impl SwapEntry {
+    /// Store `data` in the pool. Returns `None` if the pool is full.
+    fn new(data: &[u8]) -> Option<Self> {
+        let slot = {
+            let mut pool = POOL.lock();
+            let pool = pool.as_mut()?;
+            let chunk = pool.alloc(slot_chunks(data.len()))?;
+            pool.slot(chunk)
+        };
+        unsafe {
+            (slot as *mut u32).write(1);
+            (slot as *mut u32).add(1).write(data.len() as u32);
+            core::ptr::copy_nonoverlapping(data.as_ptr(), slot.add(SLOT_HEADER_SIZE), data.len());
+        }
+        SWAPPED_PAGES.fetch_add(1, Ordering::Relaxed);
+        SWAPPED_BYTES.fetch_add(data.len(), Ordering::Relaxed);
+        Some(Self { slot })
+    }
+
+    fn refcount(&self) -> &AtomicU32 {
+        unsafe { AtomicU32::from_ptr(self.slot as *mut u32) }
+    }
+
+    /// The compressed contents.
+    fn data(&self) -> &[u8] {
+        unsafe {
+            let len = (self.slot as *const u32).add(1).read() as usize;
+            core::slice::from_raw_parts(self.slot.add(SLOT_HEADER_SIZE), len)
+        }
+    }
+}
+
+impl Clone for SwapEntry {
+    fn clone(&self) -> Self {
+        self.refcount().fetch_add(1, Ordering::Relaxed);
+        Self { slot: self.slot }
+    }
+}
+
+impl Drop for SwapEntry {
+    fn drop(&mut self) {
+        if self.refcount().fetch_sub(1, Ordering::AcqRel) != 1 {
+            return;
+        }
+        let len = self.data().len();
+        SWAPPED_PAGES.fetch_sub(1, Ordering::Relaxed);
+        SWAPPED_BYTES.fetch_sub(len, Ordering::Relaxed);
+        let mut pool = POOL.lock();
+        let pool = pool.as_mut().unwrap();
+        let chunk = (self.slot as usize - pool.base) / CHUNK_SIZE;
+        pool.set_used(chunk, slot_chunks(len), false);
+    }
+}
+
+/// The pages of a process that have been swapped out, sorted by address.
+///
+/// Entries are only added after [`SwapMap::try_reserve`] has made room for
+/// them, so that adding them does not allocate.
+#[derive(Clone, Default)]
+pub struct SwapMap {
+    entries: Vec<(VirtAddr, SwapEntry)>,
+}
+
+impl SwapMap {
+    pub const fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// The number of pages swapped out.
+    pub fn count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Make room for `additional` more entries.
+    fn try_reserve(&mut self, additional: usize) -> bool {
+        self.entries.try_reserve(additional).is_ok()
+    }
+
+    /// Record the page at `vaddr` as swapped out, which it must not be yet.
+    /// There must be room for the entry.
+    fn insert(&mut self, vaddr: VirtAddr, entry: SwapEntry) {
+        debug_assert!(self.entries.len() < self.entries.capacity());
+        let index = self.entries.partition_point(|(addr, _)| *addr < vaddr);
+        self.entries.insert(index, (vaddr, entry));
+    }
+
+    /// Take the entry of the page at `vaddr`.
+    fn remove(&mut self, vaddr: VirtAddr) -> Option<SwapEntry> {
+        let index = self
+            .entries
+            .binary_search_by_key(&vaddr, |(addr, _)| *addr)
+            .ok()?;
+        Some(self.entries.remove(index).1)
+    }
+
+    /// Whether any page in `range` has been swapped out.
+    pub fn overlaps(&self, range: VirtAddrRange) -> bool {
+        let index = self
+            .entries
+            .partition_point(|(addr, _)| *addr < range.start);
+        self.entries
+            .get(index)
+            .is_some_and(|(addr, _)| range.contains(*addr))
+    }
+
+    /// Drop the entries of the pages in `range`.
+    pub fn remove_range(&mut self, range: VirtAddrRange) {
+        self.entries.retain(|(vaddr, _)| !range.contains(*vaddr));
+    }
+}
+
+fn read_word(page: &[u8], index: usize) -> u64 {
+    let offset = index * WORD_SIZE;
+    u64::from_ne_bytes(page[offset..offset + WORD_SIZE].try_into().unwrap())
+}
+
+/// Compress a page with a run-length encoding of its 64-bit words into `out`.
+///
+/// Each header byte starts either a run of up to 128 copies of the following
+/// word (the high bit set), or up to 128 literal words. Returns the size of
+/// the compressed data, or `None` if the page does not compress well.
+fn compress(page: &[u8], out: &mut [u8; MAX_COMPRESSED_SIZE]) -> Option<usize> {
+    let words = page.len() / WORD_SIZE;
+    let mut len = 0;
+    let mut push = |bytes: &[u8]| {
+        let dst = out.get_mut(len..len + bytes.len())?;
+        dst.copy_from_slice(bytes);
+        len += bytes.len();
+        Some(len)
+    };
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i <= words {
+        let run = if i < words {
+            let word = read_word(page, i);
+            (i..words.min(i + MAX_TOKEN_WORDS))
+                .take_while(|&j| read_word(page, j) == word)
+                .count()
+        } else {
+            0
+        };
+        if i < words && run < 2 {
+            i += 1;
+            continue;
+        }
+        // Flush the literals before the run, or before the end.
+        for start in (literal_start..i).step_by(MAX_TOKEN_WORDS) {
+            let end = i.min(start + MAX_TOKEN_WORDS);
+            push(&[(end - start - 1) as u8])?;
+            for j in start..end {
+                push(&read_word(page, j).to_ne_bytes())?;
+            }
+        }
+        if i == words {
+            break;
+        }
+        push(&[RUN_FLAG | (run - 1) as u8])?;
+        push(&read_word(page, i).to_ne_bytes())?;
+        i += run;
+        literal_start = i;
+    }
+    Some(len)
+}
+
+/// Decompress the data produced by [`compress`] into `page`.
+fn decompress(data: &[u8], page: &mut [u8]) {
+    let mut words = page.chunks_exact_mut(WORD_SIZE);
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data[pos];
+        let count = (header & !RUN_FLAG) as usize + 1;
+        pos += 1;
+        if header & RUN_FLAG != 0 {
+            let word = &data[pos..pos + WORD_SIZE];
+            pos += WORD_SIZE;
+            for dst in words.by_ref().take(count) {
+                dst.copy_from_slice(word);
+            }
+        } else {
+            for dst in words.by_ref().take(count) {
+                dst.copy_from_slice(&data[pos..pos + WORD_SIZE]);
+                pos += WORD_SIZE;
+            }
+        }
+    }
+}
+
+/// Swap out up to `count` resident anonymous pages that have not been
+/// accessed lately. Returns the number of pages swapped out.
+pub fn reclaim(aspace: &mut AddrSpace, vm_attrs: &mut VmAttrs, count: usize) -> usize {
+    if !vm_attrs.swapped.try_reserve(count) {
+        return 0;
+    }
+    let mut buf = [0; MAX_COMPRESSED_SIZE];
+    let mut reclaimed = 0;
+    let mut scanned = 0;
+    // Every page is looked at at most twice: once to clear its accessed bit,
+    // and once more after it has gone round.
+    let to_scan = vm_attrs.resident.len() * 2;
+    while reclaimed < count && scanned < to_scan {
+        // Pages that stay resident are pushed back right after being popped,
+        // so the queue never grows here.
+        let Some(vaddr) = vm_attrs.resident.pop_front() else {
+            break;
+        };
+        scanned += 1;
+        // Pages of shared mappings belong to the page cache.
+        if vm_attrs.shared_pages.contains_key(&vaddr) || vm_attrs.zero_pages.contains(&vaddr) {
+            continue;
+        }
+        let Ok((paddr, _, _)) = aspace.page_table().query(vaddr) else {
+            continue;
+        };
+        if pte::take_accessed(aspace, vaddr) {
+            vm_attrs.resident.push_back(vaddr);
+            continue;
+        }
+        let frame =
+            unsafe { core::slice::from_raw_parts(phys_to_virt(paddr).as_ptr(), PAGE_SIZE_4K) };
+        let Some(len) = compress(frame, &mut buf) else {
+            vm_attrs.resident.push_back(vaddr);
+            continue;
+        };
+        let Some(entry) = SwapEntry::new(&buf[..len]) else {
+            vm_attrs.resident.push_back(vaddr);
+            debug!("Swap: the pool is full");
+            break;
+        };
+        pte::unmap(aspace, vaddr);
+        pte::free_frame(paddr);
+        vm_attrs.page_freed(vaddr);
+        vm_attrs.swapped.insert(vaddr, entry);
+        reclaimed += 1;
+    }
+    debug!(
+        "Swap: {} pages swapped out, {} pages in {} bytes in total",
+        reclaimed,
+        SWAPPED_PAGES.load(Ordering::Relaxed),
+        SWAPPED_BYTES.load(Ordering::Relaxed),
+    );
+    reclaimed
+}
+
+/// Swap in the page containing `vaddr` if it has been swapped out.
+///
+/// Returns `None` if the page is not swapped out, so that the fault is left
+/// to the other handlers.
+pub fn swap_in(
+    aspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> Option<bool> {
+    let vaddr = vaddr.align_down_4k();
+    let entry = vm_attrs.swapped.remove(vaddr)?;
+    // `AddrSpace` does not populate the areas it populated when they were
+    // mapped, like the stack, so their pages are populated as after
+    // `discard_pages`.
+    if !aspace.handle_page_fault(vaddr, access_flags)
+        && !super::populate_anonymous(aspace, vm_attrs, vaddr, access_flags)
+    {
+        // Removing an entry leaves room to put it back.
+        vm_attrs.swapped.insert(vaddr, entry);
+        return Some(false);
+    }
+    let (paddr, flags, _) = aspace.page_table().query(vaddr).unwrap();
+    let frame =
+        unsafe { core::slice::from_raw_parts_mut(phys_to_virt(paddr).as_mut_ptr(), PAGE_SIZE_4K) };
+    decompress(entry.data(), frame);
+    if flags.contains(MappingFlags::EXECUTE) {
+        icache::sync_frame(paddr);
+    }
+    vm_attrs.page_populated(vaddr, false);
+    Some(true)
+}
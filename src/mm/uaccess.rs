@@ -0,0 +1,143 @@
+//! Access to user memory from system calls.
+//!
+//! System calls never dereference the pointers they are given. The memory is
+//! copied page by page through [`super::with_user_page`] instead, which checks
+//! the page against the address space of the current process and faults it in
+//! if needed, so that a pointer that is not mapped with the permissions the
+//! access needs fails with `EFAULT` rather than faulting in the kernel.
+
+use alloc::{string::String, vec::Vec};
+use core::ffi::c_char;
+use core::mem::{size_of, MaybeUninit};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axtask::{current, TaskExtRef};
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+/// The size of the longest path a system call accepts, with the NUL.
+pub const PATH_MAX: usize = 4096;
+
+/// Call `f` with each part of the user range `[addr, addr + len)` that lies in
+/// one page, as the frame of the page, the range of the part in the frame, and
+/// the offset of the part in the range.
+fn for_each_page(
+    addr: usize,
+    len: usize,
+    access_flags: MappingFlags,
+    mut f: impl FnMut(&mut [u8], core::ops::Range<usize>, usize),
+) -> LinuxResult {
+    if len == 0 {
+        return Ok(());
+    }
+    if addr == 0 || addr.checked_add(len).is_none() {
+        return Err(LinuxError::EFAULT);
+    }
+    let curr = current();
+    let mut done = 0;
+    while done < len {
+        let vaddr = addr + done;
+        let start = vaddr % PAGE_SIZE_4K;
+        let size = (PAGE_SIZE_4K - start).min(len - done);
+        super::with_user_page(
+            curr.task_ext(),
+            VirtAddr::from(vaddr),
+            access_flags,
+            |frame| f(frame, start..start + size, done),
+        )
+        .ok_or(LinuxError::EFAULT)?;
+        done += size;
+    }
+    Ok(())
+}
+
+/// Copy `dst.len()` bytes from the user address `src` into `dst`.
+pub fn copy_from_user(dst: &mut [u8], src: *const u8) -> LinuxResult {
+    for_each_page(
+        src as usize,
+        dst.len(),
+        MappingFlags::READ,
+        |frame, range, off| dst[off..off + range.len()].copy_from_slice(&frame[range]),
+    )
+}
+
+/// Copy `src` to the user address `dst`.
+pub fn copy_to_user(dst: *mut u8, src: &[u8]) -> LinuxResult {
+    for_each_page(
+        dst as usize,
+        src.len(),
+        MappingFlags::WRITE,
+        |frame, range, off| frame[range.clone()].copy_from_slice(&src[off..off + range.len()]),
+    )
+}
+
+/// Read a value of type `T` from the user address `src`.
+///
+/// `T` must be valid for any bit pattern, like the `repr(C)` structures of
+/// the system call ABI.
+pub fn read_user<T: Copy>(src: *const T) -> LinuxResult<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes =
+        unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user(bytes, src as _)?;
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Write `value` to the user address `dst`.
+pub fn write_user<T: Copy>(dst: *mut T, value: T) -> LinuxResult {
+    let bytes =
+        unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(dst as _, bytes)
+}
+
+/// Read the array of `len` values of type `T` at the user address `src`.
+pub fn read_user_slice<T: Copy>(src: *const T, len: usize) -> LinuxResult<Vec<T>> {
+    let size = len.checked_mul(size_of::<T>()).ok_or(LinuxError::EFAULT)?;
+    let mut values = Vec::<T>::new();
+    values
+        .try_reserve_exact(len)
+        .map_err(|_| LinuxError::ENOMEM)?;
+    let bytes = unsafe { core::slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, size) };
+    copy_from_user(bytes, src as _)?;
+    unsafe { values.set_len(len) };
+    Ok(values)
+}
+
+/// Read the NUL-terminated string at the user address `src`, which may be at
+/// most `max_len` bytes long without the NUL. Fails with `ENAMETOOLONG` if it
+/// is longer, and with `EINVAL` if it is not UTF-8.
+pub fn read_user_str(src: *const c_char, max_len: usize) -> LinuxResult<String> {
+    if src.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    let mut bytes = Vec::new();
+    let mut addr = src as usize;
+    loop {
+        // Read up to the end of the page, which may be the last one mapped.
+        let start = bytes.len();
+        let size = PAGE_SIZE_4K - addr % PAGE_SIZE_4K;
+        bytes.resize(start + size, 0);
+        copy_from_user(&mut bytes[start..], addr as *const u8)?;
+        if let Some(nul) = bytes[start..].iter().position(|&b| b == 0) {
+            bytes.truncate(start + nul);
+            break;
+        }
+        if bytes.len() > max_len {
+            return Err(LinuxError::ENAMETOOLONG);
+        }
+        addr += size;
+    }
+    if bytes.len() > max_len {
+        return Err(LinuxError::ENAMETOOLONG);
+    }
+    String::from_utf8(bytes).map_err(|_| LinuxError::EINVAL)
+}
+
+/// Check that the user range `[addr, addr + len)` allows `access_flags`, and
+/// fault it in, before it is passed to code that accesses it directly.
+///
+/// The pages may still be swapped out before they are accessed, but the
+/// kernel faults them back in then.
+pub fn check_user(addr: *const u8, len: usize, access_flags: MappingFlags) -> LinuxResult {
+    for_each_page(addr as usize, len, access_flags, |_, _, _| {})
+}
@@ -0,0 +1,110 @@
+//! The shared zero page.
+//!
+//! A read fault on untouched anonymous memory maps a single global frame of
+//! zeros read-only instead of allocating a frame. The first write to such a
+//! page faults again and replaces it with a private zero-filled frame, so
+//! sparse memory that is mostly read costs no frames at all.
+//!
+//! The zero frame is mapped within the area of the page, see [`pte`]. It
+//! does not belong to the area, so it is unmapped before the area goes away
+//! and replaced with a copy when the area is cloned.
+use axhal::{mem::virt_to_phys, paging::MappingFlags};
+use axmm::AddrSpace;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use super::{is_page_resident, pte, VmAttrs};
+
+#[repr(C, align(4096))]
+struct ZeroPage([u8; PAGE_SIZE_4K]);
+
+static ZERO_PAGE: ZeroPage = ZeroPage([0; PAGE_SIZE_4K]);
+
+/// The physical address of the zero frame.
+fn zero_frame() -> PhysAddr {
+    virt_to_phys(VirtAddr::from(&ZERO_PAGE as *const _ as usize))
+}
+
+/// Serve a fault on anonymous memory with the zero page, or break the
+/// sharing of the zero page on a write.
+///
+/// Returns `false` if the fault should be served with a new frame instead:
+/// the page is not in a mapping that allows the access, it is already
+/// populated, or the access is the first one and writes.
+pub fn fault(
+    aspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool {
+    let vaddr = vaddr.align_down_4k();
+    let Some((_, &flags)) = vm_attrs.mapped.get(vaddr) else {
+        return false;
+    };
+    if !flags.contains(access_flags) {
+        return false;
+    }
+
+    if vm_attrs.zero_pages.contains(&vaddr) {
+        if !access_flags.contains(MappingFlags::WRITE) {
+            return false;
+        }
+        // The zero page stays mapped if the frames have run out, for the
+        // retry after reclaim.
+        let Some(paddr) = pte::alloc_frame() else {
+            return false;
+        };
+        if pte::remap(aspace, vaddr, paddr, flags).is_none() {
+            pte::free_frame(paddr);
+            return false;
+        }
+        vm_attrs.zero_pages.remove(&vaddr);
+        vm_attrs.page_populated(vaddr, false);
+        return true;
+    }
+
+    if access_flags.contains(MappingFlags::WRITE)
+        || is_page_resident(aspace, vaddr)
+        || vm_attrs.file_maps.get(vaddr).is_some()
+        || vm_attrs.kernel_maps.get(vaddr).is_some()
+    {
+        return false;
+    }
+    if !pte::map(aspace, vaddr, zero_frame(), flags - MappingFlags::WRITE) {
+        return false;
+    }
+    vm_attrs.zero_pages.insert(vaddr);
+    true
+}
+
+/// Take the write permission back from the zero pages in `range`, e.g.
+/// after `mprotect` has granted it to the whole range.
+pub fn write_protect(aspace: &mut AddrSpace, vm_attrs: &VmAttrs, range: VirtAddrRange) {
+    for &vaddr in vm_attrs.zero_pages.range(range.start..range.end) {
+        if let Ok((_, flags, _)) = aspace.page_table().query(vaddr) {
+            pte::protect(aspace, vaddr, flags - MappingFlags::WRITE);
+        }
+    }
+}
+
+/// Unmap the zero pages in `range` before their areas are unmapped, which
+/// would free the zero frame as one of their own. They are still recorded
+/// in `vm_attrs`.
+pub fn unmap(aspace: &mut AddrSpace, vm_attrs: &VmAttrs, range: VirtAddrRange) {
+    for &vaddr in vm_attrs.zero_pages.range(range.start..range.end) {
+        pte::unmap(aspace, vaddr);
+    }
+}
+
+/// Map the zero pages of the clone `aspace` of an address space to the zero
+/// frame again. Cloning has copied them into frames of their own, which are
+/// freed.
+pub fn share_cloned(aspace: &mut AddrSpace, vm_attrs: &VmAttrs) {
+    for &vaddr in vm_attrs.zero_pages.iter() {
+        let Some((_, &flags)) = vm_attrs.mapped.get(vaddr) else {
+            continue;
+        };
+        if let Some(copy) = pte::remap(aspace, vaddr, zero_frame(), flags - MappingFlags::WRITE) {
+            pte::free_frame(copy);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! A minimal `/proc` with the per-process files that have no other home.
+//!
+//! The files are generated when read. Like memfds, their descriptors refer
+//! to `/dev/null` and the reads and writes are routed here.
+use alloc::{collections::btree_map::BTreeMap, format, string::String, vec::Vec};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axsync::Mutex;
+use axtask::{current, AxTaskRef, TaskExtRef};
+use memory_addr::{VirtAddrRange, PAGE_SIZE_4K};
+
+use crate::mm::oom::{self, OOM_SCORE_ADJ_MAX, OOM_SCORE_ADJ_MIN};
+use crate::task::{self, TaskExt};
+
+/// The files in the directory of a process.
+#[derive(Clone, Copy, Debug)]
+enum ProcEntry {
+    /// `oom_score`: the badness used by the OOM killer.
+    OomScore,
+    /// `oom_score_adj`: the adjustment of the badness.
+    OomScoreAdj,
+    /// `status`: the state of the process, including its memory usage.
+    Status,
+    /// `statm`: the memory usage of the process, in pages.
+    Statm,
+}
+
+impl ProcEntry {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "oom_score" => Some(Self::OomScore),
+            "oom_score_adj" => Some(Self::OomScoreAdj),
+            "status" => Some(Self::Status),
+            "statm" => Some(Self::Statm),
+            _ => None,
+        }
+    }
+}
+
+/// An open file in `/proc`.
+#[derive(Clone, Copy, Debug)]
+pub struct ProcFile {
+    pid: usize,
+    entry: ProcEntry,
+}
+
+impl ProcFile {
+    fn process(&self) -> LinuxResult<AxTaskRef> {
+        task::find_process(self.pid).ok_or(LinuxError::ESRCH)
+    }
+
+    /// Generate the contents of the file.
+    pub fn read(&self) -> LinuxResult<Vec<u8>> {
+        let process = self.process()?;
+        let task_ext = process.task_ext();
+        let content = match self.entry {
+            ProcEntry::OomScore => format!("{}\n", oom::oom_score(task_ext)),
+            ProcEntry::OomScoreAdj => format!("{}\n", task_ext.oom_score_adj()),
+            ProcEntry::Status => status(&process),
+            ProcEntry::Statm => {
+                let usage = MemoryUsage::of(task_ext);
+                format!(
+                    "{} {} {} {} 0 {} 0\n",
+                    usage.size,
+                    usage.rss,
+                    usage.file,
+                    usage.text,
+                    usage.data + usage.stack,
+                )
+            }
+        };
+        Ok(content.into_bytes())
+    }
+
+    /// Write `data` to the file. Returns the number of bytes consumed.
+    pub fn write(&self, data: &[u8]) -> LinuxResult<usize> {
+        let process = self.process()?;
+        let text = core::str::from_utf8(data).map_err(|_| LinuxError::EINVAL)?;
+        match self.entry {
+            ProcEntry::OomScore | ProcEntry::Status | ProcEntry::Statm => {
+                return Err(LinuxError::EACCES)
+            }
+            ProcEntry::OomScoreAdj => {
+                let adj: i32 = text.trim().parse().map_err(|_| LinuxError::EINVAL)?;
+                if !(OOM_SCORE_ADJ_MIN..=OOM_SCORE_ADJ_MAX).contains(&adj) {
+                    return Err(LinuxError::EINVAL);
+                }
+                process.task_ext().set_oom_score_adj(adj);
+            }
+        }
+        Ok(data.len())
+    }
+}
+
+/// The memory usage of a process, in pages.
+struct MemoryUsage {
+    size: usize,
+    peak_size: usize,
+    rss: usize,
+    peak_rss: usize,
+    anon: usize,
+    file: usize,
+    text: usize,
+    data: usize,
+    stack: usize,
+    page_tables: usize,
+    swap: usize,
+}
+
+impl MemoryUsage {
+    fn of(task_ext: &TaskExt) -> Self {
+        let vm_attrs = task_ext.vm_attrs.lock();
+        let stack = *task_ext.stack.lock();
+        let stats = &vm_attrs.stats;
+        let in_stack = |range: VirtAddrRange| stack.contains_range(range);
+        Self {
+            size: stats.total_vm,
+            peak_size: stats.peak_vm,
+            rss: stats.rss(),
+            peak_rss: stats.peak_rss,
+            anon: stats.anon_pages,
+            file: stats.file_pages,
+            text: vm_attrs.mapped_pages(|_, flags| flags.contains(MappingFlags::EXECUTE)),
+            data: vm_attrs.mapped_pages(|range, flags| {
+                flags.contains(MappingFlags::WRITE)
+                    && !flags.contains(MappingFlags::EXECUTE)
+                    && !in_stack(range)
+            }),
+            stack: vm_attrs.mapped_pages(|range, _| in_stack(range)),
+            page_tables: stats.page_table_pages(),
+            swap: vm_attrs.swapped.count(),
+        }
+    }
+}
+
+/// Generate `/proc/<pid>/status`.
+fn status(process: &AxTaskRef) -> String {
+    let task_ext = process.task_ext();
+    let usage = MemoryUsage::of(task_ext);
+    let kb = |pages: usize| format!("{:>8} kB", pages * PAGE_SIZE_4K / 1024);
+    let mut content = format!("Name:\t{}\nPid:\t{}\n", process.name(), task_ext.proc_id);
+    for (name, pages) in [
+        ("VmPeak", usage.peak_size),
+        ("VmSize", usage.size),
+        ("VmHWM", usage.peak_rss),
+        ("VmRSS", usage.rss),
+        ("RssAnon", usage.anon),
+        ("RssFile", usage.file),
+        ("VmData", usage.data),
+        ("VmStk", usage.stack),
+        ("VmExe", usage.text),
+        ("VmPTE", usage.page_tables),
+        ("VmSwap", usage.swap),
+    ] {
+        content += &format!("{}:\t{}\n", name, kb(pages));
+    }
+    content
+}
+
+/// Whether `path` is in `/proc`.
+pub fn is_proc_path(path: &str) -> bool {
+    path == "/proc" || path.starts_with("/proc/")
+}
+
+/// Look up the file at `path`, e.g. `/proc/self/oom_score_adj`.
+pub fn lookup(path: &str) -> LinuxResult<ProcFile> {
+    let mut parts = path
+        .strip_prefix("/proc/")
+        .ok_or(LinuxError::ENOENT)?
+        .split('/')
+        .filter(|part| !part.is_empty());
+    let pid = match parts.next().ok_or(LinuxError::EISDIR)? {
+        "self" => current().task_ext().proc_id,
+        pid => pid.parse().map_err(|_| LinuxError::ENOENT)?,
+    };
+    if task::find_process(pid).is_none() {
+        return Err(LinuxError::ENOENT);
+    }
+    let entry = parts.next().ok_or(LinuxError::EISDIR)?;
+    let entry = ProcEntry::from_name(entry).ok_or(LinuxError::ENOENT)?;
+    if parts.next().is_some() {
+        return Err(LinuxError::ENOTDIR);
+    }
+    Ok(ProcFile { pid, entry })
+}
+
+/// The files in `/proc` opened through file descriptors.
+static OPEN_FILES: Mutex<BTreeMap<i32, ProcFile>> = Mutex::new(BTreeMap::new());
+
+/// Route the reads and writes of `fd` to `file`.
+pub fn bind_fd(fd: i32, file: ProcFile) {
+    OPEN_FILES.lock().insert(fd, file);
+}
+
+/// Stop routing the reads and writes of `fd` to a file in `/proc`.
+pub fn unbind_fd(fd: i32) {
+    OPEN_FILES.lock().remove(&fd);
+}
+
+/// Get the file in `/proc` opened as `fd`, if any.
+pub fn fd_file(fd: i32) -> Option<ProcFile> {
+    OPEN_FILES.lock().get(&fd).copied()
+}
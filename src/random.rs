@@ -0,0 +1,195 @@
+//! A random number generator for the kernel.
+//!
+//! It is a ChaCha20 keystream whose key is replaced with fresh keystream
+//! after every block, so earlier output cannot be recovered from the state.
+//! The first key is taken from the hardware random number generator (RDRAND
+//! on x86_64, RNDR on aarch64) when the CPU has one, and from the jitter of
+//! the timer otherwise.
+use axsync::Mutex;
+
+/// The state of the generator.
+struct ChaCha {
+    key: [u32; 8],
+    counter: u64,
+    /// Keystream words that have not been handed out yet.
+    buf: [u32; 8],
+    /// The number of words of `buf` that have been handed out.
+    used: usize,
+}
+
+static RNG: Mutex<Option<ChaCha>> = Mutex::new(None);
+
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// Compute the ChaCha20 block for `key` and `counter`, with a zero nonce.
+fn chacha_block(key: &[u32; 8], counter: u64) -> [u32; 16] {
+    let mut input = [0; 16];
+    input[..4].copy_from_slice(&CONSTANTS);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    let mut s = input;
+    for _ in 0..10 {
+        quarter_round(&mut s, 0, 4, 8, 12);
+        quarter_round(&mut s, 1, 5, 9, 13);
+        quarter_round(&mut s, 2, 6, 10, 14);
+        quarter_round(&mut s, 3, 7, 11, 15);
+        quarter_round(&mut s, 0, 5, 10, 15);
+        quarter_round(&mut s, 1, 6, 11, 12);
+        quarter_round(&mut s, 2, 7, 8, 13);
+        quarter_round(&mut s, 3, 4, 9, 14);
+    }
+    for (word, input) in s.iter_mut().zip(input) {
+        *word = word.wrapping_add(input);
+    }
+    s
+}
+
+impl ChaCha {
+    fn new(key: [u32; 8]) -> Self {
+        Self {
+            key,
+            counter: 0,
+            buf: [0; 8],
+            used: 8,
+        }
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        if self.used == self.buf.len() {
+            let block = chacha_block(&self.key, self.counter);
+            self.counter = self.counter.wrapping_add(1);
+            self.key.copy_from_slice(&block[..8]);
+            self.buf.copy_from_slice(&block[8..]);
+            self.used = 0;
+        }
+        let word = self.buf[self.used];
+        // Do not keep what has been handed out.
+        self.buf[self.used] = 0;
+        self.used += 1;
+        word
+    }
+}
+
+/// Get a random `u64` from the hardware random number generator, if the CPU
+/// has one.
+#[cfg(target_arch = "x86_64")]
+fn hardware_u64() -> Option<u64> {
+    if !x86::random::rdrand_enabled() {
+        return None;
+    }
+    // RDRAND may fail transiently, Intel recommends 10 retries.
+    (0..10).find_map(|_| {
+        let mut value = 0;
+        unsafe { x86::random::rdrand64(&mut value) }.then_some(value)
+    })
+}
+
+/// Get a random `u64` from the hardware random number generator, if the CPU
+/// has one.
+#[cfg(target_arch = "aarch64")]
+fn hardware_u64() -> Option<u64> {
+    let isar0: u64;
+    unsafe { core::arch::asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0) };
+    // ID_AA64ISAR0_EL1.RNDR says whether FEAT_RNG is implemented.
+    if (isar0 >> 60) & 0xf == 0 {
+        return None;
+    }
+    (0..10).find_map(|_| {
+        let value: u64;
+        let ok: u64;
+        // RNDR clears the Z flag on success.
+        unsafe {
+            core::arch::asm!(
+                "mrs {}, s3_3_c2_c4_0",
+                "cset {}, ne",
+                out(reg) value,
+                out(reg) ok,
+                options(nomem, nostack),
+            )
+        };
+        (ok != 0).then_some(value)
+    })
+}
+
+/// Get a random `u64` from the hardware random number generator, if the CPU
+/// has one. The `seed` CSR of riscv64 traps unless the firmware grants access
+/// to it, and there is no way to find out, so it is not used.
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn hardware_u64() -> Option<u64> {
+    None
+}
+
+/// Collect entropy from the jitter of the timer around work whose duration
+/// varies with the state of caches, memory and interrupts.
+fn jitter_u64() -> u64 {
+    let mut acc = axhal::time::wall_time_nanos();
+    let mut scratch = [0u8; 256];
+    for i in 0..1024usize {
+        let before = axhal::time::current_ticks();
+        for (j, byte) in scratch.iter_mut().enumerate() {
+            *byte = byte.wrapping_add((acc >> (j % 64)) as u8);
+        }
+        core::hint::black_box(&scratch);
+        let delta = axhal::time::current_ticks().wrapping_sub(before);
+        acc = (acc.rotate_left(7) ^ delta).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ i as u64;
+    }
+    acc
+}
+
+/// Get a key for the generator.
+fn seed() -> [u32; 8] {
+    let mut key = [0; 8];
+    for pair in key.chunks_mut(2) {
+        let value = hardware_u64().unwrap_or(0) ^ jitter_u64();
+        pair[0] = value as u32;
+        pair[1] = (value >> 32) as u32;
+    }
+    key
+}
+
+/// Get a random `u32`.
+fn random_u32() -> u32 {
+    let mut rng = RNG.lock();
+    rng.get_or_insert_with(|| ChaCha::new(seed())).next_u32()
+}
+
+/// Get a random `u64`.
+pub fn random_u64() -> u64 {
+    (random_u32() as u64) << 32 | random_u32() as u64
+}
+
+/// Get a random `usize` in `[0, bound)`. Returns 0 if `bound` is 0.
+pub fn random_below(bound: usize) -> usize {
+    if bound == 0 {
+        return 0;
+    }
+    let bound = bound as u64;
+    // Values below `2^64 % bound` would make the smaller results more likely.
+    let threshold = bound.wrapping_neg() % bound;
+    loop {
+        let value = random_u64();
+        if value >= threshold {
+            return (value % bound) as usize;
+        }
+    }
+}
+
+/// Fill `buf` with random bytes.
+pub fn fill_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        chunk.copy_from_slice(&random_u64().to_ne_bytes()[..chunk.len()]);
+    }
+}
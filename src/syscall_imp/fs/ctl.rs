@@ -1,10 +1,115 @@
+use alloc::{ffi::CString, string::String, sync::Arc};
 use core::ffi::{c_char, c_void};
 
-use arceos_posix_api::ctypes::AT_FDCWD;
-use arceos_posix_api::{self as api, ctypes::mode_t};
-use axerrno::LinuxError;
+use arceos_posix_api::{self as api, ctypes, ctypes::mode_t};
+use axerrno::{LinuxError, LinuxResult};
+use axtask::{current, TaskExtRef};
 
-use crate::syscall_body;
+use super::path_at;
+use crate::mm::page_cache::{self, CachedFile, OpenFile, Seals};
+use crate::mm::uaccess;
+use crate::task::TaskExt;
+use crate::{procfs, syscall_body, vfs};
+
+const O_ACCMODE: i32 = 3;
+const O_RDONLY: i32 = 0;
+const O_RDWR: i32 = 2;
+/// Create the file if it does not exist.
+const O_CREAT: i32 = 0o100;
+/// Fail if the file exists.
+const O_EXCL: i32 = 0o200;
+/// Truncate the file to zero length when opening it.
+const O_TRUNC: i32 = 0o1000;
+/// Append to the end of the file on each write.
+const O_APPEND: i32 = 0o2000;
+/// Do not block on reads and writes.
+const O_NONBLOCK: i32 = 0o4000;
+/// Close the file descriptor on `execve`.
+const O_CLOEXEC: i32 = 0o2000000;
+
+const F_DUPFD: i32 = 0;
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+const F_SETFL: i32 = 4;
+const F_DUPFD_CLOEXEC: i32 = 1030;
+const F_ADD_SEALS: i32 = 1033;
+const F_GET_SEALS: i32 = 1034;
+
+/// The file descriptor flag that closes the descriptor on `execve`.
+const FD_CLOEXEC: usize = 1;
+
+/// Close the memfd on `execve`.
+const MFD_CLOEXEC: u32 = 0x0001;
+/// Allow seals to be added to the memfd.
+const MFD_ALLOW_SEALING: u32 = 0x0002;
+/// The maximum length of a memfd name, without the terminating NUL.
+const MFD_NAME_MAX_LEN: usize = 249;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFREG: u32 = 0o100000;
+
+/// Set or clear the close-on-exec flag of `fd` in the current process.
+fn set_cloexec(fd: i32, cloexec: bool) {
+    let curr = current();
+    let mut cloexec_fds = curr.task_ext().cloexec_fds.lock();
+    if cloexec {
+        cloexec_fds.insert(fd);
+    } else {
+        cloexec_fds.remove(&fd);
+    }
+}
+
+/// Check that `fd` is an open file descriptor.
+pub(crate) fn check_fd(fd: i32) -> LinuxResult {
+    let mut st: ctypes::stat = unsafe { core::mem::zeroed() };
+    if unsafe { api::sys_fstat(fd, &mut st) } != 0 {
+        return Err(LinuxError::EBADF);
+    }
+    Ok(())
+}
+
+/// Whether `fd` is closed on `execve` by the current process.
+fn is_cloexec(fd: i32) -> bool {
+    current().task_ext().cloexec_fds.lock().contains(&fd)
+}
+
+/// Close the file descriptors the current process marked close-on-exec,
+/// once a new program replaces the old one.
+///
+/// The table of file descriptors is shared by all processes, and the parent
+/// waits in `clone` until the process exits. Closing a descriptor would
+/// close it in the parent as well, so each one is moved to a new descriptor
+/// first, and [`restore_exec_stash`] gives it back to the parent.
+pub(crate) fn close_on_exec() {
+    let curr = current();
+    let task_ext = curr.task_ext();
+    let fds = core::mem::take(&mut *task_ext.cloexec_fds.lock());
+    for fd in fds {
+        if task_ext.parent_id != 0 {
+            let stash = api::sys_dup(fd);
+            if stash >= 0 {
+                copy_bindings(fd, stash);
+                task_ext.exec_stash.lock().push((fd, stash));
+            }
+        }
+        sys_close(fd);
+    }
+}
+
+/// Give the descriptors that the exited child `child` moved out of the way
+/// in [`close_on_exec`] back to the current process, its parent.
+///
+/// Whatever the child left open in their place is closed, as it would have
+/// been when the child exited with a table of its own.
+pub(crate) fn restore_exec_stash(child: &TaskExt) {
+    let stash = core::mem::take(&mut *child.exec_stash.lock());
+    for (fd, stash) in stash.into_iter().rev() {
+        let cloexec = is_cloexec(fd);
+        sys_dup3(stash, fd, 0);
+        set_cloexec(fd, cloexec);
+        sys_close(stash);
+    }
+}
 
 /// The ioctl() system call manipulates the underlying device parameters
 /// of special files.
@@ -21,23 +126,298 @@ pub(crate) fn sys_ioctl(_fd: i32, _op: usize, _argp: *mut c_void) -> i32 {
     })
 }
 
+/// Route the reads and writes of a newly opened regular file through the
+/// page cache.
+fn bind_page_cache(fd: i32, path: &str, flags: i32) {
+    let mut st: ctypes::stat = unsafe { core::mem::zeroed() };
+    if unsafe { api::sys_fstat(fd, &mut st) } != 0 || st.st_mode & S_IFMT != S_IFREG {
+        return;
+    }
+    let Ok(file) = page_cache::open(path) else {
+        return;
+    };
+    if flags & O_TRUNC != 0 {
+        // The backing file has been truncated already, but other openers
+        // may have cached its old data.
+        let _ = file.truncate(0);
+    }
+    page_cache::bind_fd(
+        fd,
+        OpenFile {
+            file,
+            append: flags & O_APPEND != 0,
+        },
+    );
+}
+
+/// Open a file descriptor for a file that the file descriptor table cannot
+/// hold. The descriptor refers to `/dev/null`, and the caller binds it to the
+/// real file.
+fn open_placeholder() -> LinuxResult<i32> {
+    let fd = api::sys_open(c"/dev/null".as_ptr(), O_RDWR, 0);
+    if fd < 0 {
+        return Err(LinuxError::EMFILE);
+    }
+    Ok(fd)
+}
+
+/// Open a file descriptor for a file that has no backing file. All its reads
+/// and writes go through the page cache.
+fn open_anonymous(file: Arc<CachedFile>, flags: i32) -> LinuxResult<i32> {
+    let fd = open_placeholder()?;
+    page_cache::bind_fd(
+        fd,
+        OpenFile {
+            file,
+            append: flags & O_APPEND != 0,
+        },
+    );
+    Ok(fd)
+}
+
+/// Check that the seals of `file` allow it to be resized to `size`.
+fn check_resize(file: &CachedFile, size: usize) -> LinuxResult {
+    let seals = file.seals();
+    let current = file.size();
+    if (size < current && seals.contains(Seals::SHRINK))
+        || (size > current && seals.contains(Seals::GROW))
+    {
+        return Err(LinuxError::EPERM);
+    }
+    Ok(())
+}
+
+/// Open a file in the tmpfs at `/dev/shm`, as done by `shm_open`.
+fn open_tmpfs(path: &str, flags: i32) -> LinuxResult<i32> {
+    let create = flags & O_CREAT != 0;
+    let file = page_cache::open_tmpfs(path, create, create && flags & O_EXCL != 0)?;
+    if flags & O_TRUNC != 0 {
+        check_resize(&file, 0)?;
+        file.truncate(0)?;
+    }
+    open_anonymous(file, flags)
+}
+
+/// Open an app in the read-only `/initrd/bin`.
+fn open_initrd(path: &str, flags: i32) -> LinuxResult<i32> {
+    let file = match page_cache::open(path) {
+        Ok(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(LinuxError::EEXIST),
+        Ok(file) => file,
+        Err(_) if flags & O_CREAT != 0 => return Err(LinuxError::EROFS),
+        Err(_) => return Err(LinuxError::ENOENT),
+    };
+    if flags & O_ACCMODE != O_RDONLY || flags & O_TRUNC != 0 {
+        return Err(LinuxError::EROFS);
+    }
+    let fd = open_placeholder()?;
+    page_cache::bind_fd(
+        fd,
+        OpenFile {
+            file,
+            append: false,
+        },
+    );
+    Ok(fd)
+}
+
+/// Open a file in `/proc`.
+fn open_proc(path: &str) -> LinuxResult<i32> {
+    let file = procfs::lookup(path)?;
+    let fd = open_placeholder()?;
+    procfs::bind_fd(fd, file);
+    Ok(fd)
+}
+
+/// Open the directory at `path`, whose descriptor is bound to its path.
+fn open_dir(path: String, flags: i32) -> LinuxResult<i32> {
+    if flags & O_ACCMODE != O_RDONLY || flags & O_CREAT != 0 {
+        return Err(LinuxError::EISDIR);
+    }
+    let fd = open_placeholder()?;
+    vfs::bind_dir(fd, path);
+    Ok(fd)
+}
+
+/// Open the file at `path` in the file system.
+fn open_file(path: &str, flags: i32, mode: mode_t) -> i32 {
+    let Ok(c_path) = CString::new(path) else {
+        return -LinuxError::EINVAL.code();
+    };
+    let fd = api::sys_open(c_path.as_ptr(), flags, mode);
+    if fd >= 0 {
+        bind_page_cache(fd, path, flags);
+    }
+    fd
+}
+
 pub(crate) fn sys_openat(dfd: i32, filename: *const c_char, flags: i32, mode: mode_t) -> i32 {
-    match dfd {
-        AT_FDCWD => api::sys_open(filename, flags, mode),
-        ..0 => -LinuxError::EBADF.code(),
-        _ => api::sys_open(filename, flags, mode),
+    let path = match path_at(dfd, filename) {
+        Ok(path) => path,
+        Err(e) => return -e.code(),
+    };
+    let fd = if page_cache::in_tmpfs(&path) {
+        syscall_body!(sys_openat, open_tmpfs(&path, flags))
+    } else if page_cache::in_initrd(&path) {
+        syscall_body!(sys_openat, open_initrd(&path, flags))
+    } else if procfs::is_proc_path(&path) {
+        syscall_body!(sys_openat, open_proc(&path))
+    } else if vfs::is_dir(&path) {
+        syscall_body!(sys_openat, open_dir(path, flags))
+    } else {
+        open_file(&path, flags, mode)
+    };
+    if fd >= 0 {
+        set_cloexec(fd, flags & O_CLOEXEC != 0);
+    }
+    fd
+}
+
+/// Create a pipe, and write the descriptors of its read and write ends to
+/// `fds`.
+pub(crate) fn sys_pipe2(fds: *mut i32, flags: i32) -> i32 {
+    syscall_body!(sys_pipe2, {
+        if flags & !(O_CLOEXEC | O_NONBLOCK) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        if fds.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let mut pipe = [0; 2];
+        let ret = api::sys_pipe(&mut pipe);
+        if ret < 0 {
+            return Err(LinuxError::try_from(-ret).unwrap_or(LinuxError::EMFILE));
+        }
+        for fd in pipe {
+            set_cloexec(fd, flags & O_CLOEXEC != 0);
+            if flags & O_NONBLOCK != 0 {
+                api::sys_fcntl(fd, F_SETFL, O_NONBLOCK as usize);
+            }
+        }
+        if let Err(e) = uaccess::write_user(fds.cast::<[i32; 2]>(), pipe) {
+            for fd in pipe {
+                sys_close(fd);
+            }
+            return Err(e);
+        }
+        Ok(0)
+    })
+}
+
+/// Make the duplicate `new_fd` of `fd` refer to the same page cache or
+/// `/proc` file, or directory.
+fn copy_bindings(fd: i32, new_fd: i32) {
+    page_cache::unbind_fd(new_fd);
+    procfs::unbind_fd(new_fd);
+    vfs::copy_binding(fd, new_fd);
+    if let Some(file) = page_cache::fd_file(fd) {
+        page_cache::bind_fd(new_fd, file);
+    }
+    if let Some(file) = procfs::fd_file(fd) {
+        procfs::bind_fd(new_fd, file);
     }
 }
 
 pub(crate) fn sys_close(fd: i32) -> i32 {
+    page_cache::unbind_fd(fd);
+    procfs::unbind_fd(fd);
+    vfs::unbind_fd(fd);
+    set_cloexec(fd, false);
     api::sys_close(fd)
 }
 
 pub(crate) fn sys_dup(fd: i32) -> i32 {
-    api::sys_dup(fd)
+    let new_fd = api::sys_dup(fd);
+    if new_fd >= 0 {
+        copy_bindings(fd, new_fd);
+        set_cloexec(new_fd, false);
+    }
+    new_fd
 }
 
 pub(crate) fn sys_dup3(oldfd: i32, newfd: i32, flags: i32) -> i32 {
-    // TODO: check `flags` and set F_DUPFD_CLOEXEC if needed
-    api::sys_dup2(oldfd, newfd)
+    if flags & !O_CLOEXEC != 0 || oldfd == newfd {
+        return -LinuxError::EINVAL.code();
+    }
+    let ret = api::sys_dup2(oldfd, newfd);
+    if ret >= 0 {
+        copy_bindings(oldfd, newfd);
+        set_cloexec(newfd, flags & O_CLOEXEC != 0);
+    }
+    ret
+}
+
+pub(crate) fn sys_fcntl(fd: i32, cmd: i32, arg: usize) -> i32 {
+    match cmd {
+        F_ADD_SEALS => syscall_body!(sys_fcntl, {
+            let file = page_cache::fd_file(fd)
+                .filter(|file| file.file.is_anonymous())
+                .ok_or(LinuxError::EINVAL)?;
+            let seals = Seals::from_bits(arg as u32).ok_or(LinuxError::EINVAL)?;
+            // Writes through shared mappings that exist cannot be stopped.
+            if seals.contains(Seals::WRITE)
+                && !file.file.seals().contains(Seals::SEAL)
+                && file.file.has_writable_mappings()
+            {
+                return Err(LinuxError::EBUSY);
+            }
+            if !file.file.add_seals(seals) {
+                return Err(LinuxError::EPERM);
+            }
+            Ok(0)
+        }),
+        F_GET_SEALS => syscall_body!(sys_fcntl, {
+            let file = page_cache::fd_file(fd)
+                .filter(|file| file.file.is_anonymous())
+                .ok_or(LinuxError::EINVAL)?;
+            Ok(file.file.seals().bits())
+        }),
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            let new_fd = api::sys_fcntl(fd, cmd, arg);
+            if new_fd >= 0 {
+                copy_bindings(fd, new_fd);
+                set_cloexec(new_fd, cmd == F_DUPFD_CLOEXEC);
+            }
+            new_fd
+        }
+        F_GETFD | F_SETFD => syscall_body!(sys_fcntl, {
+            check_fd(fd)?;
+            if cmd == F_SETFD {
+                set_cloexec(fd, arg & FD_CLOEXEC != 0);
+                return Ok(0);
+            }
+            Ok(if is_cloexec(fd) { FD_CLOEXEC as i32 } else { 0 })
+        }),
+        _ => api::sys_fcntl(fd, cmd, arg),
+    }
+}
+
+pub(crate) fn sys_ftruncate(fd: i32, length: isize) -> i32 {
+    syscall_body!(sys_ftruncate, {
+        let size = usize::try_from(length).map_err(|_| LinuxError::EINVAL)?;
+        let file = page_cache::fd_file(fd).ok_or(LinuxError::EINVAL)?;
+        check_resize(&file.file, size)?;
+        file.file.truncate(size)?;
+        Ok(0)
+    })
+}
+
+/// Create an anonymous file that lives only in memory.
+pub(crate) fn sys_memfd_create(name: *const c_char, flags: u32) -> i32 {
+    syscall_body!(sys_memfd_create, {
+        if flags & !(MFD_CLOEXEC | MFD_ALLOW_SEALING) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        uaccess::read_user_str(name, MFD_NAME_MAX_LEN).map_err(|e| match e {
+            LinuxError::ENAMETOOLONG => LinuxError::EINVAL,
+            e => e,
+        })?;
+        let seals = if flags & MFD_ALLOW_SEALING != 0 {
+            Seals::empty()
+        } else {
+            Seals::SEAL
+        };
+        let fd = open_anonymous(Arc::new(CachedFile::new_anonymous(0, seals)), O_RDWR)?;
+        set_cloexec(fd, flags & MFD_CLOEXEC != 0);
+        Ok(fd)
+    })
 }
@@ -1,7 +1,190 @@
+use alloc::{ffi::CString, string::String, vec::Vec};
 use core::ffi::c_char;
 
-use arceos_posix_api::{self as api, ctypes::mode_t};
+use arceos_posix_api::{self as api, ctypes, ctypes::mode_t};
+use axerrno::{LinuxError, LinuxResult};
+use axtask::{current, TaskExtRef};
+
+use crate::mm::page_cache;
+use crate::mm::uaccess::{self, PATH_MAX};
+use crate::{syscall_body, vfs};
+
+/// Remove a directory instead of a file.
+const AT_REMOVEDIR: i32 = 0x200;
+/// Operate on `dirfd` itself if the path is empty.
+const AT_EMPTY_PATH: i32 = 0x1000;
+
+const S_IFREG: u32 = 0o100000;
+
+/// Get the normalized absolute path of the file `pathname` names relative
+/// to `dirfd`.
+pub(crate) fn path_at(dirfd: i32, pathname: *const c_char) -> LinuxResult<String> {
+    let path = uaccess::read_user_str(pathname, PATH_MAX - 1)?;
+    vfs::resolve_at(dirfd, &path)
+}
 
 pub fn sys_mkdirat(dfd: i32, pathname: *const c_char, mode: mode_t) -> isize {
-    api::sys_mkdirat(dfd, pathname, mode) as _
+    syscall_body!(sys_mkdirat, {
+        let path = uaccess::read_user_str(pathname, PATH_MAX - 1)?;
+        let path = CString::new(path).map_err(|_| LinuxError::EINVAL)?;
+        Ok(api::sys_mkdirat(dfd, path.as_ptr(), mode))
+    })
+}
+
+pub(crate) fn sys_unlinkat(dfd: i32, pathname: *const c_char, flags: i32) -> i32 {
+    syscall_body!(sys_unlinkat, {
+        let path = path_at(dfd, pathname)?;
+        if page_cache::in_initrd(&path) {
+            return Err(LinuxError::EROFS);
+        } else if page_cache::in_tmpfs(&path) {
+            if !page_cache::remove(&path) {
+                return Err(LinuxError::ENOENT);
+            }
+        } else if flags & AT_REMOVEDIR != 0 {
+            axstd::fs::remove_dir(&path)?;
+        } else {
+            axstd::fs::remove_file(&path)?;
+            page_cache::remove(&path);
+        }
+        Ok(0)
+    })
+}
+
+/// Get the status of the file open as `fd`.
+pub(crate) fn fstat(fd: i32) -> LinuxResult<ctypes::stat> {
+    if let Some(dir) = vfs::fd_dir(fd) {
+        return vfs::stat(&dir.path);
+    }
+    let mut st: ctypes::stat = unsafe { core::mem::zeroed() };
+    let ret = unsafe { api::sys_fstat(fd, &mut st) };
+    if ret < 0 {
+        return Err(LinuxError::try_from(-ret).unwrap_or(LinuxError::EBADF));
+    }
+    if let Some(file) = page_cache::fd_file(fd) {
+        // The page cache may hold data that has not been written back yet.
+        let size = file.file.size();
+        st.st_size = size as _;
+        st.st_blocks = size.div_ceil(512) as _;
+        if file.file.is_anonymous() {
+            st.st_mode = S_IFREG | 0o600;
+            st.st_rdev = 0;
+        } else if file.file.is_image() {
+            st.st_mode = S_IFREG | 0o555;
+            st.st_rdev = 0;
+        }
+    }
+    Ok(st)
+}
+
+/// Get the status of the file `pathname` names relative to `dirfd`, or of
+/// `dirfd` itself if the path is empty and `flags` has `AT_EMPTY_PATH`.
+pub(crate) fn fstatat(
+    dirfd: i32,
+    pathname: *const c_char,
+    flags: i32,
+) -> LinuxResult<ctypes::stat> {
+    let path = uaccess::read_user_str(pathname, PATH_MAX - 1)?;
+    if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        return fstat(dirfd);
+    }
+    vfs::stat(&vfs::resolve_at(dirfd, &path)?)
+}
+
+pub(crate) fn sys_fstat(fd: i32, statbuf: *mut ctypes::stat) -> i32 {
+    syscall_body!(sys_fstat, {
+        uaccess::write_user(statbuf, fstat(fd)?)?;
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_fstatat(
+    dirfd: i32,
+    pathname: *const c_char,
+    statbuf: *mut ctypes::stat,
+    flags: i32,
+) -> i32 {
+    syscall_body!(sys_fstatat, {
+        uaccess::write_user(statbuf, fstatat(dirfd, pathname, flags)?)?;
+        Ok(0)
+    })
+}
+
+/// Check for execute permission in `faccessat`.
+const X_OK: u32 = 1;
+/// Check for write permission in `faccessat`.
+const W_OK: u32 = 2;
+
+/// Check whether the process may access the file `pathname` names relative
+/// to `dirfd` as `mode` asks, with its real user ID.
+///
+/// Root may read and write any file, and execute a file if anyone may. Write
+/// access fails with `EROFS` on a read-only file system, like `/initrd`.
+pub(crate) fn sys_faccessat(dirfd: i32, pathname: *const c_char, mode: u32, _flags: i32) -> i32 {
+    syscall_body!(sys_faccessat, {
+        if mode & !0o7 != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let path = path_at(dirfd, pathname)?;
+        let st = vfs::stat(&path)?;
+        if mode & W_OK != 0 && vfs::is_read_only(&path) {
+            return Err(LinuxError::EROFS);
+        }
+        let uid = current().task_ext().creds.lock().uid;
+        let allowed = if uid == 0 {
+            mode & X_OK == 0 || st.st_mode & 0o111 != 0
+        } else {
+            let perm = if st.st_uid == uid {
+                st.st_mode >> 6
+            } else {
+                st.st_mode
+            };
+            mode & !perm & 0o7 == 0
+        };
+        if !allowed {
+            return Err(LinuxError::EACCES);
+        }
+        Ok(0)
+    })
+}
+
+/// The size of the fixed part of `struct linux_dirent64`: `d_ino`, `d_off`,
+/// `d_reclen` and `d_type`.
+const DIRENT64_HEADER_SIZE: usize = 19;
+
+/// Read the entries of the directory open as `fd` into `buf`, as
+/// `struct linux_dirent64` records. Returns the number of bytes read, 0 at
+/// the end of the directory.
+pub(crate) fn sys_getdents64(fd: i32, buf: *mut u8, len: usize) -> isize {
+    syscall_body!(sys_getdents64, {
+        let dir = vfs::fd_dir(fd).ok_or_else(|| match super::check_fd(fd) {
+            Ok(()) => LinuxError::ENOTDIR,
+            Err(e) => e,
+        })?;
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let entries = vfs::read_dir(&dir.path)?;
+        let mut pos = dir.pos();
+        let mut data = Vec::new();
+        for (name, d_type) in entries.iter().skip(pos) {
+            let reclen = (DIRENT64_HEADER_SIZE + name.len() + 1).next_multiple_of(8);
+            if data.len() + reclen > len {
+                if data.is_empty() {
+                    return Err(LinuxError::EINVAL);
+                }
+                break;
+            }
+            let end = data.len() + reclen;
+            data.extend_from_slice(&dir.ino_of(name).to_ne_bytes());
+            data.extend_from_slice(&(pos as i64 + 1).to_ne_bytes());
+            data.extend_from_slice(&(reclen as u16).to_ne_bytes());
+            data.push(*d_type);
+            data.extend_from_slice(name.as_bytes());
+            data.resize(end, 0);
+            pos += 1;
+        }
+        uaccess::copy_to_user(buf, &data)?;
+        dir.set_pos(pos);
+        Ok(data.len())
+    })
 }
@@ -1,15 +1,171 @@
+use alloc::vec;
 use core::ffi::c_void;
 
 use arceos_posix_api as api;
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+
+use crate::mm::page_cache::{self, OpenFile, Seals};
+use crate::mm::uaccess;
+use crate::procfs::{self, ProcFile};
+use crate::{syscall_body, vfs};
+
+const SEEK_SET: i32 = 0;
+const SEEK_CUR: i32 = 1;
+const SEEK_END: i32 = 2;
+
+/// The maximum number of buffers in an I/O vector.
+const IOV_MAX: usize = 1024;
+
+/// Get the file offset of `fd`, which is kept by the file descriptor table.
+fn file_offset(fd: i32) -> LinuxResult<usize> {
+    usize::try_from(api::sys_lseek(fd, 0, SEEK_CUR)).map_err(|_| LinuxError::ESPIPE)
+}
+
+fn set_file_offset(fd: i32, offset: usize) {
+    api::sys_lseek(fd, offset as _, SEEK_SET);
+}
+
+fn cached_read(fd: i32, file: &OpenFile, buf: *mut u8, count: usize) -> LinuxResult<isize> {
+    let offset = file_offset(fd)?;
+    // Read into a kernel buffer first, so that faults on `buf` are not taken
+    // while the page cache is locked.
+    let mut data = vec![0; count.min(file.file.size().saturating_sub(offset))];
+    let len = file.file.read_at(offset, &mut data)?;
+    uaccess::copy_to_user(buf, &data[..len])?;
+    set_file_offset(fd, offset + len);
+    Ok(len as isize)
+}
+
+fn cached_write(fd: i32, file: &OpenFile, buf: *const u8, count: usize) -> LinuxResult<isize> {
+    let data = uaccess::read_user_slice(buf, count)?;
+    let size = file.file.size();
+    let offset = if file.append { size } else { file_offset(fd)? };
+    let seals = file.file.seals();
+    if seals.intersects(Seals::WRITE | Seals::FUTURE_WRITE)
+        || (seals.contains(Seals::GROW) && offset.saturating_add(count) > size)
+    {
+        return Err(LinuxError::EPERM);
+    }
+    let len = file.file.write_at(offset, &data)?;
+    set_file_offset(fd, offset + len);
+    Ok(len as isize)
+}
+
+fn proc_read(fd: i32, file: &ProcFile, buf: *mut u8, count: usize) -> LinuxResult<isize> {
+    let offset = file_offset(fd)?;
+    let data = file.read()?;
+    let data = data.get(offset..).unwrap_or_default();
+    let len = count.min(data.len());
+    uaccess::copy_to_user(buf, &data[..len])?;
+    set_file_offset(fd, offset + len);
+    Ok(len as isize)
+}
+
+fn proc_write(file: &ProcFile, buf: *const u8, count: usize) -> LinuxResult<isize> {
+    let data = uaccess::read_user_slice(buf, count)?;
+    Ok(file.write(&data)? as isize)
+}
 
 pub(crate) fn sys_read(fd: i32, buf: *mut c_void, count: usize) -> isize {
-    api::sys_read(fd, buf, count)
+    if let Some(file) = procfs::fd_file(fd) {
+        return syscall_body!(sys_read, proc_read(fd, &file, buf as _, count));
+    }
+    match page_cache::fd_file(fd) {
+        Some(file) => syscall_body!(sys_read, cached_read(fd, &file, buf as _, count)),
+        None => syscall_body!(sys_read, {
+            // The file systems and pipes copy to `buf` directly.
+            uaccess::check_user(buf as _, count, MappingFlags::WRITE)?;
+            Ok(api::sys_read(fd, buf, count))
+        }),
+    }
 }
 
 pub(crate) fn sys_write(fd: i32, buf: *const c_void, count: usize) -> isize {
-    api::sys_write(fd, buf, count)
+    if let Some(file) = procfs::fd_file(fd) {
+        return syscall_body!(sys_write, proc_write(&file, buf as _, count));
+    }
+    match page_cache::fd_file(fd) {
+        Some(file) => syscall_body!(sys_write, cached_write(fd, &file, buf as _, count)),
+        None => syscall_body!(sys_write, {
+            uaccess::check_user(buf as _, count, MappingFlags::READ)?;
+            Ok(api::sys_write(fd, buf, count))
+        }),
+    }
+}
+
+/// Write the buffers of `iovs` to `fd`.
+pub(crate) fn writev(fd: i32, iovs: &[api::ctypes::iovec]) -> LinuxResult<isize> {
+    match page_cache::fd_file(fd) {
+        Some(file) => {
+            let mut written = 0;
+            for iov in iovs {
+                written += cached_write(fd, &file, iov.iov_base as _, iov.iov_len as _)?;
+            }
+            Ok(written)
+        }
+        None => {
+            for iov in iovs {
+                uaccess::check_user(iov.iov_base as _, iov.iov_len as _, MappingFlags::READ)?;
+            }
+            let ret = unsafe { api::sys_writev(fd, iovs.as_ptr(), iovs.len() as _) };
+            if ret < 0 {
+                return Err(LinuxError::try_from(-ret as i32).unwrap_or(LinuxError::EINVAL));
+            }
+            Ok(ret)
+        }
+    }
 }
 
 pub(crate) fn sys_writev(fd: i32, iov: *const api::ctypes::iovec, iocnt: i32) -> isize {
-    unsafe { api::sys_writev(fd, iov, iocnt) }
+    syscall_body!(sys_writev, {
+        let iocnt = usize::try_from(iocnt)
+            .ok()
+            .filter(|&iocnt| iocnt <= IOV_MAX)
+            .ok_or(LinuxError::EINVAL)?;
+        writev(fd, &uaccess::read_user_slice(iov, iocnt)?)
+    })
+}
+
+/// Move the file offset of `fd` and return the new one.
+///
+/// The end of a file in the page cache is where the cache says, as its data
+/// may not have been written back yet. The offset of a directory is the
+/// index of the next entry `getdents64` returns.
+pub(crate) fn sys_lseek(fd: i32, offset: isize, whence: i32) -> isize {
+    if let Some(dir) = vfs::fd_dir(fd) {
+        return syscall_body!(sys_lseek, {
+            let pos = match whence {
+                SEEK_SET => Some(offset),
+                SEEK_CUR => (dir.pos() as isize).checked_add(offset),
+                _ => return Err(LinuxError::EINVAL),
+            };
+            let pos = pos
+                .and_then(|pos| usize::try_from(pos).ok())
+                .ok_or(LinuxError::EINVAL)?;
+            dir.set_pos(pos);
+            Ok(pos as isize)
+        });
+    }
+    match page_cache::fd_file(fd) {
+        Some(file) if whence == SEEK_END => syscall_body!(sys_lseek, {
+            let pos = (file.file.size() as isize)
+                .checked_add(offset)
+                .and_then(|pos| usize::try_from(pos).ok())
+                .ok_or(LinuxError::EINVAL)?;
+            set_file_offset(fd, pos);
+            Ok(pos as isize)
+        }),
+        _ => api::sys_lseek(fd, offset as _, whence) as _,
+    }
+}
+
+/// Write the dirty page cache pages of `fd` back to the file.
+pub(crate) fn sys_fsync(fd: i32) -> i32 {
+    syscall_body!(sys_fsync, {
+        if let Some(file) = page_cache::fd_file(fd) {
+            file.file.sync()?;
+        }
+        Ok(0)
+    })
 }
@@ -0,0 +1,625 @@
+//! System calls of 32-bit x86 programs, made with `int 0x80`.
+//!
+//! They have their own numbering, and take their arguments in `ebx`, `ecx`,
+//! `edx`, `esi`, `edi` and `ebp`. They are translated to the 64-bit system
+//! calls, converting the structures whose layout differs.
+
+use alloc::vec::Vec;
+use core::ffi::{c_char, c_void};
+
+use arceos_posix_api::ctypes::{self, pid_t, AT_FDCWD};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::arch::TrapFrame;
+use axtask::{current, TaskExtRef};
+use memory_addr::{MemoryAddr, VirtAddr, PAGE_SIZE_4K};
+use num_enum::TryFromPrimitive;
+
+use super::fs::*;
+use super::mm::*;
+use super::sys::*;
+use super::task::*;
+use super::time::*;
+use crate::ia32::UserDesc;
+use crate::mm::{uaccess, COMPAT_TASK_SIZE};
+use crate::syscall_body;
+use crate::task::{Rlimit, RLIM_INFINITY};
+
+/// The system calls of 32-bit x86 programs that are supported.
+///
+/// See <https://github.com/torvalds/linux/blob/master/arch/x86/entry/syscalls/syscall_32.tbl>
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
+#[repr(u32)]
+enum Sysno32 {
+    exit = 1,
+    read = 3,
+    write = 4,
+    open = 5,
+    close = 6,
+    waitpid = 7,
+    unlink = 10,
+    execve = 11,
+    chdir = 12,
+    lseek = 19,
+    getpid = 20,
+    access = 33,
+    mkdir = 39,
+    dup = 41,
+    pipe = 42,
+    times = 43,
+    brk = 45,
+    ioctl = 54,
+    fcntl = 55,
+    dup2 = 63,
+    getppid = 64,
+    setrlimit = 75,
+    getrlimit = 76,
+    getrusage = 77,
+    gettimeofday = 78,
+    mmap = 90,
+    munmap = 91,
+    ftruncate = 93,
+    wait4 = 114,
+    fsync = 118,
+    clone = 120,
+    uname = 122,
+    mprotect = 125,
+    personality = 136,
+    _llseek = 140,
+    writev = 146,
+    fdatasync = 148,
+    sched_yield = 158,
+    nanosleep = 162,
+    getcwd = 183,
+    ugetrlimit = 191,
+    mmap2 = 192,
+    ftruncate64 = 194,
+    stat64 = 195,
+    lstat64 = 196,
+    fstat64 = 197,
+    getuid32 = 199,
+    geteuid32 = 201,
+    setreuid32 = 203,
+    setuid32 = 213,
+    mincore = 218,
+    madvise = 219,
+    getdents64 = 220,
+    fcntl64 = 221,
+    set_thread_area = 243,
+    exit_group = 252,
+    set_tid_address = 258,
+    clock_gettime = 265,
+    clock_nanosleep = 267,
+    openat = 295,
+    mkdirat = 296,
+    fstatat64 = 300,
+    unlinkat = 301,
+    faccessat = 307,
+    getcpu = 318,
+    dup3 = 330,
+    pipe2 = 331,
+    prlimit64 = 340,
+    memfd_create = 356,
+    execveat = 358,
+    clock_gettime64 = 403,
+    clock_nanosleep_time64 = 407,
+}
+
+/// Place the mapping exactly at the given address.
+const MAP_FIXED: i32 = 0x10;
+/// The maximum number of buffers of `writev`.
+const IOV_MAX: usize = 1024;
+/// The value of an unlimited resource limit for 32-bit programs.
+const RLIM32_INFINITY: u32 = u32::MAX;
+/// The value of an unlimited resource limit reported by the old `getrlimit`.
+const OLD_RLIM32_INFINITY: u32 = i32::MAX as u32;
+
+/// The pointer in the argument `arg`.
+fn ptr<T>(arg: u32) -> *mut T {
+    arg as usize as *mut T
+}
+
+/// `struct timespec` of 32-bit programs, with 32-bit seconds.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Timespec32 {
+    tv_sec: i32,
+    tv_nsec: i32,
+}
+
+impl From<Timespec32> for ctypes::timespec {
+    fn from(ts: Timespec32) -> Self {
+        Self {
+            tv_sec: ts.tv_sec as _,
+            tv_nsec: ts.tv_nsec as _,
+        }
+    }
+}
+
+impl TryFrom<ctypes::timespec> for Timespec32 {
+    type Error = LinuxError;
+
+    /// Fails with `EOVERFLOW` after 2038.
+    fn try_from(ts: ctypes::timespec) -> LinuxResult<Self> {
+        Ok(Self {
+            tv_sec: ts.tv_sec.try_into().map_err(|_| LinuxError::EOVERFLOW)?,
+            tv_nsec: ts.tv_nsec as _,
+        })
+    }
+}
+
+/// `struct timeval` of 32-bit programs, with 32-bit seconds.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Timeval32 {
+    tv_sec: i32,
+    tv_usec: i32,
+}
+
+/// `struct tms` of 32-bit programs, with a 32-bit `clock_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Tms32 {
+    tms_utime: i32,
+    tms_stime: i32,
+    tms_cutime: i32,
+    tms_cstime: i32,
+}
+
+/// `struct iovec` of 32-bit programs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Iovec32 {
+    iov_base: u32,
+    iov_len: u32,
+}
+
+/// `struct stat64` of 32-bit x86 programs.
+///
+/// See <https://github.com/torvalds/linux/blob/master/arch/x86/include/uapi/asm/stat.h>
+#[repr(C, packed)]
+#[derive(Default, Clone, Copy)]
+struct Stat64 {
+    st_dev: u64,
+    __pad0: [u8; 4],
+    /// The inode number truncated to 32 bits.
+    __st_ino: u32,
+    st_mode: u32,
+    st_nlink: u32,
+    st_uid: u32,
+    st_gid: u32,
+    st_rdev: u64,
+    __pad3: [u8; 4],
+    st_size: i64,
+    st_blksize: u32,
+    st_blocks: u64,
+    st_atime: u32,
+    st_atime_nsec: u32,
+    st_mtime: u32,
+    st_mtime_nsec: u32,
+    st_ctime: u32,
+    st_ctime_nsec: u32,
+    st_ino: u64,
+}
+
+const _: () = assert!(size_of::<Stat64>() == 96);
+
+impl From<ctypes::stat> for Stat64 {
+    fn from(st: ctypes::stat) -> Self {
+        Self {
+            st_dev: st.st_dev as _,
+            __st_ino: st.st_ino as _,
+            st_mode: st.st_mode as _,
+            st_nlink: st.st_nlink as _,
+            st_uid: st.st_uid as _,
+            st_gid: st.st_gid as _,
+            st_rdev: st.st_rdev as _,
+            st_size: st.st_size as _,
+            st_blksize: st.st_blksize as _,
+            st_blocks: st.st_blocks as _,
+            st_atime: st.st_atime.tv_sec as _,
+            st_atime_nsec: st.st_atime.tv_nsec as _,
+            st_mtime: st.st_mtime.tv_sec as _,
+            st_mtime_nsec: st.st_mtime.tv_nsec as _,
+            st_ctime: st.st_ctime.tv_sec as _,
+            st_ctime_nsec: st.st_ctime.tv_nsec as _,
+            st_ino: st.st_ino as _,
+            ..Default::default()
+        }
+    }
+}
+
+/// `struct rlimit` of 32-bit programs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Rlimit32 {
+    rlim_cur: u32,
+    rlim_max: u32,
+}
+
+/// `struct rusage` of 32-bit programs, with 32-bit `timeval`s and `long`s.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct Rusage32 {
+    ru_utime: [i32; 2],
+    ru_stime: [i32; 2],
+    /// `ru_maxrss` to `ru_nivcsw`.
+    counters: [i32; 14],
+}
+
+impl From<Rusage> for Rusage32 {
+    fn from(ru: Rusage) -> Self {
+        let counters = [
+            ru.ru_maxrss,
+            ru.ru_ixrss,
+            ru.ru_idrss,
+            ru.ru_isrss,
+            ru.ru_minflt,
+            ru.ru_majflt,
+            ru.ru_nswap,
+            ru.ru_inblock,
+            ru.ru_oublock,
+            ru.ru_msgsnd,
+            ru.ru_msgrcv,
+            ru.ru_nsignals,
+            ru.ru_nvcsw,
+            ru.ru_nivcsw,
+        ];
+        Self {
+            ru_utime: ru.ru_utime.map(|v| v as i32),
+            ru_stime: ru.ru_stime.map(|v| v as i32),
+            counters: counters.map(|v| v as i32),
+        }
+    }
+}
+
+/// The arguments of the old `mmap`, which takes them in memory.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct MmapArgs32 {
+    addr: u32,
+    len: u32,
+    prot: u32,
+    flags: u32,
+    fd: u32,
+    offset: u32,
+}
+
+/// Map memory like `mmap`, within the address space of 32-bit programs.
+///
+/// Other mappings are placed below the stack, which is there already.
+fn sys_mmap32(addr: u32, length: u32, prot: i32, flags: i32, fd: i32, offset: u64) -> usize {
+    if flags & MAP_FIXED != 0 && addr as usize + length as usize > COMPAT_TASK_SIZE {
+        return -LinuxError::ENOMEM.code() as _;
+    }
+    let Ok(offset) = isize::try_from(offset) else {
+        return -LinuxError::EINVAL.code() as _;
+    };
+    sys_mmap(ptr(addr), length as _, prot, flags, fd, offset)
+}
+
+/// The old `mmap`, with the arguments at `args`.
+fn sys_old_mmap(args: *const MmapArgs32) -> usize {
+    let args = match uaccess::read_user(args) {
+        Ok(args) => args,
+        Err(e) => return -e.code() as _,
+    };
+    if !(args.offset as usize).is_aligned_4k() {
+        return -LinuxError::EINVAL.code() as _;
+    }
+    sys_mmap32(
+        args.addr,
+        args.len,
+        args.prot as _,
+        args.flags as _,
+        args.fd as _,
+        args.offset as _,
+    )
+}
+
+fn sys_writev32(fd: i32, iov: *const Iovec32, iocnt: i32) -> isize {
+    syscall_body!(sys_writev32, {
+        let count = usize::try_from(iocnt)
+            .ok()
+            .filter(|&count| count <= IOV_MAX)
+            .ok_or(LinuxError::EINVAL)?;
+        let iovs: Vec<_> = uaccess::read_user_slice(iov, count)?
+            .into_iter()
+            .map(|iov| ctypes::iovec {
+                iov_base: ptr(iov.iov_base),
+                iov_len: iov.iov_len as _,
+            })
+            .collect();
+        writev(fd, &iovs)
+    })
+}
+
+fn sys_fstat64(fd: i32, statbuf: *mut Stat64) -> i32 {
+    syscall_body!(sys_fstat64, {
+        uaccess::write_user(statbuf, Stat64::from(fstat(fd)?))?;
+        Ok(0)
+    })
+}
+
+fn sys_fstatat64(dirfd: i32, pathname: *const c_char, statbuf: *mut Stat64, flags: i32) -> i32 {
+    syscall_body!(sys_fstatat64, {
+        let st = fstatat(dirfd, pathname, flags)?;
+        uaccess::write_user(statbuf, Stat64::from(st))?;
+        Ok(0)
+    })
+}
+
+/// `lseek` with a 32-bit offset, which fails with `EOVERFLOW` if the new
+/// offset does not fit.
+fn sys_lseek32(fd: i32, offset: i32, whence: i32) -> isize {
+    let pos = sys_lseek(fd, offset as _, whence);
+    if pos > i32::MAX as isize {
+        return -LinuxError::EOVERFLOW.code() as _;
+    }
+    pos
+}
+
+/// `lseek` with a 64-bit offset in two halves, which writes the new offset
+/// to `result`.
+fn sys_llseek(fd: i32, offset_high: u32, offset_low: u32, result: *mut i64, whence: i32) -> i32 {
+    let offset = ((offset_high as u64) << 32 | offset_low as u64) as i64;
+    let pos = sys_lseek(fd, offset as _, whence);
+    if pos < 0 {
+        return pos as _;
+    }
+    // `loff_t` is only 4-byte aligned for 32-bit programs, which the copy
+    // does not mind.
+    syscall_body!(sys_llseek, {
+        uaccess::write_user(result, pos as i64)?;
+        Ok(0)
+    })
+}
+
+fn sys_gettimeofday32(tv: *mut Timeval32, _tz: *mut c_void) -> i32 {
+    syscall_body!(sys_gettimeofday32, {
+        if tv.is_null() {
+            return Ok(0);
+        }
+        let now = wall_timeval();
+        let tv_now = Timeval32 {
+            tv_sec: i32::try_from(now.tv_sec).map_err(|_| LinuxError::EOVERFLOW)?,
+            tv_usec: now.tv_usec as _,
+        };
+        uaccess::write_user(tv, tv_now)?;
+        Ok(0)
+    })
+}
+
+fn sys_times32(buf: *mut Tms32) -> isize {
+    syscall_body!(sys_times32, {
+        if !buf.is_null() {
+            let tms = Tms::default();
+            let tms = Tms32 {
+                tms_utime: tms.tms_utime as _,
+                tms_stime: tms.tms_stime as _,
+                tms_cutime: tms.tms_cutime as _,
+                tms_cstime: tms.tms_cstime as _,
+            };
+            uaccess::write_user(buf, tms)?;
+        }
+        // `clock_t` wraps around for 32-bit programs.
+        Ok(clock_ticks() as i32 as isize)
+    })
+}
+
+fn sys_clock_gettime32(clock_id: i32, tp: *mut Timespec32) -> i32 {
+    syscall_body!(sys_clock_gettime32, {
+        let ts = Timespec32::try_from(clock_gettime(clock_id)?)?;
+        uaccess::write_user(tp, ts)?;
+        Ok(0)
+    })
+}
+
+fn sys_clock_nanosleep32(
+    clock_id: i32,
+    flags: i32,
+    req: *const Timespec32,
+    rem: *mut Timespec32,
+) -> i32 {
+    syscall_body!(sys_clock_nanosleep32, {
+        let dur = clock_sleep_duration(clock_id, flags, uaccess::read_user(req)?.into())?;
+        let Some(left) = nanosleep(dur) else {
+            return Ok(0);
+        };
+        if flags & TIMER_ABSTIME == 0 && !rem.is_null() {
+            let left = Timespec32 {
+                tv_sec: left.as_secs() as _,
+                tv_nsec: left.subsec_nanos() as _,
+            };
+            uaccess::write_user(rem, left)?;
+        }
+        Err(LinuxError::EINTR)
+    })
+}
+
+fn sys_nanosleep32(req: *const Timespec32, rem: *mut Timespec32) -> i32 {
+    sys_clock_nanosleep32(CLOCK_MONOTONIC, 0, req, rem)
+}
+
+/// Get a resource limit, reporting limits that do not fit in 32 bits as
+/// `infinity`.
+fn sys_getrlimit32(resource: u32, rlim: *mut Rlimit32, infinity: u32) -> i32 {
+    syscall_body!(sys_getrlimit32, {
+        let limit = prlimit(0, resource, None)?;
+        let narrow =
+            |value: u64| u32::try_from(value).map_or(infinity, |value| value.min(infinity));
+        let limit = Rlimit32 {
+            rlim_cur: narrow(limit.rlim_cur),
+            rlim_max: narrow(limit.rlim_max),
+        };
+        uaccess::write_user(rlim, limit)?;
+        Ok(0)
+    })
+}
+
+fn sys_setrlimit32(resource: u32, rlim: *const Rlimit32) -> i32 {
+    syscall_body!(sys_setrlimit32, {
+        let limit = uaccess::read_user(rlim)?;
+        let widen = |value: u32| {
+            if value == RLIM32_INFINITY {
+                RLIM_INFINITY
+            } else {
+                value as u64
+            }
+        };
+        let limit = Rlimit {
+            rlim_cur: widen(limit.rlim_cur),
+            rlim_max: widen(limit.rlim_max),
+        };
+        prlimit(0, resource, Some(limit))?;
+        Ok(0)
+    })
+}
+
+/// `wait4` with the resource usage of 32-bit programs.
+fn sys_wait4_32(pid: pid_t, wstatus: *mut i32, options: i32, rusage: *mut Rusage32) -> i32 {
+    syscall_body!(sys_wait4_32, {
+        let (pid, status, usage) = wait4(pid, options)?;
+        if !wstatus.is_null() {
+            uaccess::write_user(wstatus, status)?;
+        }
+        if !rusage.is_null() {
+            uaccess::write_user(rusage, Rusage32::from(usage))?;
+        }
+        Ok(pid)
+    })
+}
+
+fn sys_getrusage32(who: i32, usage: *mut Rusage32) -> i32 {
+    syscall_body!(sys_getrusage32, {
+        uaccess::write_user(usage, Rusage32::from(getrusage(who)?))?;
+        Ok(0)
+    })
+}
+
+/// `dup2`, which 64-bit programs get from the C library on top of `dup3`.
+fn sys_dup2(oldfd: i32, newfd: i32) -> i32 {
+    if oldfd == newfd {
+        return match check_fd(oldfd) {
+            Ok(()) => oldfd,
+            Err(e) => -e.code(),
+        };
+    }
+    sys_dup3(oldfd, newfd, 0)
+}
+
+fn sys_set_thread_area(u_info: *mut UserDesc) -> i32 {
+    syscall_body!(sys_set_thread_area, {
+        current().task_ext().ia32.set_thread_area(u_info)?;
+        Ok(0)
+    })
+}
+
+/// Handle a system call of a 32-bit program.
+pub(super) fn do_handle_syscall(tf: &TrapFrame, syscall_num: usize) -> LinuxResult<isize> {
+    let Ok(sysno) = Sysno32::try_from(syscall_num as u32) else {
+        warn!("Unimplemented 32-bit syscall: {}", syscall_num);
+        crate::task::exit(LinuxError::ENOSYS as _)
+    };
+    // The upper halves of the registers are not part of the arguments.
+    let a = [tf.rbx, tf.rcx, tf.rdx, tf.rsi, tf.rdi, tf.rbp].map(|reg| reg as u32);
+    Ok(match sysno {
+        Sysno32::read => sys_read(a[0] as _, ptr(a[1]), a[2] as _),
+        Sysno32::write => sys_write(a[0] as _, ptr(a[1]), a[2] as _),
+        Sysno32::mmap => sys_old_mmap(ptr(a[0])) as _,
+        Sysno32::mmap2 => sys_mmap32(
+            a[0],
+            a[1],
+            a[2] as _,
+            a[3] as _,
+            a[4] as _,
+            a[5] as u64 * PAGE_SIZE_4K as u64,
+        ) as _,
+        Sysno32::munmap => sys_munmap(ptr(a[0]), a[1] as _) as _,
+        Sysno32::mprotect => sys_mprotect(ptr(a[0]), a[1] as _, a[2] as _) as _,
+        Sysno32::madvise => sys_madvise(a[0] as _, a[1] as _, a[2] as _) as _,
+        Sysno32::mincore => sys_mincore(a[0] as _, a[1] as _, ptr(a[2])) as _,
+        Sysno32::ioctl => sys_ioctl(a[0] as _, a[1] as _, ptr(a[2])) as _,
+        Sysno32::writev => sys_writev32(a[0] as _, ptr(a[1]), a[2] as _),
+        Sysno32::sched_yield => sys_sched_yield() as _,
+        Sysno32::getcpu => sys_getcpu(ptr(a[0]), ptr(a[1])) as _,
+        Sysno32::nanosleep => sys_nanosleep32(ptr(a[0]), ptr(a[1])) as _,
+        Sysno32::getpid => sys_getpid() as _,
+        Sysno32::getppid => sys_getppid() as _,
+        Sysno32::uname => sys_uname(ptr(a[0])) as _,
+        Sysno32::times => sys_times32(ptr(a[0])),
+        Sysno32::gettimeofday => sys_gettimeofday32(ptr(a[0]), ptr(a[1])) as _,
+        Sysno32::getuid32 => sys_getuid(),
+        Sysno32::geteuid32 => sys_geteuid(),
+        Sysno32::setuid32 => sys_setuid(a[0]),
+        Sysno32::setreuid32 => sys_setreuid(a[0], a[1]),
+        Sysno32::exit => sys_exit(a[0] as _),
+        Sysno32::set_thread_area => sys_set_thread_area(ptr(a[0])) as _,
+        Sysno32::personality => sys_personality(a[0]),
+        Sysno32::set_tid_address => sys_set_tid_address(ptr(a[0])),
+        Sysno32::exit_group => sys_exit_group(a[0] as _),
+        Sysno32::clock_gettime => sys_clock_gettime32(a[0] as _, ptr(a[1])) as _,
+        Sysno32::clock_gettime64 => sys_clock_gettime(a[0] as _, ptr(a[1])) as _,
+        Sysno32::clock_nanosleep => {
+            sys_clock_nanosleep32(a[0] as _, a[1] as _, ptr(a[2]), ptr(a[3])) as _
+        }
+        Sysno32::clock_nanosleep_time64 => {
+            sys_clock_nanosleep(a[0] as _, a[1] as _, ptr(a[2]), ptr(a[3])) as _
+        }
+        Sysno32::brk => sys_brk(VirtAddr::from_usize(a[0] as _)).as_usize() as _,
+        Sysno32::mkdir => sys_mkdirat(AT_FDCWD, ptr(a[0]), a[1] as _),
+        Sysno32::mkdirat => sys_mkdirat(a[0] as _, ptr(a[1]), a[2] as _),
+        Sysno32::chdir => sys_chdir(ptr(a[0])),
+        Sysno32::getcwd => sys_getcwd(ptr(a[0]), a[1] as _),
+        Sysno32::open => sys_openat(AT_FDCWD, ptr(a[0]), a[1] as _, a[2] as _) as _,
+        Sysno32::openat => sys_openat(a[0] as _, ptr(a[1]), a[2] as _, a[3] as _) as _,
+        Sysno32::close => sys_close(a[0] as _) as _,
+        Sysno32::unlink => sys_unlinkat(AT_FDCWD, ptr(a[0]), 0) as _,
+        Sysno32::unlinkat => sys_unlinkat(a[0] as _, ptr(a[1]), a[2] as _) as _,
+        Sysno32::fstat64 => sys_fstat64(a[0] as _, ptr(a[1])) as _,
+        // There are no symbolic links, so `lstat64` is `stat64`.
+        Sysno32::stat64 | Sysno32::lstat64 => sys_fstatat64(AT_FDCWD, ptr(a[0]), ptr(a[1]), 0) as _,
+        Sysno32::fstatat64 => sys_fstatat64(a[0] as _, ptr(a[1]), ptr(a[2]), a[3] as _) as _,
+        Sysno32::getdents64 => sys_getdents64(a[0] as _, ptr(a[1]), a[2] as _),
+        Sysno32::lseek => sys_lseek32(a[0] as _, a[1] as _, a[2] as _),
+        Sysno32::_llseek => sys_llseek(a[0] as _, a[1], a[2], ptr(a[3]), a[4] as _) as _,
+        Sysno32::access => sys_faccessat(AT_FDCWD, ptr(a[0]), a[1], 0) as _,
+        Sysno32::faccessat => sys_faccessat(a[0] as _, ptr(a[1]), a[2], a[3] as _) as _,
+        Sysno32::pipe => sys_pipe2(ptr(a[0]), 0) as _,
+        Sysno32::pipe2 => sys_pipe2(ptr(a[0]), a[1] as _) as _,
+        Sysno32::fcntl | Sysno32::fcntl64 => sys_fcntl(a[0] as _, a[1] as _, a[2] as _) as _,
+        Sysno32::ftruncate => sys_ftruncate(a[0] as _, a[1] as i32 as _) as _,
+        Sysno32::ftruncate64 => {
+            let length = ((a[2] as u64) << 32) | a[1] as u64;
+            sys_ftruncate(a[0] as _, length as i64 as _) as _
+        }
+        Sysno32::memfd_create => sys_memfd_create(ptr(a[0]), a[1]) as _,
+        Sysno32::fsync | Sysno32::fdatasync => sys_fsync(a[0] as _) as _,
+        Sysno32::dup => sys_dup(a[0] as _) as _,
+        Sysno32::dup2 => sys_dup2(a[0] as _, a[1] as _) as _,
+        Sysno32::dup3 => sys_dup3(a[0] as _, a[1] as _, a[2] as _) as _,
+        // The arguments are `flags`, `newsp`, `parent_tidptr`, `tls` and
+        // `child_tidptr`, and `tls` points to a `struct user_desc`.
+        Sysno32::clone => sys_clone(
+            a[0] as _,
+            VirtAddr::from_usize(a[1] as _),
+            ptr(a[2]),
+            ptr(a[4]),
+            VirtAddr::from_usize(a[3] as _),
+        ) as _,
+        Sysno32::execve => {
+            syscall_body!(sys_execve, execve::<u32>(ptr(a[0]), ptr(a[1]), ptr(a[2])))
+        }
+        Sysno32::execveat => syscall_body!(
+            sys_execveat,
+            execveat::<u32>(a[0] as _, ptr(a[1]), ptr(a[2]), ptr(a[3]), a[4] as _)
+        ),
+        Sysno32::waitpid => sys_wait4_32(a[0] as _, ptr(a[1]), a[2] as _, ptr(0)) as _,
+        Sysno32::wait4 => sys_wait4_32(a[0] as _, ptr(a[1]), a[2] as _, ptr(a[3])) as _,
+        Sysno32::prlimit64 => sys_prlimit64(a[0] as _, a[1], ptr(a[2]), ptr(a[3])) as _,
+        Sysno32::getrlimit => sys_getrlimit32(a[0], ptr(a[1]), OLD_RLIM32_INFINITY) as _,
+        Sysno32::ugetrlimit => sys_getrlimit32(a[0], ptr(a[1]), RLIM32_INFINITY) as _,
+        Sysno32::setrlimit => sys_setrlimit32(a[0], ptr(a[1])) as _,
+        Sysno32::getrusage => sys_getrusage32(a[0] as _, ptr(a[1])) as _,
+    })
+}
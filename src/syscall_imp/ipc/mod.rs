@@ -0,0 +1,3 @@
+mod shm;
+
+pub(crate) use self::shm::*;
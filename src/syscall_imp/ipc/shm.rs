@@ -0,0 +1,236 @@
+use alloc::vec::Vec;
+
+use axerrno::LinuxError;
+use axhal::paging::MappingFlags;
+use axtask::{current, TaskExtRef};
+use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use crate::ipc::{self, ShmAttachment, IPC_PRIVATE};
+use crate::mm::{self, uaccess, FileMapping};
+use crate::syscall_body;
+
+/// Create the segment if the key does not exist.
+const IPC_CREAT: i32 = 0o1000;
+/// Fail if the key exists.
+const IPC_EXCL: i32 = 0o2000;
+
+/// Remove the segment.
+const IPC_RMID: i32 = 0;
+/// Set the owner and the permissions of the segment.
+const IPC_SET: i32 = 1;
+/// Get the `shmid_ds` of the segment.
+const IPC_STAT: i32 = 2;
+/// Flag set by the C library to request the 64-bit `shmid_ds` layout.
+const IPC_64: i32 = 0x100;
+/// Lock the segment in memory.
+const SHM_LOCK: i32 = 11;
+/// Unlock the segment.
+const SHM_UNLOCK: i32 = 12;
+
+/// Attach the segment read-only.
+const SHM_RDONLY: i32 = 0o10000;
+/// Round the attach address down to `SHMLBA`.
+const SHM_RND: i32 = 0o20000;
+/// Take over the mappings in the range of the attachment.
+const SHM_REMAP: i32 = 0o40000;
+/// Allow the attached segment to be executed.
+const SHM_EXEC: i32 = 0o100000;
+
+/// The alignment of attach addresses.
+const SHMLBA: usize = PAGE_SIZE_4K;
+/// The maximum size of a segment.
+const SHMMAX: usize = usize::MAX - (1 << 24);
+
+/// The permissions of a System V IPC object, as `struct ipc64_perm`.
+///
+/// See <https://github.com/torvalds/linux/blob/master/include/uapi/asm-generic/ipcbuf.h>
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+pub struct IpcPerm {
+    key: i32,
+    uid: u32,
+    gid: u32,
+    cuid: u32,
+    cgid: u32,
+    mode: u32,
+    seq: u16,
+    __pad2: u16,
+    __unused1: u64,
+    __unused2: u64,
+}
+
+/// The status of a segment, as `struct shmid64_ds`.
+///
+/// See <https://github.com/torvalds/linux/blob/master/include/uapi/asm-generic/shmbuf.h>
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+pub struct ShmidDs {
+    shm_perm: IpcPerm,
+    shm_segsz: usize,
+    shm_atime: i64,
+    shm_dtime: i64,
+    shm_ctime: i64,
+    shm_cpid: i32,
+    shm_lpid: i32,
+    shm_nattch: u64,
+    __unused4: u64,
+    __unused5: u64,
+}
+
+pub(crate) fn sys_shmget(key: i32, size: usize, shmflg: i32) -> i32 {
+    syscall_body!(sys_shmget, {
+        if let Some(segment) = ipc::find_segment(key) {
+            if shmflg & IPC_CREAT != 0 && shmflg & IPC_EXCL != 0 {
+                return Err(LinuxError::EEXIST);
+            }
+            if size > segment.size {
+                return Err(LinuxError::EINVAL);
+            }
+            return Ok(segment.id);
+        }
+        if key != IPC_PRIVATE && shmflg & IPC_CREAT == 0 {
+            return Err(LinuxError::ENOENT);
+        }
+        if size == 0 || size > SHMMAX {
+            return Err(LinuxError::EINVAL);
+        }
+        let pid = current().task_ext().proc_id as i32;
+        Ok(ipc::create_segment(key, size, shmflg as u32, pid).id)
+    })
+}
+
+pub(crate) fn sys_shmat(shmid: i32, shmaddr: usize, shmflg: i32) -> usize {
+    syscall_body!(sys_shmat, {
+        let segment = ipc::get_segment(shmid).ok_or(LinuxError::EINVAL)?;
+        let mut flags = MappingFlags::USER | MappingFlags::READ;
+        if shmflg & SHM_RDONLY == 0 {
+            flags |= MappingFlags::WRITE;
+        }
+        if shmflg & SHM_EXEC != 0 {
+            flags |= MappingFlags::EXECUTE;
+        }
+        if !mm::wx_allowed(flags) {
+            return Err(LinuxError::EACCES);
+        }
+        let length = segment.size.align_up_4k();
+
+        let curr = current();
+        let curr_ext = curr.task_ext();
+        let mut aspace = curr_ext.aspace.lock();
+
+        let start_addr = if shmaddr == 0 {
+            let limit =
+                VirtAddrRange::new(aspace.base(), mm::stack_guard_start(*curr_ext.stack.lock()));
+            aspace
+                .find_free_area(curr_ext.mmap_base(), length, limit)
+                .or(aspace.find_free_area(aspace.base(), length, limit))
+                .ok_or(LinuxError::ENOMEM)?
+        } else {
+            let start_addr = if shmflg & SHM_RND != 0 {
+                VirtAddr::from(shmaddr).align_down(SHMLBA)
+            } else {
+                VirtAddr::from(shmaddr)
+            };
+            if !start_addr.is_aligned(SHMLBA) {
+                return Err(LinuxError::EINVAL);
+            }
+            let range =
+                VirtAddrRange::try_from_start_size(start_addr, length).ok_or(LinuxError::EINVAL)?;
+            if aspace.overlap(range) {
+                if shmflg & SHM_REMAP == 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                mm::unmap_range(&mut aspace, &mut curr_ext.vm_attrs.lock(), range)?;
+            }
+            start_addr
+        };
+
+        aspace.map_alloc(start_addr, length, flags, false)?;
+        let range = VirtAddrRange::from_start_size(start_addr, length);
+        let mut vm_attrs = curr_ext.vm_attrs.lock();
+        vm_attrs.mapped(range, flags);
+        vm_attrs.file_maps.insert(
+            range,
+            FileMapping {
+                file: segment.file.clone(),
+                start: start_addr,
+                offset: 0,
+                flags,
+                shared: true,
+            },
+        );
+        vm_attrs
+            .shm
+            .insert(range, ShmAttachment::new(segment.clone(), start_addr));
+        segment.record_attach(curr_ext.proc_id as i32);
+        Ok(start_addr.as_usize())
+    })
+}
+
+pub(crate) fn sys_shmdt(shmaddr: usize) -> i32 {
+    syscall_body!(sys_shmdt, {
+        let start_addr = VirtAddr::from(shmaddr);
+        let curr = current();
+        let curr_ext = curr.task_ext();
+        let mut aspace = curr_ext.aspace.lock();
+        let mut vm_attrs = curr_ext.vm_attrs.lock();
+
+        // The attachment may have been split by `munmap` or `mprotect`.
+        let attached: Vec<_> = vm_attrs
+            .shm
+            .iter()
+            .filter(|(_, attachment)| attachment.start == start_addr)
+            .map(|(range, attachment)| (range, attachment.segment.clone()))
+            .collect();
+        let Some((_, segment)) = attached.first() else {
+            return Err(LinuxError::EINVAL);
+        };
+        segment.record_detach(curr_ext.proc_id as i32);
+        for (range, _) in attached.iter() {
+            mm::unmap_range(&mut aspace, &mut vm_attrs, *range)?;
+        }
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_shmctl(shmid: i32, cmd: i32, buf: *mut ShmidDs) -> i32 {
+    syscall_body!(sys_shmctl, {
+        let segment = ipc::get_segment(shmid).ok_or(LinuxError::EINVAL)?;
+        match cmd & !IPC_64 {
+            IPC_RMID => {
+                ipc::remove_segment(shmid);
+            }
+            IPC_SET => {
+                let perm = uaccess::read_user(buf)?.shm_perm;
+                segment.set_owner(perm.uid, perm.gid, perm.mode);
+            }
+            IPC_STAT => {
+                let info = segment.info();
+                let stat = ShmidDs {
+                    shm_perm: IpcPerm {
+                        key: segment.key,
+                        uid: info.uid,
+                        gid: info.gid,
+                        cuid: info.cuid,
+                        cgid: info.cgid,
+                        mode: info.mode,
+                        ..Default::default()
+                    },
+                    shm_segsz: segment.size,
+                    shm_atime: info.atime,
+                    shm_dtime: info.dtime,
+                    shm_ctime: info.ctime,
+                    shm_cpid: info.cpid,
+                    shm_lpid: info.lpid,
+                    shm_nattch: segment.nattch() as u64,
+                    ..Default::default()
+                };
+                uaccess::write_user(buf, stat)?;
+            }
+            // Segments are never swapped out, so there is nothing to lock.
+            SHM_LOCK | SHM_UNLOCK => {}
+            _ => return Err(LinuxError::EINVAL),
+        }
+        Ok(0)
+    })
+}
@@ -2,36 +2,55 @@ use axhal::paging::MappingFlags;
 use axtask::TaskExtRef;
 use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange};
 
+use crate::mm;
+use crate::task::RlimitResource;
+
+/// Change the location of the program break.
+///
+/// The heap is reserved lazily and its pages are faulted in on first access.
+/// Moving the break down unmaps and frees the pages above it. On failure the
+/// current break is returned unchanged.
 pub(crate) fn sys_brk(new_break: VirtAddr) -> VirtAddr {
     let current = axtask::current();
     let task_ext = current.task_ext();
 
     let mut break_pos = task_ext.break_pos.lock();
-    let break_start = task_ext.break_start;
+    let break_start = task_ext.break_start();
     let current_break = *break_pos;
 
-    let new_break = new_break.align_up_4k();
-    if new_break <= current_break {
+    if new_break < break_start {
+        return current_break;
+    }
+    let data_limit = task_ext.rlimits.lock().cur(RlimitResource::Data);
+    if (new_break - break_start) as u64 > data_limit {
         return current_break;
     }
 
+    let old_end = current_break.align_up_4k();
+    let new_end = new_break.align_up_4k();
     let mut aspace = task_ext.aspace.lock();
 
-    let new_range = VirtAddrRange::new(current_break, new_break);
-    if aspace.overlap(new_range) {
-        return current_break;
+    if new_end > old_end {
+        let new_range = VirtAddrRange::new(old_end, new_end);
+        if aspace.overlap(new_range) || new_end > mm::stack_guard_start(*task_ext.stack.lock()) {
+            return current_break;
+        }
+        let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        if aspace
+            .map_alloc(old_end, new_range.size(), flags, false)
+            .is_err()
+        {
+            return current_break;
+        }
+        task_ext.vm_attrs.lock().mapped(new_range, flags);
+    } else if new_end < old_end {
+        let freed_range = VirtAddrRange::new(new_end, old_end);
+        let mut vm_attrs = task_ext.vm_attrs.lock();
+        if mm::unmap_range(&mut aspace, &mut vm_attrs, freed_range).is_err() {
+            return current_break;
+        }
     }
 
-    // TODO: populating mappings are broken in Debug mode under riscv64.
-    //       GenericPTE expects the mapping flags to contain at least one of `R` or `X`
-    //       while the implementation gives `empty()`.
-    aspace.map_alloc(
-        current_break,
-        new_range.size(),
-        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER,
-        true,
-    );
-
     *break_pos = new_break;
     new_break
 }
@@ -0,0 +1,103 @@
+use axerrno::LinuxError;
+use axtask::{current, TaskExtRef};
+use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange};
+use num_enum::TryFromPrimitive;
+
+use crate::mm::{self, ReadAdvice};
+use crate::syscall_body;
+
+/// Advice values for sys_madvise
+///
+/// See <https://github.com/bminor/glibc/blob/master/bits/mman-linux.h>
+#[derive(Debug, Eq, PartialEq, TryFromPrimitive)]
+#[repr(i32)]
+enum MmapAdvice {
+    /// No further special treatment.
+    Normal = 0,
+    /// Expect random page references.
+    Random = 1,
+    /// Expect sequential page references.
+    Sequential = 2,
+    /// Will need these pages.
+    WillNeed = 3,
+    /// Don't need these pages.
+    DontNeed = 4,
+    /// Free pages only if memory pressure. The pages are freed right away,
+    /// as with `MADV_DONTNEED`.
+    Free = 8,
+    /// Remove these pages and resources.
+    Remove = 9,
+    /// Do not inherit across fork.
+    DontFork = 10,
+    /// Do inherit across fork.
+    DoFork = 11,
+    /// KSM may merge identical pages.
+    Mergeable = 12,
+    /// KSM may not merge identical pages.
+    Unmergeable = 13,
+    /// Worth backing with hugepages.
+    HugePage = 14,
+    /// Not worth backing with hugepages.
+    NoHugePage = 15,
+    /// Explicitly exclude from the core dump.
+    DontDump = 16,
+    /// Clear the MADV_DONTDUMP flag.
+    DoDump = 17,
+}
+
+/// Give advice about the use of memory in `[addr, addr + length)`.
+///
+/// `MADV_DONTNEED` drops the resident pages immediately, so the next access
+/// sees zero-filled memory. `MADV_FREE` is handled exactly the same way:
+/// rather than waiting for memory to run short, the pages are dropped at
+/// once, which is allowed as their contents are undefined until written.
+/// The access pattern hints are recorded for the range and `MADV_DONTFORK`
+/// keeps the range out of child processes. `MADV_HUGEPAGE` and `MADV_NOHUGEPAGE` fail with `EINVAL`, as
+/// on Linux without transparent huge pages. Other advice that only affects
+/// performance is accepted and ignored.
+pub(crate) fn sys_madvise(addr: usize, length: usize, advice: i32) -> i32 {
+    syscall_body!(sys_madvise, {
+        let start = VirtAddr::from(addr);
+        if !start.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        let advice = MmapAdvice::try_from(advice).map_err(|_| LinuxError::EINVAL)?;
+        let end = start
+            .as_usize()
+            .checked_add(length)
+            .ok_or(LinuxError::EINVAL)?
+            .align_up_4k();
+        let range = VirtAddrRange::new(start, VirtAddr::from(end));
+        if range.is_empty() {
+            return Ok(0);
+        }
+
+        let curr = current();
+        let curr_ext = curr.task_ext();
+        let mut aspace = curr_ext.aspace.lock();
+        if !aspace.overlap(range) {
+            return Err(LinuxError::ENOMEM);
+        }
+        let mut vm_attrs = curr_ext.vm_attrs.lock();
+
+        match advice {
+            MmapAdvice::Normal => vm_attrs.read_advice.remove(range),
+            MmapAdvice::Random => vm_attrs.read_advice.insert(range, ReadAdvice::Random),
+            MmapAdvice::Sequential => vm_attrs.read_advice.insert(range, ReadAdvice::Sequential),
+            MmapAdvice::WillNeed => mm::prefault_pages(&mut aspace, &mut vm_attrs, range),
+            MmapAdvice::DontNeed | MmapAdvice::Free => {
+                mm::discard_pages(&mut aspace, &mut vm_attrs, range)?
+            }
+            MmapAdvice::DontFork => vm_attrs.dontfork.insert(range, ()),
+            MmapAdvice::DoFork => vm_attrs.dontfork.remove(range),
+            MmapAdvice::Remove | MmapAdvice::HugePage | MmapAdvice::NoHugePage => {
+                return Err(LinuxError::EINVAL)
+            }
+            MmapAdvice::Mergeable
+            | MmapAdvice::Unmergeable
+            | MmapAdvice::DontDump
+            | MmapAdvice::DoDump => {}
+        }
+        Ok(0)
+    })
+}
@@ -0,0 +1,50 @@
+use alloc::vec::Vec;
+
+use axerrno::LinuxError;
+use axhal::paging::MappingFlags;
+use axtask::{current, TaskExtRef};
+use memory_addr::{MemoryAddr, PageIter4K, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use crate::mm::{self, uaccess};
+use crate::syscall_body;
+
+/// Report which pages of `[addr, addr + length)` are resident in memory.
+///
+/// One byte per page is written to `vec`, whose least significant bit is set
+/// if the page is resident.
+pub(crate) fn sys_mincore(addr: usize, length: usize, vec: *mut u8) -> i32 {
+    syscall_body!(sys_mincore, {
+        let start = VirtAddr::from(addr);
+        if !start.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        let end = start
+            .as_usize()
+            .checked_add(length)
+            .ok_or(LinuxError::ENOMEM)?
+            .align_up_4k();
+        let range = VirtAddrRange::new(start, VirtAddr::from(end));
+        if range.is_empty() {
+            return Ok(0);
+        }
+        // `vec` must hold a byte for every page before anything is looked up.
+        let pages = (range.end - range.start) / PAGE_SIZE_4K;
+        uaccess::check_user(vec, pages, MappingFlags::WRITE)?;
+
+        // Collect the result before writing it out, since `vec` itself may
+        // fault and the fault handler takes the address space lock.
+        let resident: Vec<u8> = {
+            let curr = current();
+            let aspace = curr.task_ext().aspace.lock();
+            if !aspace.overlap(range) {
+                return Err(LinuxError::ENOMEM);
+            }
+            PageIter4K::new(range.start, range.end)
+                .unwrap()
+                .map(|vaddr| mm::is_page_resident(&aspace, vaddr) as u8)
+                .collect()
+        };
+        uaccess::copy_to_user(vec, &resident)?;
+        Ok(0)
+    })
+}
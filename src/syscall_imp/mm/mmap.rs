@@ -1,8 +1,12 @@
+use alloc::vec::Vec;
+
 use axerrno::LinuxError;
 use axhal::paging::MappingFlags;
 use axtask::{current, TaskExtRef};
-use memory_addr::{VirtAddr, VirtAddrRange};
+use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
 
+use crate::mm::page_cache::{self, Seals};
+use crate::mm::{self, FileMapping};
 use crate::syscall_body;
 
 bitflags::bitflags! {
@@ -54,6 +58,8 @@ bitflags::bitflags! {
         const MAP_NORESERVE = 1 << 14;
         /// Allocation is for a stack.
         const MAP_STACK = 0x20000;
+        /// Create a huge page mapping.
+        const MAP_HUGETLB = 0x40000;
     }
 }
 
@@ -62,37 +68,205 @@ pub(crate) fn sys_mmap(
     length: usize,
     prot: i32,
     flags: i32,
-    _fd: i32,
-    _offset: isize,
+    fd: i32,
+    offset: isize,
 ) -> usize {
     syscall_body!(sys_mmap, {
-        let curr = current();
-        let curr_ext = curr.task_ext();
-        let mut aspace = curr_ext.aspace.lock();
-        let permission_flags = MmapProt::from_bits_truncate(prot);
+        let permission_flags: MappingFlags = MmapProt::from_bits_truncate(prot).into();
         // TODO: check illegal flags for mmap
         // An example is the flags contained none of MAP_PRIVATE, MAP_SHARED, or MAP_SHARED_VALIDATE.
         let map_flags = MmapFlags::from_bits_truncate(flags);
+        if length == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        if !mm::wx_allowed(permission_flags) {
+            return Err(LinuxError::EACCES);
+        }
+        // There are no huge pages: `AddrSpace` only maps 4 KiB pages.
+        if map_flags.contains(MmapFlags::MAP_HUGETLB) {
+            return Err(LinuxError::EINVAL);
+        }
+        let length = length
+            .checked_add(PAGE_SIZE_4K - 1)
+            .ok_or(LinuxError::ENOMEM)?
+            .align_down_4k();
+
+        let file = if map_flags.contains(MmapFlags::MAP_ANONYMOUS) {
+            None
+        } else {
+            if offset < 0 || !(offset as usize).is_aligned_4k() {
+                return Err(LinuxError::EINVAL);
+            }
+            let file = page_cache::fd_file(fd).ok_or(LinuxError::ENODEV)?.file;
+            if map_flags.contains(MmapFlags::MAP_SHARED)
+                && permission_flags.contains(MappingFlags::WRITE)
+                && file.seals().intersects(Seals::WRITE | Seals::FUTURE_WRITE)
+            {
+                return Err(LinuxError::EPERM);
+            }
+            Some(file)
+        };
+
+        let curr = current();
+        let curr_ext = curr.task_ext();
+        let mut aspace = curr_ext.aspace.lock();
 
         let start_addr = if map_flags.contains(MmapFlags::MAP_FIXED) {
-            VirtAddr::from(addr as usize)
+            let start_addr = VirtAddr::from(addr as usize);
+            if !start_addr.is_aligned_4k() {
+                return Err(LinuxError::EINVAL);
+            }
+            // Replace whatever was mapped there before.
+            mm::unmap_range(
+                &mut aspace,
+                &mut curr_ext.vm_attrs.lock(),
+                VirtAddrRange::from_start_size(start_addr, length),
+            )?;
+            start_addr
         } else {
-            aspace
-                .find_free_area(
-                    VirtAddr::from(addr as usize),
-                    length,
-                    VirtAddrRange::new(aspace.base(), aspace.end()),
-                )
-                .or(aspace.find_free_area(
-                    aspace.base(),
-                    length,
-                    VirtAddrRange::new(aspace.base(), aspace.end()),
-                ))
+            // Keep the area below the stack free for it to grow.
+            let limit =
+                VirtAddrRange::new(aspace.base(), mm::stack_guard_start(*curr_ext.stack.lock()));
+            // Without a hint, start at the mmap base, leaving the room below
+            // it to the program break.
+            let hint = (addr != 0).then(|| VirtAddr::from(addr as usize));
+            hint.and_then(|hint| aspace.find_free_area(hint, length, limit))
+                .or(aspace.find_free_area(curr_ext.mmap_base(), length, limit))
+                .or(aspace.find_free_area(aspace.base(), length, limit))
                 .ok_or(LinuxError::ENOMEM)?
         };
 
-        aspace.map_alloc(start_addr, length, permission_flags.into(), false)?;
+        aspace.map_alloc(start_addr, length, permission_flags, false)?;
+        let range = VirtAddrRange::from_start_size(start_addr, length);
+        let mut vm_attrs = curr_ext.vm_attrs.lock();
+        vm_attrs.mapped(range, permission_flags);
+        if let Some(file) = file {
+            vm_attrs.file_maps.insert(
+                range,
+                FileMapping {
+                    file,
+                    start: start_addr,
+                    offset: offset as usize,
+                    flags: permission_flags,
+                    shared: map_flags.contains(MmapFlags::MAP_SHARED),
+                },
+            );
+        }
 
         Ok(start_addr.as_usize())
     })
 }
+
+pub(crate) fn sys_munmap(addr: *mut usize, length: usize) -> i32 {
+    syscall_body!(sys_munmap, {
+        let start_addr = VirtAddr::from(addr as usize);
+        if !start_addr.is_aligned_4k() || length == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let length = length
+            .checked_add(PAGE_SIZE_4K - 1)
+            .ok_or(LinuxError::EINVAL)?
+            .align_down_4k();
+
+        let curr = current();
+        let curr_ext = curr.task_ext();
+        mm::unmap_range(
+            &mut curr_ext.aspace.lock(),
+            &mut curr_ext.vm_attrs.lock(),
+            VirtAddrRange::from_start_size(start_addr, length),
+        )?;
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_mprotect(addr: *mut usize, length: usize, prot: i32) -> i32 {
+    syscall_body!(sys_mprotect, {
+        let start_addr = VirtAddr::from(addr as usize);
+        if !start_addr.is_aligned_4k() {
+            return Err(LinuxError::EINVAL);
+        }
+        let length = length
+            .checked_add(PAGE_SIZE_4K - 1)
+            .ok_or(LinuxError::ENOMEM)?
+            .align_down_4k();
+        if length == 0 {
+            return Ok(0);
+        }
+        let permission_flags: MappingFlags = MmapProt::from_bits_truncate(prot).into();
+        if !mm::wx_allowed(permission_flags) {
+            return Err(LinuxError::EACCES);
+        }
+        let range = VirtAddrRange::from_start_size(start_addr, length);
+
+        let curr = current();
+        let curr_ext = curr.task_ext();
+        let mut aspace = curr_ext.aspace.lock();
+        let mut vm_attrs = curr_ext.vm_attrs.lock();
+        let mapped: usize = vm_attrs
+            .mapped
+            .overlapping(range)
+            .map(|(part, _)| part.size())
+            .sum();
+        if mapped != length {
+            return Err(LinuxError::ENOMEM);
+        }
+        let file_maps: Vec<_> = vm_attrs
+            .file_maps
+            .overlapping(range)
+            .map(|(part, mapping)| (part, mapping.clone()))
+            .collect();
+        if permission_flags.contains(MappingFlags::WRITE)
+            && file_maps.iter().any(|(_, mapping)| {
+                mapping.shared
+                    && mapping
+                        .file
+                        .seals()
+                        .intersects(Seals::WRITE | Seals::FUTURE_WRITE)
+            })
+        {
+            return Err(LinuxError::EACCES);
+        }
+
+        aspace.protect(start_addr, length, permission_flags)?;
+        if permission_flags.contains(MappingFlags::WRITE) {
+            mm::zero_page::write_protect(&mut aspace, &vm_attrs, range);
+        }
+        vm_attrs.mapped.insert(range, permission_flags);
+        for (part, mapping) in file_maps {
+            vm_attrs.file_maps.insert(
+                part,
+                FileMapping {
+                    flags: permission_flags,
+                    ..mapping
+                },
+            );
+        }
+        if permission_flags.contains(MappingFlags::WRITE) {
+            page_cache::write_protect_clean(&mut aspace, &vm_attrs, range);
+        }
+        // The pages may have been written as data before.
+        if permission_flags.contains(MappingFlags::EXECUTE) {
+            mm::icache::sync_range(&aspace, range);
+        }
+        Ok(0)
+    })
+}
+
+/// Only flush the instruction cache of the current hart.
+#[cfg(target_arch = "riscv64")]
+const SYS_RISCV_FLUSH_ICACHE_LOCAL: usize = 1;
+
+/// Make the instructions written by the process visible to its instruction
+/// fetches, as needed by `__clear_cache` on riscv64.
+///
+/// The whole instruction cache is flushed, whatever the range.
+#[cfg(target_arch = "riscv64")]
+pub(crate) fn sys_riscv_flush_icache(_start: usize, _end: usize, flags: usize) -> i32 {
+    syscall_body!(sys_riscv_flush_icache, {
+        if flags & !SYS_RISCV_FLUSH_ICACHE_LOCAL != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        mm::icache::flush_icache_all();
+        Ok(0)
+    })
+}
@@ -1,5 +1,11 @@
 mod brk;
+mod madvise;
+mod mincore;
 mod mmap;
+mod process_vm;
 
 pub(crate) use self::brk::*;
+pub(crate) use self::madvise::*;
+pub(crate) use self::mincore::*;
 pub(crate) use self::mmap::*;
+pub(crate) use self::process_vm::*;
@@ -0,0 +1,183 @@
+use alloc::{vec, vec::Vec};
+
+use arceos_posix_api::ctypes::{iovec, pid_t};
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axtask::TaskExtRef;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+use crate::mm::{self, uaccess};
+use crate::syscall_body;
+use crate::task::{self, TaskExt};
+
+/// The maximum number of buffers in an I/O vector.
+const IOV_MAX: usize = 1024;
+
+/// Get the I/O vector at `iov` with `iovcnt` buffers.
+fn iovecs(iov: *const iovec, iovcnt: usize) -> LinuxResult<Vec<iovec>> {
+    if iovcnt > IOV_MAX {
+        return Err(LinuxError::EINVAL);
+    }
+    uaccess::read_user_slice(iov, iovcnt)
+}
+
+/// The total length of the buffers in `iovs`.
+fn total_len(iovs: &[iovec]) -> LinuxResult<usize> {
+    iovs.iter()
+        .try_fold(0usize, |total, iov| total.checked_add(iov.iov_len as usize))
+        .filter(|&total| total <= isize::MAX as usize)
+        .ok_or(LinuxError::EINVAL)
+}
+
+/// Whether the current process may access the memory of `target`: it must
+/// be privileged, or its effective user ID must be all the user IDs of
+/// `target`, as for `ptrace`.
+fn may_access(target: &TaskExt) -> bool {
+    let caller = *axtask::current().task_ext().creds.lock();
+    let target = *target.creds.lock();
+    caller.is_privileged()
+        || (caller.euid == target.uid && caller.euid == target.euid && caller.euid == target.suid)
+}
+
+/// A position in the buffers of the caller.
+struct LocalCursor<'a> {
+    iovs: &'a [iovec],
+    index: usize,
+    offset: usize,
+}
+
+impl LocalCursor<'_> {
+    /// Copy `buf` to the buffers at the position, or fill it from them if
+    /// `to_local` is false, and advance the position.
+    fn transfer(&mut self, buf: &mut [u8], to_local: bool) -> LinuxResult {
+        let mut done = 0;
+        while done < buf.len() {
+            let iov = &self.iovs[self.index];
+            let len = (iov.iov_len as usize - self.offset).min(buf.len() - done);
+            let local = (iov.iov_base as *mut u8).wrapping_add(self.offset);
+            if to_local {
+                uaccess::copy_to_user(local, &buf[done..done + len])?;
+            } else {
+                uaccess::copy_from_user(&mut buf[done..done + len], local)?;
+            }
+            done += len;
+            self.offset += len;
+            if self.offset == iov.iov_len as usize {
+                self.index += 1;
+                self.offset = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Copy between the buffers of the caller and the memory of process `pid`.
+///
+/// The remote memory is accessed one page at a time through a bounce buffer,
+/// so that no user memory is touched while the address space of the other
+/// process is locked. The transfer stops at the first local or remote page
+/// that cannot be accessed, and the number of bytes copied so far is
+/// returned.
+fn process_vm_rw(
+    pid: pid_t,
+    local_iov: *const iovec,
+    liovcnt: usize,
+    remote_iov: *const iovec,
+    riovcnt: usize,
+    flags: usize,
+    write: bool,
+) -> LinuxResult<isize> {
+    if flags != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let local = iovecs(local_iov, liovcnt)?;
+    let remote = iovecs(remote_iov, riovcnt)?;
+    let mut left = total_len(&local)?.min(total_len(&remote)?);
+
+    let pid = usize::try_from(pid).map_err(|_| LinuxError::ESRCH)?;
+    let target = task::find_process(pid).ok_or(LinuxError::ESRCH)?;
+    let target_ext = target.task_ext();
+    if !may_access(target_ext) {
+        return Err(LinuxError::EPERM);
+    }
+
+    let access_flags = if write {
+        MappingFlags::WRITE
+    } else {
+        MappingFlags::READ
+    };
+    let mut cursor = LocalCursor {
+        iovs: &local,
+        index: 0,
+        offset: 0,
+    };
+    let mut buf = vec![0u8; PAGE_SIZE_4K];
+    let mut copied = 0;
+    'remote: for iov in &remote {
+        let mut addr = iov.iov_base as usize;
+        let end = addr
+            .checked_add(iov.iov_len as usize)
+            .ok_or(LinuxError::EFAULT)?;
+        while addr < end && left > 0 {
+            let len = (end - addr)
+                .min(PAGE_SIZE_4K - addr % PAGE_SIZE_4K)
+                .min(left);
+            let offset = addr % PAGE_SIZE_4K;
+            let buf = &mut buf[..len];
+            if write && cursor.transfer(buf, false).is_err() {
+                break 'remote;
+            }
+            let accessed =
+                mm::with_user_page(target_ext, VirtAddr::from(addr), access_flags, |frame| {
+                    if write {
+                        frame[offset..offset + len].copy_from_slice(buf);
+                    } else {
+                        buf.copy_from_slice(&frame[offset..offset + len]);
+                    }
+                });
+            if accessed.is_none() {
+                break 'remote;
+            }
+            if !write && cursor.transfer(buf, true).is_err() {
+                break 'remote;
+            }
+            addr += len;
+            left -= len;
+            copied += len;
+        }
+    }
+    if copied == 0 && left > 0 {
+        return Err(LinuxError::EFAULT);
+    }
+    Ok(copied as isize)
+}
+
+/// Read the memory of process `pid` into the buffers of `local_iov`.
+pub(crate) fn sys_process_vm_readv(
+    pid: pid_t,
+    local_iov: *const iovec,
+    liovcnt: usize,
+    remote_iov: *const iovec,
+    riovcnt: usize,
+    flags: usize,
+) -> isize {
+    syscall_body!(
+        sys_process_vm_readv,
+        process_vm_rw(pid, local_iov, liovcnt, remote_iov, riovcnt, flags, false)
+    )
+}
+
+/// Write the buffers of `local_iov` into the memory of process `pid`.
+pub(crate) fn sys_process_vm_writev(
+    pid: pid_t,
+    local_iov: *const iovec,
+    liovcnt: usize,
+    remote_iov: *const iovec,
+    riovcnt: usize,
+    flags: usize,
+) -> isize {
+    syscall_body!(
+        sys_process_vm_writev,
+        process_vm_rw(pid, local_iov, liovcnt, remote_iov, riovcnt, flags, true)
+    )
+}
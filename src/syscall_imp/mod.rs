@@ -1,18 +1,27 @@
 mod fs;
+#[cfg(feature = "ia32")]
+mod ia32;
+mod ipc;
 mod mm;
+mod sys;
 mod task;
 mod time;
 
+#[cfg(target_arch = "x86_64")]
+use arceos_posix_api::ctypes::AT_FDCWD;
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{
     arch::TrapFrame,
     trap::{register_trap_handler, SYSCALL},
 };
+use axtask::TaskExtRef;
 use memory_addr::VirtAddr;
 use syscalls::Sysno;
 
 use self::fs::*;
+use self::ipc::*;
 use self::mm::*;
+use self::sys::*;
 use self::task::*;
 use self::time::*;
 
@@ -50,14 +59,48 @@ fn do_handle_syscall(tf: &TrapFrame, syscall_num: usize) -> LinuxResult<isize> {
             tf.arg4() as _,
             tf.arg5() as _,
         ) as _,
+        Sysno::munmap => sys_munmap(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::mprotect => sys_mprotect(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+        #[cfg(target_arch = "riscv64")]
+        Sysno::riscv_flush_icache => {
+            sys_riscv_flush_icache(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _
+        }
+        Sysno::madvise => sys_madvise(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+        Sysno::process_vm_readv => sys_process_vm_readv(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+            tf.arg5() as _,
+        ),
+        Sysno::process_vm_writev => sys_process_vm_writev(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+            tf.arg5() as _,
+        ),
+        Sysno::mincore => sys_mincore(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
         Sysno::ioctl => sys_ioctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
         Sysno::writev => sys_writev(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         Sysno::sched_yield => sys_sched_yield() as isize,
+        Sysno::getcpu => sys_getcpu(tf.arg0() as _, tf.arg1() as _) as _,
         Sysno::nanosleep => sys_nanosleep(tf.arg0() as _, tf.arg1() as _) as _,
         Sysno::getpid => sys_getpid() as isize,
+        Sysno::getppid => sys_getppid() as isize,
+        Sysno::uname => sys_uname(tf.arg0() as _) as _,
+        Sysno::times => sys_times(tf.arg0() as _),
+        Sysno::gettimeofday => sys_gettimeofday(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::getuid => sys_getuid(),
+        Sysno::geteuid => sys_geteuid(),
+        Sysno::setuid => sys_setuid(tf.arg0() as _),
+        Sysno::setreuid => sys_setreuid(tf.arg0() as _, tf.arg1() as _),
         Sysno::exit => sys_exit(tf.arg0() as _),
         #[cfg(target_arch = "x86_64")]
         Sysno::arch_prctl => sys_arch_prctl(tf.arg0() as _, tf.arg1() as _),
+        Sysno::personality => sys_personality(tf.arg0() as _),
         Sysno::set_tid_address => sys_set_tid_address(tf.arg0() as _),
         Sysno::clock_gettime => sys_clock_gettime(tf.arg0() as _, tf.arg1() as _) as _,
         Sysno::exit_group => sys_exit_group(tf.arg0() as _),
@@ -65,10 +108,19 @@ fn do_handle_syscall(tf: &TrapFrame, syscall_num: usize) -> LinuxResult<isize> {
         Sysno::clock_gettime => sys_clock_gettime(tf.arg0() as _, tf.arg1() as _) as _,
         #[cfg(not(target_arch = "x86_64"))]
         Sysno::clock_gettime64 => sys_clock_gettime(tf.arg0() as _, tf.arg1() as _) as _,
-        // TODO: `clock_id` and `flags`
-        Sysno::clock_nanosleep_time64 => sys_nanosleep(tf.arg2() as _, tf.arg3() as _) as _,
+        Sysno::clock_nanosleep_time64 => sys_clock_nanosleep(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ) as _,
         #[cfg(not(target_arch = "x86_64"))]
-        Sysno::clock_nanosleep_time64 => sys_nanosleep(tf.arg2() as _, tf.arg3() as _) as _,
+        Sysno::clock_nanosleep_time64 => sys_clock_nanosleep(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ) as _,
         Sysno::brk => {
             let vaddr = VirtAddr::from_usize(tf.arg0());
             sys_brk(vaddr).as_usize() as isize
@@ -82,7 +134,36 @@ fn do_handle_syscall(tf: &TrapFrame, syscall_num: usize) -> LinuxResult<isize> {
             tf.arg2() as _,
             tf.arg3() as _,
         ) as _,
+        #[cfg(target_arch = "x86_64")]
+        Sysno::open => sys_openat(AT_FDCWD, tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
         Sysno::close => sys_close(tf.arg0() as _) as _,
+        Sysno::unlinkat => sys_unlinkat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+        #[cfg(target_arch = "x86_64")]
+        Sysno::unlink => sys_unlinkat(AT_FDCWD, tf.arg0() as _, 0) as _,
+        Sysno::fstat => sys_fstat(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::newfstatat => sys_fstatat(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ) as _,
+        Sysno::getdents64 => sys_getdents64(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::lseek => sys_lseek(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::pipe2 => sys_pipe2(tf.arg0() as _, tf.arg1() as _) as _,
+        #[cfg(target_arch = "x86_64")]
+        Sysno::pipe => sys_pipe2(tf.arg0() as _, 0) as _,
+        Sysno::faccessat => sys_faccessat(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ) as _,
+        #[cfg(target_arch = "x86_64")]
+        Sysno::access => sys_faccessat(AT_FDCWD, tf.arg0() as _, tf.arg1() as _, 0) as _,
+        Sysno::fcntl => sys_fcntl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+        Sysno::ftruncate => sys_ftruncate(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::memfd_create => sys_memfd_create(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::fsync | Sysno::fdatasync => sys_fsync(tf.arg0() as _) as _,
         Sysno::dup => sys_dup(tf.arg0() as _) as _,
         Sysno::dup3 => sys_dup3(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
         Sysno::clone => {
@@ -104,17 +185,62 @@ fn do_handle_syscall(tf: &TrapFrame, syscall_num: usize) -> LinuxResult<isize> {
                 arg4,
             ) as _
         }
-        Sysno::wait4 => tf.arg0() as _,
+        Sysno::execve => sys_execve(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        Sysno::execveat => sys_execveat(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+            tf.arg4() as _,
+        ),
+        Sysno::wait4 => sys_wait4(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ) as _,
+        Sysno::prlimit64 => sys_prlimit64(
+            tf.arg0() as _,
+            tf.arg1() as _,
+            tf.arg2() as _,
+            tf.arg3() as _,
+        ) as _,
+        Sysno::getrlimit => sys_getrlimit(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::setrlimit => sys_setrlimit(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::getrusage => sys_getrusage(tf.arg0() as _, tf.arg1() as _) as _,
+        Sysno::shmget => sys_shmget(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+        Sysno::shmat => sys_shmat(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
+        Sysno::shmdt => sys_shmdt(tf.arg0() as _) as _,
+        Sysno::shmctl => sys_shmctl(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _) as _,
         _ => {
             warn!("Unimplemented syscall: {}", syscall_num);
-            axtask::exit(LinuxError::ENOSYS as _)
+            crate::task::exit(LinuxError::ENOSYS as _)
         }
     })
 }
 
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
-    match do_handle_syscall(tf, syscall_num) {
+    let curr = axtask::current();
+    if curr.task_ext().is_killed() {
+        warn!("{}: killed by the OOM killer, exit!", curr.id_name());
+        crate::task::exit(crate::mm::oom::OOM_KILL_EXIT_CODE);
+    }
+    // 32-bit programs have their own system calls.
+    #[cfg(feature = "ia32")]
+    let result = if crate::ia32::is_compat_frame(tf) {
+        ia32::do_handle_syscall(tf, syscall_num)
+    } else {
+        do_handle_syscall(tf, syscall_num)
+    };
+    #[cfg(not(feature = "ia32"))]
+    let result = do_handle_syscall(tf, syscall_num);
+    // The process may have been killed while it was blocked.
+    if curr.task_ext().is_killed() {
+        warn!("{}: killed by the OOM killer, exit!", curr.id_name());
+        crate::task::exit(crate::mm::oom::OOM_KILL_EXIT_CODE);
+    }
+    match result {
         Ok(retval) => retval,
         Err(error) => -error.code() as isize,
     }
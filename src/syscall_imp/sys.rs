@@ -0,0 +1,87 @@
+use axerrno::LinuxError;
+
+use crate::loader::USER_HZ;
+use crate::mm::uaccess;
+use crate::syscall_body;
+
+/// The length of each field of `struct utsname`, with the terminating NUL.
+const UTS_LEN: usize = 65;
+
+/// `struct utsname`, the same for 32-bit programs.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct UtsName {
+    sysname: [u8; UTS_LEN],
+    nodename: [u8; UTS_LEN],
+    release: [u8; UTS_LEN],
+    version: [u8; UTS_LEN],
+    machine: [u8; UTS_LEN],
+    domainname: [u8; UTS_LEN],
+}
+
+/// The hardware the kernel runs on, as reported by `uname`.
+const MACHINE: &str = if cfg!(target_arch = "x86_64") {
+    "x86_64"
+} else if cfg!(target_arch = "aarch64") {
+    "aarch64"
+} else {
+    "riscv64"
+};
+
+/// A field of `struct utsname` holding `s`.
+fn uts_field(s: &str) -> [u8; UTS_LEN] {
+    let mut field = [0; UTS_LEN];
+    field[..s.len()].copy_from_slice(s.as_bytes());
+    field
+}
+
+/// Get the name and version of the kernel.
+///
+/// It claims to be Linux, whose interface it implements, so that the C
+/// libraries do not refuse to run.
+pub(crate) fn sys_uname(name: *mut UtsName) -> i32 {
+    syscall_body!(sys_uname, {
+        if name.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let uts = UtsName {
+            sysname: uts_field("Linux"),
+            nodename: uts_field("starry"),
+            release: uts_field("6.1.0"),
+            version: uts_field(concat!("#1 SMP starry ", env!("CARGO_PKG_VERSION"))),
+            machine: uts_field(MACHINE),
+            domainname: uts_field("(none)"),
+        };
+        uaccess::write_user(name, uts)?;
+        Ok(0)
+    })
+}
+
+/// `struct tms`, the CPU times of a process and its children in clock
+/// ticks.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Tms {
+    pub tms_utime: i64,
+    pub tms_stime: i64,
+    pub tms_cutime: i64,
+    pub tms_cstime: i64,
+}
+
+/// The number of clock ticks of `times` since boot.
+pub(crate) fn clock_ticks() -> i64 {
+    (axhal::time::monotonic_time_nanos() / (1_000_000_000 / USER_HZ as u64)) as i64
+}
+
+/// Get the CPU times of the process into `buf` unless it is null, and
+/// return the number of clock ticks since boot.
+///
+/// CPU time is not accounted yet, so the times are zero.
+pub(crate) fn sys_times(buf: *mut Tms) -> isize {
+    syscall_body!(sys_times, {
+        if !buf.is_null() {
+            uaccess::write_user(buf, Tms::default())?;
+        }
+        Ok(clock_ticks() as isize)
+    })
+}
@@ -0,0 +1,70 @@
+use axerrno::LinuxError;
+use axtask::{current, TaskExtRef};
+
+use crate::syscall_body;
+
+/// The user ID that leaves an ID unchanged in `setreuid`.
+const UID_UNCHANGED: u32 = u32::MAX;
+
+/// Get the real user ID of the process.
+pub(crate) fn sys_getuid() -> isize {
+    current().task_ext().creds.lock().uid as isize
+}
+
+/// Get the effective user ID of the process.
+pub(crate) fn sys_geteuid() -> isize {
+    current().task_ext().creds.lock().euid as isize
+}
+
+/// Set the user IDs of the process.
+///
+/// A privileged process sets all of them. Others may only set the effective
+/// user ID, to their real or saved one.
+pub(crate) fn sys_setuid(uid: u32) -> isize {
+    syscall_body!(sys_setuid, {
+        if uid == UID_UNCHANGED {
+            return Err(LinuxError::EINVAL);
+        }
+        let curr = current();
+        let mut creds = curr.task_ext().creds.lock();
+        if creds.is_privileged() {
+            creds.uid = uid;
+            creds.suid = uid;
+        } else if uid != creds.uid && uid != creds.suid {
+            return Err(LinuxError::EPERM);
+        }
+        creds.euid = uid;
+        Ok(0)
+    })
+}
+
+/// Set the real and effective user IDs of the process. `-1` leaves an ID
+/// unchanged.
+///
+/// Unprivileged processes may swap their real and effective user IDs, and
+/// set the effective one to the saved one. The saved user ID follows the
+/// effective one when the real one is set or the effective one is set to
+/// something else than the real one.
+pub(crate) fn sys_setreuid(ruid: u32, euid: u32) -> isize {
+    syscall_body!(sys_setreuid, {
+        let curr = current();
+        let mut creds = curr.task_ext().creds.lock();
+        let old = *creds;
+        if ruid != UID_UNCHANGED {
+            if !old.is_privileged() && ruid != old.uid && ruid != old.euid {
+                return Err(LinuxError::EPERM);
+            }
+            creds.uid = ruid;
+        }
+        if euid != UID_UNCHANGED {
+            if !old.is_privileged() && euid != old.uid && euid != old.euid && euid != old.suid {
+                return Err(LinuxError::EPERM);
+            }
+            creds.euid = euid;
+        }
+        if ruid != UID_UNCHANGED || (euid != UID_UNCHANGED && euid != old.uid) {
+            creds.suid = creds.euid;
+        }
+        Ok(0)
+    })
+}
@@ -0,0 +1,163 @@
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::ffi::c_char;
+
+use arceos_posix_api::ctypes::AT_FDCWD;
+use axerrno::{LinuxError, LinuxResult};
+use axtask::{current, TaskExtRef};
+
+use crate::mm::uaccess::{self, PATH_MAX};
+use crate::mm::{self, page_cache::CachedFile, ARG_MAX, MAX_ARG_STRLEN};
+use crate::syscall_imp::fs::{check_fd, close_on_exec};
+use crate::{syscall_body, task, vfs};
+
+/// Run the file of `dirfd` itself if `pathname` is empty.
+const AT_EMPTY_PATH: i32 = 0x1000;
+/// Do not follow a symbolic link at the end of `pathname`.
+const AT_SYMLINK_NOFOLLOW: i32 = 0x100;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Get the path at `pathname`.
+fn user_path(pathname: *const c_char) -> LinuxResult<String> {
+    uaccess::read_user_str(pathname, PATH_MAX - 1)
+}
+
+/// An element of the argument and environment arrays of `execve`, a pointer
+/// to a string. It is 32-bit for 32-bit programs.
+pub(crate) trait StringPtr: Copy {
+    fn as_c_str(self) -> *const c_char;
+}
+
+impl StringPtr for *const c_char {
+    fn as_c_str(self) -> *const c_char {
+        self
+    }
+}
+
+#[cfg(feature = "ia32")]
+impl StringPtr for u32 {
+    fn as_c_str(self) -> *const c_char {
+        self as usize as *const c_char
+    }
+}
+
+/// Get the strings of the NULL-terminated array at `array`. A null `array`
+/// has no strings.
+///
+/// Their total size is checked by `mm::open_executable`, and only the
+/// strings that are too long to ever fit are refused here.
+fn user_strings<P: StringPtr>(array: *const P) -> LinuxResult<Vec<String>> {
+    let mut strings = Vec::new();
+    if array.is_null() {
+        return Ok(strings);
+    }
+    loop {
+        let s = uaccess::read_user(array.wrapping_add(strings.len()))?.as_c_str();
+        if s.is_null() {
+            return Ok(strings);
+        }
+        // Each string takes at least a pointer and a NUL.
+        if strings.len() >= ARG_MAX / size_of::<P>() {
+            return Err(LinuxError::E2BIG);
+        }
+        let s = uaccess::read_user_str(s, MAX_ARG_STRLEN - 1).map_err(|e| match e {
+            LinuxError::ENAMETOOLONG => LinuxError::E2BIG,
+            e => e,
+        })?;
+        strings.push(s);
+    }
+}
+
+/// Replace the program of the current process with the program `name`, or
+/// with `file` if it is given.
+///
+/// Only errors found before the old program is gone are returned.
+fn do_execve<P: StringPtr>(
+    name: String,
+    file: Option<Arc<CachedFile>>,
+    argv: *const P,
+    envp: *const P,
+) -> LinuxResult<isize> {
+    let args = user_strings(argv)?;
+    let envs = user_strings(envp)?;
+    let randomize = mm::aslr_enabled(current().task_ext().personality());
+    let exe = mm::open_executable(name, file, args, envs, randomize)?;
+    close_on_exec();
+    task::exec_user_task(exe)
+}
+
+/// Execute the program at `pathname`.
+pub(crate) fn execve<P: StringPtr>(
+    pathname: *const c_char,
+    argv: *const P,
+    envp: *const P,
+) -> LinuxResult<isize> {
+    let path = user_path(pathname)?;
+    if path.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+    do_execve(path, None, argv, envp)
+}
+
+pub(crate) fn sys_execve(
+    pathname: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> isize {
+    syscall_body!(sys_execve, execve(pathname, argv, envp))
+}
+
+/// Execute the program at `pathname` relative to `dirfd`, or with
+/// `AT_EMPTY_PATH` and an empty `pathname`, the file open as `dirfd`. The
+/// latter is how `fexecve` is implemented.
+pub(crate) fn execveat<P: StringPtr>(
+    dirfd: i32,
+    pathname: *const c_char,
+    argv: *const P,
+    envp: *const P,
+    flags: i32,
+) -> LinuxResult<isize> {
+    if flags & !(AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let path = user_path(pathname)?;
+    if path.is_empty() {
+        if flags & AT_EMPTY_PATH == 0 {
+            return Err(LinuxError::ENOENT);
+        }
+        check_fd(dirfd)?;
+        // Regular files and memfds are opened through the page cache.
+        // Nothing else can be executed.
+        let file = mm::page_cache::fd_file(dirfd)
+            .ok_or(LinuxError::EACCES)?
+            .file;
+        // The program runs as the descriptor, as in Linux. There is no
+        // `/dev/fd` though, so the interpreter of a script could not open it.
+        if mm::is_script(&file)? {
+            return Err(LinuxError::ENOENT);
+        }
+        return do_execve(format!("/dev/fd/{}", dirfd), Some(file), argv, envp);
+    }
+    let path = if path.starts_with('/') || dirfd == AT_FDCWD {
+        path
+    } else {
+        vfs::resolve_at(dirfd, &path)?
+    };
+    // The file systems have no symbolic links so far, but one that reports
+    // a link is refused.
+    if flags & AT_SYMLINK_NOFOLLOW != 0 && vfs::stat(&path)?.st_mode & S_IFMT == S_IFLNK {
+        return Err(LinuxError::ELOOP);
+    }
+    do_execve(path, None, argv, envp)
+}
+
+pub(crate) fn sys_execveat(
+    dirfd: i32,
+    pathname: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+    flags: i32,
+) -> isize {
+    syscall_body!(sys_execveat, execveat(dirfd, pathname, argv, envp, flags))
+}
@@ -1,5 +1,11 @@
+mod cred;
+mod execve;
+mod resource;
 mod schedule;
 mod thread;
 
+pub(crate) use self::cred::*;
+pub(crate) use self::execve::*;
+pub(crate) use self::resource::*;
 pub(crate) use self::schedule::*;
 pub(crate) use self::thread::*;
@@ -0,0 +1,123 @@
+use arceos_posix_api::ctypes::pid_t;
+use axerrno::{LinuxError, LinuxResult};
+use axtask::{current, TaskExtRef};
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::mm::{uaccess, MemStats};
+use crate::syscall_body;
+use crate::task::{Rlimit, RlimitResource};
+
+/// Get the resource limit of a process, and set it to `new_limit` if given.
+///
+/// Only the calling process can be targeted for now, either by `pid == 0` or
+/// by its own process ID.
+pub(crate) fn prlimit(pid: pid_t, resource: u32, new_limit: Option<Rlimit>) -> LinuxResult<Rlimit> {
+    let curr = current();
+    if pid != 0 && pid as usize != curr.task_ext().proc_id {
+        return Err(LinuxError::ESRCH);
+    }
+    let resource = RlimitResource::try_from(resource).map_err(|_| LinuxError::EINVAL)?;
+    if new_limit.is_some_and(|limit| limit.rlim_cur > limit.rlim_max) {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut rlimits = curr.task_ext().rlimits.lock();
+    let old = rlimits.get(resource);
+    if let Some(limit) = new_limit {
+        rlimits.set(resource, limit);
+    }
+    Ok(old)
+}
+
+/// Get and/or set the resource limits of a process.
+pub(crate) fn sys_prlimit64(
+    pid: pid_t,
+    resource: u32,
+    new_limit: *const Rlimit,
+    old_limit: *mut Rlimit,
+) -> i32 {
+    syscall_body!(sys_prlimit64, {
+        let new_limit = if new_limit.is_null() {
+            None
+        } else {
+            Some(uaccess::read_user(new_limit)?)
+        };
+        let old = prlimit(pid, resource, new_limit)?;
+        if !old_limit.is_null() {
+            uaccess::write_user(old_limit, old)?;
+        }
+        Ok(0)
+    })
+}
+
+pub(crate) fn sys_getrlimit(resource: u32, rlim: *mut Rlimit) -> i32 {
+    sys_prlimit64(0, resource, core::ptr::null(), rlim)
+}
+
+pub(crate) fn sys_setrlimit(resource: u32, rlim: *const Rlimit) -> i32 {
+    sys_prlimit64(0, resource, rlim, core::ptr::null_mut())
+}
+
+/// Report the usage of the calling process.
+const RUSAGE_SELF: i32 = 0;
+/// Report the usage of the terminated and waited-for children.
+const RUSAGE_CHILDREN: i32 = -1;
+/// Report the usage of the calling thread.
+const RUSAGE_THREAD: i32 = 1;
+
+/// The resource usage reported by `getrusage`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rusage {
+    /// User CPU time used, as seconds and microseconds.
+    pub ru_utime: [i64; 2],
+    /// System CPU time used, as seconds and microseconds.
+    pub ru_stime: [i64; 2],
+    /// The peak resident set size, in kilobytes.
+    pub ru_maxrss: i64,
+    pub ru_ixrss: i64,
+    pub ru_idrss: i64,
+    pub ru_isrss: i64,
+    /// Page faults served without I/O.
+    pub ru_minflt: i64,
+    /// Page faults that required I/O.
+    pub ru_majflt: i64,
+    pub ru_nswap: i64,
+    pub ru_inblock: i64,
+    pub ru_oublock: i64,
+    pub ru_msgsnd: i64,
+    pub ru_msgrcv: i64,
+    pub ru_nsignals: i64,
+    pub ru_nvcsw: i64,
+    pub ru_nivcsw: i64,
+}
+
+/// The resource usage of a process with the memory usage counters `stats`.
+pub(crate) fn stats_rusage(stats: &MemStats) -> Rusage {
+    Rusage {
+        ru_maxrss: (stats.peak_rss * PAGE_SIZE_4K / 1024) as i64,
+        ru_minflt: stats.minor_faults as i64,
+        ru_majflt: stats.major_faults as i64,
+        ..Default::default()
+    }
+}
+
+/// Get the resource usage of the calling process.
+///
+/// Only the memory counters are reported. Children are not accounted for
+/// yet, so `RUSAGE_CHILDREN` reports zeros.
+pub(crate) fn getrusage(who: i32) -> LinuxResult<Rusage> {
+    match who {
+        RUSAGE_SELF | RUSAGE_THREAD => {
+            Ok(stats_rusage(&current().task_ext().vm_attrs.lock().stats))
+        }
+        RUSAGE_CHILDREN => Ok(Rusage::default()),
+        _ => Err(LinuxError::EINVAL),
+    }
+}
+
+pub(crate) fn sys_getrusage(who: i32, usage: *mut Rusage) -> i32 {
+    syscall_body!(sys_getrusage, {
+        uaccess::write_user(usage, getrusage(who)?)?;
+        Ok(0)
+    })
+}
@@ -1,25 +1,170 @@
+use core::time::Duration;
+
 use arceos_posix_api::{self as api, ctypes::pid_t};
-use axerrno::LinuxError;
+use axerrno::{LinuxError, LinuxResult};
 
 use axtask::{current, TaskExtRef};
 use bitflags::bitflags;
 use memory_addr::VirtAddr;
 
-use crate::task::{clone_user_task, spawn_user_task};
+use crate::{
+    mm::{oom, uaccess},
+    syscall_body,
+    syscall_imp::fs::restore_exec_stash,
+    task::{clone_user_task, spawn_user_task, ExitedChild},
+};
+
+use super::{stats_rusage, Rusage};
 
 pub(crate) fn sys_sched_yield() -> i32 {
     api::sys_sched_yield()
 }
 
+/// The duration in `ts`, which must be normalized.
+pub(crate) fn timespec_duration(ts: api::ctypes::timespec) -> LinuxResult<Duration> {
+    if ts.tv_sec < 0 || !(0..1_000_000_000).contains(&ts.tv_nsec) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+/// Sleep for `dur`. Returns the time left if the sleep is cut short because
+/// the process is killed by the OOM killer, so that it exits right away.
+pub(crate) fn nanosleep(dur: Duration) -> Option<Duration> {
+    let deadline = axhal::time::monotonic_time() + dur;
+    if !oom::killable_sleep(dur) {
+        return None;
+    }
+    Some(deadline.saturating_sub(axhal::time::monotonic_time()))
+}
+
+/// The clock of the wall-clock time.
+const CLOCK_REALTIME: i32 = 0;
+/// The clock that counts from boot.
+pub(crate) const CLOCK_MONOTONIC: i32 = 1;
+/// The clock that counts from boot, including suspend, which never happens.
+const CLOCK_BOOTTIME: i32 = 7;
+/// Sleep until the time given rather than for it.
+pub(crate) const TIMER_ABSTIME: i32 = 1;
+
+/// The time that `clock_nanosleep` sleeps for on the clock `clock_id`:
+/// `req` itself, or with `TIMER_ABSTIME` in `flags`, the time until the
+/// clock reads `req`.
+pub(crate) fn clock_sleep_duration(
+    clock_id: i32,
+    flags: i32,
+    req: api::ctypes::timespec,
+) -> LinuxResult<Duration> {
+    let req = timespec_duration(req)?;
+    let now = match clock_id {
+        CLOCK_REALTIME => axhal::time::wall_time(),
+        CLOCK_MONOTONIC | CLOCK_BOOTTIME => axhal::time::monotonic_time(),
+        _ => return Err(LinuxError::EINVAL),
+    };
+    if flags & TIMER_ABSTIME != 0 {
+        Ok(req.saturating_sub(now))
+    } else {
+        Ok(req)
+    }
+}
+
+/// Sleep on the clock `clock_id` for the time in `req`, or until it with
+/// `TIMER_ABSTIME`.
+///
+/// The sleep is cut short with `EINTR` if the process is killed by the OOM
+/// killer. The time left of a relative sleep is then stored in `rem`.
+pub(crate) fn sys_clock_nanosleep(
+    clock_id: i32,
+    flags: i32,
+    req: *const api::ctypes::timespec,
+    rem: *mut api::ctypes::timespec,
+) -> i32 {
+    syscall_body!(sys_clock_nanosleep, {
+        let dur = clock_sleep_duration(clock_id, flags, uaccess::read_user(req)?)?;
+        let Some(left) = nanosleep(dur) else {
+            return Ok(0);
+        };
+        if flags & TIMER_ABSTIME == 0 && !rem.is_null() {
+            let left = api::ctypes::timespec {
+                tv_sec: left.as_secs() as _,
+                tv_nsec: left.subsec_nanos() as _,
+            };
+            uaccess::write_user(rem, left)?;
+        }
+        Err(LinuxError::EINTR)
+    })
+}
+
+/// Sleep for the time in `req`, storing the time left in `rem` if the sleep
+/// is cut short.
 pub(crate) fn sys_nanosleep(
     req: *const api::ctypes::timespec,
     rem: *mut api::ctypes::timespec,
 ) -> i32 {
-    unsafe { api::sys_nanosleep(req, rem) }
+    sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)
+}
+
+/// Get the CPU and the NUMA node the calling thread is running on.
+pub(crate) fn sys_getcpu(cpu: *mut u32, node: *mut u32) -> i32 {
+    syscall_body!(sys_getcpu, {
+        if !cpu.is_null() {
+            uaccess::write_user(cpu, axhal::cpu::this_cpu_id() as u32)?;
+        }
+        if !node.is_null() {
+            uaccess::write_user(node, 0)?;
+        }
+        Ok(0)
+    })
+}
+
+/// Return at once if no child has exited.
+const WNOHANG: i32 = 1;
+/// Also report stopped children.
+const WUNTRACED: i32 = 2;
+/// Also report children resumed by `SIGCONT`.
+const WCONTINUED: i32 = 8;
+/// Only wait for children of the calling thread.
+const __WNOTHREAD: i32 = 0x2000_0000;
+/// Wait for all children, whatever signal they exit with.
+const __WALL: i32 = 0x4000_0000;
+/// Wait for children that do not exit with `SIGCHLD`.
+const __WCLONE: i32 = 0x8000_0000u32 as i32;
+
+/// Reap a child that has exited: the child `pid` if it is positive, or else
+/// any child. Returns its process ID, its wait status and its resource
+/// usage.
+///
+/// `clone` returns once the child has exited, so there is never anything to
+/// wait for, and children are never stopped. There are no process groups
+/// either: the whole system is one.
+pub(crate) fn wait4(pid: pid_t, options: i32) -> LinuxResult<(pid_t, i32, Rusage)> {
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED | __WNOTHREAD | __WALL | __WCLONE) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let curr = current();
+    let mut exited = curr.task_ext().exited_children.lock();
+    let index = exited
+        .iter()
+        .position(|child| pid <= 0 || child.pid == pid as usize)
+        .ok_or(LinuxError::ECHILD)?;
+    let child = exited.remove(index);
+    let status = (child.exit_code & 0xff) << 8;
+    Ok((child.pid as pid_t, status, stats_rusage(&child.stats)))
 }
 
-pub(crate) fn sys_wait4(pid: pid_t, status: *mut i32, options: i32, rusage: *mut ()) -> pid_t {
-    -22
+/// Wait for a child to exit, storing its wait status in `wstatus` and its
+/// resource usage in `rusage` unless they are null.
+pub(crate) fn sys_wait4(pid: pid_t, wstatus: *mut i32, options: i32, rusage: *mut Rusage) -> i32 {
+    syscall_body!(sys_wait4, {
+        let (pid, status, usage) = wait4(pid, options)?;
+        if !wstatus.is_null() {
+            uaccess::write_user(wstatus, status)?;
+        }
+        if !rusage.is_null() {
+            uaccess::write_user(rusage, usage)?;
+        }
+        Ok(pid)
+    })
 }
 
 bitflags! {
@@ -55,19 +200,34 @@ fn do_sys_clone(
     child_stack: VirtAddr,
     _ptid: *mut pid_t,
     _ctid: *mut pid_t,
-    _tls: VirtAddr,
+    tls: VirtAddr,
 ) -> pid_t {
-    // TODO: We ignore all the flags for now, only do what `fork` will do.
+    // TODO: We ignore the other flags for now, only do what `fork` will do.
     let current = current();
 
-    let new_task = match clone_user_task(current.as_task_ref(), child_stack) {
+    let tls = flags.contains(CloneFlags::CLONE_SETTLS).then_some(tls);
+    let new_task = match clone_user_task(current.as_task_ref(), child_stack, tls) {
         Ok(task) => task,
         Err(e) => {
             return -LinuxError::from(e).code() as _;
         }
     };
 
+    // A parent killed by the OOM killer stops waiting and exits, and leaves
+    // the child to run on its own.
+    if oom::killable_wait(|| new_task.task_ext().has_exited()) {
+        return -LinuxError::EINTR.code() as _;
+    }
     let status = new_task.join();
+    restore_exec_stash(new_task.task_ext());
+    current.task_ext().exited_children.lock().push(ExitedChild {
+        pid: new_task.task_ext().proc_id,
+        exit_code: status.unwrap_or(0),
+        stats: new_task.task_ext().vm_attrs.lock().stats.clone(),
+    });
+    // The child may have installed its own TLS descriptors on this CPU.
+    #[cfg(feature = "ia32")]
+    current.task_ext().ia32.load();
     info!(
         "clone: new task({}) exited with status: {:?}",
         new_task.task_ext().proc_id,
@@ -83,13 +243,13 @@ pub(crate) fn sys_clone(
     child_stack: VirtAddr,
     _ptid: *mut pid_t,
     _ctid: *mut pid_t,
-    _newtls: VirtAddr,
+    newtls: VirtAddr,
 ) -> pid_t {
     let flags = CloneFlags::from_bits_truncate(flags);
     if child_stack.as_usize() == 0 {
         return -LinuxError::EINVAL.code() as _;
     }
-    do_sys_clone(flags, child_stack, _ptid, _ctid, _newtls)
+    do_sys_clone(flags, child_stack, _ptid, _ctid, newtls)
 }
 
 #[cfg(not(target_arch = "x86_64"))]
@@ -97,12 +257,12 @@ pub(crate) fn sys_clone(
     flags: i32,
     child_stack: VirtAddr,
     _ptid: *mut pid_t,
-    _newtls: VirtAddr,
+    newtls: VirtAddr,
     _ctid: *mut pid_t,
 ) -> pid_t {
     let flags = CloneFlags::from_bits_truncate(flags);
     if child_stack.as_usize() == 0 {
         return -LinuxError::EINVAL.code() as _;
     }
-    do_sys_clone(flags, child_stack, _ptid, _ctid, _newtls)
+    do_sys_clone(flags, child_stack, _ptid, _ctid, newtls)
 }
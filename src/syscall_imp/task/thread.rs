@@ -1,10 +1,13 @@
 use core::ffi::{c_char, c_ulong};
 
-use arceos_posix_api::{self as api};
+use arceos_posix_api::ctypes::AT_FDCWD;
+use axerrno::LinuxError;
 use axtask::{current, TaskExtRef};
 use num_enum::TryFromPrimitive;
 
-use crate::syscall_body;
+use crate::mm::uaccess;
+use crate::syscall_imp::fs::path_at;
+use crate::{syscall_body, task, vfs};
 
 /// ARCH_PRCTL codes
 ///
@@ -31,23 +34,27 @@ pub(crate) fn sys_getpid() -> i32 {
     current().task_ext().proc_id as i32
 }
 
+/// Get the ID of the parent process, 0 if the process was started by the
+/// kernel.
+pub(crate) fn sys_getppid() -> i32 {
+    current().task_ext().parent_id as i32
+}
+
 pub(crate) fn sys_exit(status: i32) -> ! {
     let curr = current();
     let clear_child_tid = curr.task_ext().clear_child_tid() as *mut i32;
     if !clear_child_tid.is_null() {
-        // TODO: check whether the address is valid
-        unsafe {
-            // TODO: Encapsulate all operations that access user-mode memory into a unified function
-            *(clear_child_tid) = 0;
-        }
+        // The address is not checked by `set_tid_address`, and an invalid one
+        // is ignored.
+        let _ = uaccess::write_user(clear_child_tid, 0);
         // TODO: wake up threads, which are blocked by futex, and waiting for the address pointed by clear_child_tid
     }
-    axtask::exit(status);
+    task::exit(status);
 }
 
 pub(crate) fn sys_exit_group(status: i32) -> ! {
     warn!("Temporarily replace sys_exit_group with sys_exit");
-    axtask::exit(status);
+    task::exit(status);
 }
 
 /// To set the clear_child_tid field in the task extended data.
@@ -61,17 +68,51 @@ pub(crate) fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
     })
 }
 
+/// Get or set the execution domain of the process.
+///
+/// Only the flags are kept. `ADDR_NO_RANDOMIZE` takes effect on the next
+/// program loaded by the process. Passing `0xffffffff` only queries the
+/// current value.
+pub(crate) fn sys_personality(persona: u32) -> isize {
+    syscall_body!(sys_personality, {
+        let curr = current();
+        let old = curr.task_ext().personality();
+        if persona != 0xffff_ffff {
+            curr.task_ext().set_personality(persona);
+        }
+        Ok(old as isize)
+    })
+}
+
+/// Change the current directory, which may be one that exists only in the
+/// kernel.
 pub(crate) fn sys_chdir(pathname: *const c_char) -> isize {
-    api::sys_chdir(pathname) as _
+    syscall_body!(sys_chdir, {
+        let path = path_at(AT_FDCWD, pathname)?;
+        vfs::set_current_dir(&path)?;
+        Ok(0)
+    })
 }
 
+/// Copy the path of the current directory to `buf`, and return `buf`.
 pub(crate) fn sys_getcwd(buf: *mut c_char, bufsize: c_ulong) -> isize {
-    api::sys_getcwd(buf, bufsize as usize) as _
+    syscall_body!(sys_getcwd, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let cwd = vfs::current_dir()?;
+        if cwd.len() + 1 > bufsize as usize {
+            return Err(LinuxError::ERANGE);
+        }
+        let mut path = cwd.into_bytes();
+        path.push(0);
+        uaccess::copy_to_user(buf as _, &path)?;
+        Ok(buf as isize)
+    })
 }
 
 #[cfg(target_arch = "x86_64")]
 pub(crate) fn sys_arch_prctl(code: i32, addr: u64) -> isize {
-    use axerrno::LinuxError;
     syscall_body!(sys_arch_prctl, {
         match ArchPrctlCode::try_from(code) {
             // TODO: check the legality of the address
@@ -82,9 +123,7 @@ pub(crate) fn sys_arch_prctl(code: i32, addr: u64) -> isize {
                 Ok(0)
             }
             Ok(ArchPrctlCode::GetFs) => {
-                unsafe {
-                    *(addr as *mut u64) = axhal::arch::read_thread_pointer() as u64;
-                }
+                uaccess::write_user(addr as *mut u64, axhal::arch::read_thread_pointer() as u64)?;
                 Ok(0)
             }
             Ok(ArchPrctlCode::SetGs) => {
@@ -94,9 +133,8 @@ pub(crate) fn sys_arch_prctl(code: i32, addr: u64) -> isize {
                 Ok(0)
             }
             Ok(ArchPrctlCode::GetGs) => {
-                unsafe {
-                    *(addr as *mut u64) = x86::msr::rdmsr(x86::msr::IA32_KERNEL_GSBASE);
-                }
+                let gs = unsafe { x86::msr::rdmsr(x86::msr::IA32_KERNEL_GSBASE) };
+                uaccess::write_user(addr as *mut u64, gs)?;
                 Ok(0)
             }
             _ => Err(LinuxError::ENOSYS),
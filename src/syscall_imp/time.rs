@@ -1,5 +1,44 @@
+use core::ffi::c_void;
+
 use arceos_posix_api as api;
+use axerrno::{LinuxError, LinuxResult};
+
+use crate::mm::uaccess;
+use crate::syscall_body;
+
+/// Get the time of the clock `clock_id`.
+pub(crate) fn clock_gettime(clock_id: i32) -> LinuxResult<api::ctypes::timespec> {
+    let mut ts: api::ctypes::timespec = unsafe { core::mem::zeroed() };
+    let ret = unsafe { api::sys_clock_gettime(clock_id, &mut ts) };
+    if ret < 0 {
+        return Err(LinuxError::try_from(-ret).unwrap_or(LinuxError::EINVAL));
+    }
+    Ok(ts)
+}
+
+/// The wall-clock time, as `gettimeofday` reports it.
+pub(crate) fn wall_timeval() -> api::ctypes::timeval {
+    let now = axhal::time::wall_time();
+    api::ctypes::timeval {
+        tv_sec: now.as_secs() as _,
+        tv_usec: now.subsec_micros() as _,
+    }
+}
 
 pub(crate) fn sys_clock_gettime(clock_id: i32, tp: *mut api::ctypes::timespec) -> i32 {
-    unsafe { api::sys_clock_gettime(clock_id, tp) }
+    syscall_body!(sys_clock_gettime, {
+        uaccess::write_user(tp, clock_gettime(clock_id)?)?;
+        Ok(0)
+    })
+}
+
+/// Get the wall-clock time into `tv` unless it is null. The time zone is
+/// always UTC, so `tz` is ignored.
+pub(crate) fn sys_gettimeofday(tv: *mut api::ctypes::timeval, _tz: *mut c_void) -> i32 {
+    syscall_body!(sys_gettimeofday, {
+        if !tv.is_null() {
+            uaccess::write_user(tv, wall_timeval())?;
+        }
+        Ok(0)
+    })
 }
@@ -1,19 +1,166 @@
-use alloc::sync::Arc;
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
 use axerrno::AxResult;
-use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use memory_addr::VirtAddr;
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use memory_addr::{VirtAddr, VirtAddrRange};
+use num_enum::TryFromPrimitive;
 
 use axhal::arch::{TrapFrame, UspaceContext};
+use axhal::paging::MappingFlags;
 use axmm::AddrSpace;
 use axsync::Mutex;
-use axtask::{AxTaskRef, TaskExtRef, TaskInner};
+use axtask::{AxTaskRef, TaskExtRef, TaskInner, WeakAxTaskRef};
+
+use crate::mm::{self, MemStats, RangeMap, VmAttrs};
 
 static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
 
+/// The user processes, by process ID.
+static PROCESSES: Mutex<BTreeMap<usize, WeakAxTaskRef>> = Mutex::new(BTreeMap::new());
+
+/// The value of a resource limit that is not enforced.
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// Resources that can be limited by `setrlimit` and `prlimit64`.
+///
+/// See <https://github.com/bminor/glibc/blob/master/sysdeps/unix/sysv/linux/bits/resource.h>
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
+#[repr(u32)]
+pub enum RlimitResource {
+    /// Per-process CPU limit, in seconds.
+    Cpu = 0,
+    /// Largest file that can be created, in bytes.
+    Fsize = 1,
+    /// Maximum size of data segment, in bytes.
+    Data = 2,
+    /// Maximum size of stack segment, in bytes.
+    Stack = 3,
+    /// Largest core file that can be created, in bytes.
+    Core = 4,
+    /// Largest resident set size, in bytes.
+    Rss = 5,
+    /// Number of processes.
+    Nproc = 6,
+    /// Number of open files.
+    Nofile = 7,
+    /// Locked-in-memory address space.
+    Memlock = 8,
+    /// Address space limit.
+    As = 9,
+    /// Maximum number of file locks.
+    Locks = 10,
+    /// Maximum number of pending signals.
+    Sigpending = 11,
+    /// Maximum bytes in POSIX message queues.
+    Msgqueue = 12,
+    /// Maximum nice priority allowed to raise to.
+    Nice = 13,
+    /// Maximum realtime priority allowed for non-privileged processes.
+    Rtprio = 14,
+    /// Maximum CPU time in microseconds that a real-time task may consume
+    /// without making a blocking system call.
+    Rttime = 15,
+}
+
+/// The number of resource limits.
+const RLIM_NLIMITS: usize = 16;
+
+/// A soft and hard limit of a resource, laid out as `struct rlimit64`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rlimit {
+    /// The soft limit.
+    pub rlim_cur: u64,
+    /// The hard limit.
+    pub rlim_max: u64,
+}
+
+impl Rlimit {
+    const fn new(rlim_cur: u64, rlim_max: u64) -> Self {
+        Self { rlim_cur, rlim_max }
+    }
+}
+
+/// The resource limits of a process.
+#[derive(Clone)]
+pub struct Rlimits([Rlimit; RLIM_NLIMITS]);
+
+impl Rlimits {
+    /// The limits of the initial process, the same as the defaults of Linux.
+    pub const fn new() -> Self {
+        let mut limits = [Rlimit::new(RLIM_INFINITY, RLIM_INFINITY); RLIM_NLIMITS];
+        limits[RlimitResource::Stack as usize] = Rlimit::new(8 * 1024 * 1024, RLIM_INFINITY);
+        limits[RlimitResource::Core as usize] = Rlimit::new(0, RLIM_INFINITY);
+        limits[RlimitResource::Nofile as usize] = Rlimit::new(1024, 4096);
+        limits[RlimitResource::Memlock as usize] = Rlimit::new(8 * 1024 * 1024, 8 * 1024 * 1024);
+        limits[RlimitResource::Msgqueue as usize] = Rlimit::new(819200, 819200);
+        limits[RlimitResource::Nice as usize] = Rlimit::new(0, 0);
+        limits[RlimitResource::Rtprio as usize] = Rlimit::new(0, 0);
+        Self(limits)
+    }
+
+    /// Get the limit of `resource`.
+    pub fn get(&self, resource: RlimitResource) -> Rlimit {
+        self.0[resource as usize]
+    }
+
+    /// Get the soft limit of `resource`.
+    pub fn cur(&self, resource: RlimitResource) -> u64 {
+        self.0[resource as usize].rlim_cur
+    }
+
+    /// Set the limit of `resource`.
+    pub fn set(&mut self, resource: RlimitResource, limit: Rlimit) {
+        self.0[resource as usize] = limit;
+    }
+}
+
+/// A child process that has exited, until its parent waits for it.
+pub struct ExitedChild {
+    /// The process ID.
+    pub pid: usize,
+    /// The exit code.
+    pub exit_code: i32,
+    /// The memory usage counters when it exited.
+    pub stats: MemStats,
+}
+
+/// The user IDs of a process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    /// The real user ID.
+    pub uid: u32,
+    /// The effective user ID, which permissions are checked against.
+    pub euid: u32,
+    /// The saved set-user-ID, which the effective one may be set back to.
+    pub suid: u32,
+}
+
+impl Credentials {
+    /// The credentials of root, which the first process starts with.
+    pub const fn root() -> Self {
+        Self {
+            uid: 0,
+            euid: 0,
+            suid: 0,
+        }
+    }
+
+    /// Whether the process has the privileges of root.
+    pub fn is_privileged(&self) -> bool {
+        self.euid == 0
+    }
+}
+
 /// Task extended data for the monolithic kernel.
 pub struct TaskExt {
     /// The process ID.
     pub proc_id: usize,
+    /// The ID of the parent process, 0 for processes started by the kernel.
+    pub parent_id: usize,
     /// The clear thread tid field
     ///
     /// See <https://manpages.debian.org/unstable/manpages-dev/set_tid_address.2.en.html#clear_child_tid>
@@ -25,20 +172,82 @@ pub struct TaskExt {
     /// The virtual memory address space.
     pub aspace: Arc<Mutex<AddrSpace>>,
     /// The start position of the program break.
-    pub break_start: VirtAddr,
+    break_start: AtomicUsize,
     /// The current position of the program break.
     pub break_pos: Mutex<VirtAddr>,
+    /// The currently mapped range of the user stack.
+    ///
+    /// The stack grows downwards on demand, see [`crate::mm::grow_stack`].
+    pub stack: Mutex<VirtAddrRange>,
+    /// Where the search for free areas in `mmap` starts.
+    mmap_base: AtomicUsize,
+    /// The execution domain set by `personality`.
+    personality: AtomicU32,
+    /// The attributes of user mappings that are not tracked by `aspace`.
+    pub vm_attrs: Mutex<VmAttrs>,
+    /// The resource limits.
+    pub rlimits: Mutex<Rlimits>,
+    /// The user IDs.
+    pub creds: Mutex<Credentials>,
+    /// The adjustment of the badness used to choose the victim of the OOM
+    /// killer, see [`crate::mm::oom`].
+    oom_score_adj: AtomicI32,
+    /// Whether the process has been killed and must exit as soon as it runs.
+    killed: AtomicBool,
+    /// Whether the process has exited, or is about to, see [`exit`].
+    exited: AtomicBool,
+    /// The file descriptors the process closes on `execve`. The table of
+    /// file descriptors itself is shared by all processes.
+    pub cloexec_fds: Mutex<BTreeSet<i32>>,
+    /// The descriptors of the parent that were moved out of the way when the
+    /// process ran a new program, as the original and the new descriptor.
+    /// The parent gets them back when the process exits.
+    pub exec_stash: Mutex<Vec<(i32, i32)>>,
+    /// The children that have exited and not been waited for yet.
+    pub exited_children: Mutex<Vec<ExitedChild>>,
+    /// The thread pointer of user space the task starts with, and the one it
+    /// had when it last cloned. It is not part of the trap frame on x86_64
+    /// (`FS_BASE`) and aarch64 (`TPIDR_EL0`); while the task runs, the
+    /// context switch saves and restores it (the `tls` feature of `axhal`).
+    #[cfg(not(target_arch = "riscv64"))]
+    tls: AtomicUsize,
+    /// Whether the task runs a 32-bit program, and its TLS descriptors.
+    #[cfg(feature = "ia32")]
+    pub ia32: crate::ia32::CompatState,
 }
 
 impl TaskExt {
-    pub fn new(uctx: UspaceContext, aspace: Arc<Mutex<AddrSpace>>, break_start: VirtAddr) -> Self {
+    pub fn new(
+        uctx: UspaceContext,
+        aspace: Arc<Mutex<AddrSpace>>,
+        break_start: VirtAddr,
+        stack: VirtAddrRange,
+        mmap_base: VirtAddr,
+    ) -> Self {
         Self {
             proc_id: NEXT_PID.fetch_add(1, Ordering::AcqRel),
+            parent_id: 0,
             uctx,
             clear_child_tid: AtomicU64::new(0),
             aspace,
-            break_start,
+            break_start: AtomicUsize::new(break_start.as_usize()),
             break_pos: Mutex::new(break_start),
+            stack: Mutex::new(stack),
+            mmap_base: AtomicUsize::new(mmap_base.as_usize()),
+            personality: AtomicU32::new(0),
+            vm_attrs: Mutex::new(VmAttrs::new()),
+            rlimits: Mutex::new(Rlimits::new()),
+            creds: Mutex::new(Credentials::root()),
+            oom_score_adj: AtomicI32::new(0),
+            killed: AtomicBool::new(false),
+            exited: AtomicBool::new(false),
+            cloexec_fds: Mutex::new(BTreeSet::new()),
+            exec_stash: Mutex::new(Vec::new()),
+            exited_children: Mutex::new(Vec::new()),
+            #[cfg(not(target_arch = "riscv64"))]
+            tls: AtomicUsize::new(0),
+            #[cfg(feature = "ia32")]
+            ia32: crate::ia32::CompatState::new(),
         }
     }
 
@@ -51,23 +260,201 @@ impl TaskExt {
         self.clear_child_tid
             .store(clear_child_tid, core::sync::atomic::Ordering::Relaxed);
     }
+
+    /// The start position of the program break.
+    pub(crate) fn break_start(&self) -> VirtAddr {
+        VirtAddr::from(self.break_start.load(Ordering::Relaxed))
+    }
+
+    /// Where the search for free areas in `mmap` starts.
+    pub(crate) fn mmap_base(&self) -> VirtAddr {
+        VirtAddr::from(self.mmap_base.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn personality(&self) -> u32 {
+        self.personality.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_personality(&self, personality: u32) {
+        self.personality.store(personality, Ordering::Relaxed);
+    }
+
+    pub(crate) fn oom_score_adj(&self) -> i32 {
+        self.oom_score_adj.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_oom_score_adj(&self, oom_score_adj: i32) {
+        self.oom_score_adj.store(oom_score_adj, Ordering::Relaxed);
+    }
+
+    /// Whether the process has been killed.
+    pub(crate) fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Acquire)
+    }
+
+    /// Mark the process as killed. It exits the next time it enters the
+    /// kernel.
+    pub(crate) fn kill(&self) {
+        self.killed.store(true, Ordering::Release);
+    }
+
+    /// Whether the process has exited, or is about to.
+    pub(crate) fn has_exited(&self) -> bool {
+        self.exited.load(Ordering::Acquire)
+    }
+
+    /// Save the thread pointer of user space from the CPU. The task must be
+    /// the current one.
+    ///
+    /// On riscv64 the thread pointer is `tp`, which is saved in the trap
+    /// frame, so there is nothing to do.
+    pub(crate) fn save_tls(&self) {
+        #[cfg(not(target_arch = "riscv64"))]
+        self.tls
+            .store(axhal::arch::read_thread_pointer(), Ordering::Relaxed);
+    }
+
+    /// Load the saved thread pointer of user space into the CPU before
+    /// entering user space for the first time, or with a new program. The
+    /// task must be the current one.
+    ///
+    /// The TLS descriptors of 32-bit programs are installed as well.
+    pub(crate) fn load_tls(&self) {
+        #[cfg(not(target_arch = "riscv64"))]
+        unsafe {
+            axhal::arch::write_thread_pointer(self.tls.load(Ordering::Relaxed))
+        };
+        #[cfg(feature = "ia32")]
+        self.ia32.load();
+    }
+}
+
+fn register_process(task: &AxTaskRef) {
+    let mut processes = PROCESSES.lock();
+    processes.retain(|_, task| task.strong_count() > 0);
+    processes.insert(task.task_ext().proc_id, Arc::downgrade(task));
+}
+
+/// Get the user process with ID `pid`.
+pub fn find_process(pid: usize) -> Option<AxTaskRef> {
+    PROCESSES.lock().get(&pid).and_then(|task| task.upgrade())
+}
+
+/// Get all user processes.
+pub fn processes() -> Vec<AxTaskRef> {
+    PROCESSES
+        .lock()
+        .values()
+        .filter_map(|task| task.upgrade())
+        .collect()
+}
+
+impl Drop for TaskExt {
+    fn drop(&mut self) {
+        // The address space unmaps its areas when it is dropped, which must
+        // not free the frames that do not belong to them.
+        let old_attrs = mm::clear_user_space(&mut self.aspace.lock(), self.vm_attrs.get_mut());
+        drop(old_attrs);
+    }
 }
 
 axtask::def_task_ext!(TaskExt);
 
-pub fn clone_user_task(task: &AxTaskRef, child_stack: VirtAddr) -> AxResult<AxTaskRef> {
-    let aspace = Arc::new(Mutex::new(task.task_ext().aspace.lock().new_cloned()?));
+/// Exit the current process with `exit_code`, and wake its parent if it is
+/// waiting for it.
+pub fn exit(exit_code: i32) -> ! {
+    axtask::current()
+        .task_ext()
+        .exited
+        .store(true, Ordering::Release);
+    mm::oom::wake_killable();
+    axtask::exit(exit_code)
+}
+
+/// Create a child of the current task `task`.
+///
+/// The child starts with the thread pointer `tls` if it is given
+/// (`CLONE_SETTLS`), or else with the one of the parent. For 32-bit programs
+/// `tls` points to the TLS descriptor of the child instead.
+pub fn clone_user_task(
+    task: &AxTaskRef,
+    child_stack: VirtAddr,
+    tls: Option<VirtAddr>,
+) -> AxResult<AxTaskRef> {
+    let mut aspace = task.task_ext().aspace.lock().new_cloned()?;
+    let mut vm_attrs = task.task_ext().vm_attrs.lock().clone();
+    mm::zero_page::share_cloned(&mut aspace, &vm_attrs);
+    // Mappings marked with `MADV_DONTFORK` are not inherited by the child.
+    let dontfork = core::mem::replace(&mut vm_attrs.dontfork, RangeMap::new());
+    for (range, _) in dontfork.iter() {
+        mm::unmap_range(&mut aspace, &mut vm_attrs, range)?;
+    }
+    // The code of the child is in copies of the pages of the parent.
+    for (range, flags) in vm_attrs.mapped.iter() {
+        if flags.contains(MappingFlags::EXECUTE) {
+            mm::icache::sync_range(&aspace, range);
+        }
+    }
+    vm_attrs.stats.reset_for_child();
+    let aspace = Arc::new(Mutex::new(aspace));
 
     let trap_stack = task.kernel_stack_top().unwrap() - size_of::<TrapFrame>();
     let trap_frame = unsafe { &*(trap_stack.as_usize() as *const TrapFrame) };
+    #[cfg(target_arch = "riscv64")]
+    let trap_frame = &{
+        let mut trap_frame = *trap_frame;
+        if let Some(tls) = tls {
+            trap_frame.regs.tp = tls.as_usize();
+        }
+        trap_frame
+    };
     let mut uctx = UspaceContext::from(trap_frame);
 
+    // The trap frame points at the `ecall` on riscv64, and after the system
+    // call instruction elsewhere.
+    #[cfg(target_arch = "riscv64")]
     uctx.set_ip(uctx.get_ip() + 4); // Next instruction
     uctx.set_retval(0); // Child process returns 0
     uctx.set_sp(child_stack.as_usize());
 
-    let mut new_task_ext = TaskExt::new(uctx, aspace.clone(), task.task_ext().break_start);
+    let mut new_task_ext = TaskExt::new(
+        uctx,
+        aspace.clone(),
+        task.task_ext().break_start(),
+        *task.task_ext().stack.lock(),
+        task.task_ext().mmap_base(),
+    );
+    new_task_ext.parent_id = task.task_ext().proc_id;
     *new_task_ext.break_pos.get_mut() = *task.task_ext().break_pos.lock();
+    *new_task_ext.vm_attrs.get_mut() = vm_attrs;
+    *new_task_ext.rlimits.get_mut() = task.task_ext().rlimits.lock().clone();
+    *new_task_ext.creds.get_mut() = *task.task_ext().creds.lock();
+    *new_task_ext.cloexec_fds.get_mut() = task.task_ext().cloexec_fds.lock().clone();
+    new_task_ext.set_personality(task.task_ext().personality());
+    new_task_ext.set_oom_score_adj(task.task_ext().oom_score_adj());
+    #[cfg(feature = "ia32")]
+    let tls = {
+        new_task_ext.ia32.inherit(&task.task_ext().ia32);
+        match tls {
+            Some(u_info) if new_task_ext.ia32.is_compat() => {
+                new_task_ext
+                    .ia32
+                    .set_tls(u_info.as_usize() as *const _)
+                    .map_err(|_| axerrno::AxError::InvalidInput)?;
+                None
+            }
+            tls => tls,
+        }
+    };
+    #[cfg(not(target_arch = "riscv64"))]
+    {
+        task.task_ext().save_tls();
+        let tls = tls.map_or(
+            task.task_ext().tls.load(Ordering::Relaxed),
+            VirtAddr::as_usize,
+        );
+        *new_task_ext.tls.get_mut() = tls;
+    }
 
     let mut new_task = TaskInner::new(
         || {
@@ -79,6 +466,7 @@ pub fn clone_user_task(task: &AxTaskRef, child_stack: VirtAddr) -> AxResult<AxTa
                 curr.task_ext().uctx.get_sp(),
                 kstack_top,
             );
+            curr.task_ext().load_tls();
             unsafe { curr.task_ext().uctx.enter_uspace(kstack_top) };
         },
         "[usertask]".into(),
@@ -89,13 +477,28 @@ pub fn clone_user_task(task: &AxTaskRef, child_stack: VirtAddr) -> AxResult<AxTa
         .set_page_table_root(aspace.lock().page_table_root());
     new_task.init_task_ext(new_task_ext);
 
-    Ok(axtask::spawn_task(new_task))
+    let new_task = axtask::spawn_task(new_task);
+    register_process(&new_task);
+    Ok(new_task)
 }
 
+/// The context that starts `app` in user space, with `arg0` as the first
+/// argument.
+///
+/// 32-bit programs start in the 32-bit code segment, without arguments.
+pub fn uspace_context(app: &mm::UserApp, arg0: usize) -> UspaceContext {
+    #[cfg(feature = "ia32")]
+    if app.compat {
+        return crate::ia32::uspace_context(app.entry, app.sp);
+    }
+    UspaceContext::new(app.entry.as_usize(), app.sp, arg0)
+}
+
+/// Create a task that runs `app`, loaded in `aspace`, from `uctx`.
 pub fn spawn_user_task(
     aspace: Arc<Mutex<AddrSpace>>,
     uctx: UspaceContext,
-    break_start: VirtAddr,
+    app: mm::UserApp,
 ) -> AxTaskRef {
     let mut task = TaskInner::new(
         || {
@@ -107,6 +510,7 @@ pub fn spawn_user_task(
                 curr.task_ext().uctx.get_sp(),
                 kstack_top,
             );
+            curr.task_ext().load_tls();
             unsafe { curr.task_ext().uctx.enter_uspace(kstack_top) };
         },
         "[usertask]".into(),
@@ -114,6 +518,81 @@ pub fn spawn_user_task(
     );
     task.ctx_mut()
         .set_page_table_root(aspace.lock().page_table_root());
-    task.init_task_ext(TaskExt::new(uctx, aspace, break_start));
-    axtask::spawn_task(task)
+    let mut task_ext = TaskExt::new(uctx, aspace, app.break_pos, app.stack, app.mmap_base);
+    *task_ext.vm_attrs.get_mut() = app.vm_attrs;
+    #[cfg(feature = "ia32")]
+    task_ext.ia32.reset(app.compat);
+    task.init_task_ext(task_ext);
+    let task = axtask::spawn_task(task);
+    register_process(&task);
+    task
+}
+
+/// The exit code of a task that Linux would kill with `SIGSEGV`, as a shell
+/// reports it. There are no signals yet, so the task exits with it instead.
+pub const SIGSEGV_EXIT_CODE: i32 = 128 + 11;
+
+/// The exit code of a process whose new program could not be loaded. Its
+/// old program is gone by then, and Linux kills it with `SIGSEGV`.
+const EXEC_FAILED_EXIT_CODE: i32 = SIGSEGV_EXIT_CODE;
+
+/// Replace the program of the current task with `exe` and start running it.
+///
+/// The address space is emptied and reused, and the mappings of the old
+/// program are released. The resource limits, the personality and the
+/// `oom_score_adj` of the process are kept.
+pub fn exec_user_task(exe: mm::Executable) -> ! {
+    let (uctx, kstack_top) = {
+        let curr = axtask::current();
+        let task_ext = curr.task_ext();
+        let (app, old_attrs) = {
+            let mut aspace = task_ext.aspace.lock();
+            let mut vm_attrs = task_ext.vm_attrs.lock();
+            // Unmap everything before the shared memory of the old program is
+            // released with its attributes.
+            let old_attrs = mm::clear_user_space(&mut aspace, &mut vm_attrs);
+            let app = mm::load_executable(&mut aspace, exe).map(|mut app| {
+                *vm_attrs = core::mem::replace(&mut app.vm_attrs, VmAttrs::new());
+                app
+            });
+            (app, old_attrs)
+        };
+        drop(old_attrs);
+        let app = match app {
+            Ok(app) => app,
+            Err(e) => {
+                warn!(
+                    "{}: failed to load the new program: {:?}",
+                    curr.id_name(),
+                    e
+                );
+                exit(EXEC_FAILED_EXIT_CODE);
+            }
+        };
+
+        task_ext
+            .break_start
+            .store(app.break_pos.as_usize(), Ordering::Relaxed);
+        *task_ext.break_pos.lock() = app.break_pos;
+        *task_ext.stack.lock() = app.stack;
+        task_ext
+            .mmap_base
+            .store(app.mmap_base.as_usize(), Ordering::Relaxed);
+        task_ext.set_clear_child_tid(0);
+        #[cfg(not(target_arch = "riscv64"))]
+        task_ext.tls.store(0, Ordering::Relaxed);
+        #[cfg(feature = "ia32")]
+        task_ext.ia32.reset(app.compat);
+        task_ext.load_tls();
+
+        let kstack_top = curr.kernel_stack_top().unwrap();
+        info!(
+            "Enter user space: entry={:#x}, ustack={:#x}, kstack={:#x}",
+            app.entry.as_usize(),
+            app.sp.as_usize(),
+            kstack_top,
+        );
+        (uspace_context(&app, 0), kstack_top)
+    };
+    unsafe { uctx.enter_uspace(kstack_top) }
 }
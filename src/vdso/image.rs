@@ -0,0 +1,172 @@
+//! Building the ELF image of the vDSO.
+//!
+//! The image is a minimal shared object: one `PT_LOAD` segment covering the
+//! whole image and a `PT_DYNAMIC` segment with the symbols, which is all that
+//! the dynamic linkers of the C libraries look at.
+use alloc::vec::Vec;
+
+use memory_addr::{align_up_4k, PAGE_SIZE_4K};
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+const PHDR_NUM: usize = 2;
+const DYN_SIZE: usize = 16;
+const SYM_SIZE: usize = 24;
+
+const ET_DYN: u16 = 3;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PF_X: u32 = 1;
+const PF_R: u32 = 4;
+
+const DT_NULL: u64 = 0;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_STRSZ: u64 = 10;
+const DT_SYMENT: u64 = 11;
+const DT_SONAME: u64 = 14;
+
+const STB_GLOBAL: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+/// The name the vDSO is known by, as on Linux.
+const SONAME: &str = "linux-vdso.so.1";
+
+#[cfg(target_arch = "x86_64")]
+const MACHINE: u16 = 62;
+#[cfg(target_arch = "aarch64")]
+const MACHINE: u16 = 183;
+#[cfg(target_arch = "riscv64")]
+const MACHINE: u16 = 243;
+
+/// The RVC and double-float ABI flags, as in the vDSO of Linux.
+#[cfg(target_arch = "riscv64")]
+const FLAGS: u32 = 0x5;
+#[cfg(not(target_arch = "riscv64"))]
+const FLAGS: u32 = 0;
+
+/// A little-endian byte buffer.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn phdr(&mut self, p_type: u32, flags: u32, offset: usize, size: usize, align: usize) {
+        self.u32(p_type);
+        self.u32(flags);
+        self.u64(offset as u64);
+        self.u64(offset as u64);
+        self.u64(offset as u64);
+        self.u64(size as u64);
+        self.u64(size as u64);
+        self.u64(align as u64);
+    }
+
+    fn dyn_entry(&mut self, tag: u64, value: usize) {
+        self.u64(tag);
+        self.u64(value as u64);
+    }
+}
+
+/// Build the image with `code` at `code_offset` and the functions in
+/// `symbols`, given by their names and offsets in `code`.
+///
+/// The image is padded to whole pages.
+pub fn build(code: &[u8], code_offset: usize, symbols: &[(&str, usize)]) -> Vec<u8> {
+    let mut strtab = Vec::from(b"\0" as &[u8]);
+    let mut add_string = |s: &str| {
+        let offset = strtab.len();
+        strtab.extend_from_slice(s.as_bytes());
+        strtab.push(0);
+        offset
+    };
+    let soname = add_string(SONAME);
+    let names: Vec<usize> = symbols.iter().map(|(name, _)| add_string(name)).collect();
+
+    // Symbol 0 is the undefined symbol.
+    let nsyms = symbols.len() + 1;
+    let dynamic_offset = EHDR_SIZE + PHDR_SIZE * PHDR_NUM;
+    let dynamic_size = DYN_SIZE * 7;
+    let symtab_offset = dynamic_offset + dynamic_size;
+    let hash_offset = symtab_offset + SYM_SIZE * nsyms;
+    let strtab_offset = hash_offset + 4 * (2 + 1 + nsyms);
+    assert!(strtab_offset + strtab.len() <= code_offset);
+    let size = align_up_4k(code_offset + code.len());
+
+    let mut w = Writer(Vec::with_capacity(size));
+    // The ELF header.
+    w.0.extend_from_slice(b"\x7fELF");
+    w.u8(2); // 64-bit
+    w.u8(1); // little-endian
+    w.u8(1); // the ELF version
+    w.0.resize(16, 0);
+    w.u16(ET_DYN);
+    w.u16(MACHINE);
+    w.u32(1);
+    w.u64(0); // no entry point
+    w.u64(EHDR_SIZE as u64);
+    w.u64(0); // no section headers
+    w.u32(FLAGS);
+    w.u16(EHDR_SIZE as u16);
+    w.u16(PHDR_SIZE as u16);
+    w.u16(PHDR_NUM as u16);
+    w.u16(64);
+    w.u16(0);
+    w.u16(0);
+
+    w.phdr(PT_LOAD, PF_R | PF_X, 0, size, PAGE_SIZE_4K);
+    w.phdr(PT_DYNAMIC, PF_R, dynamic_offset, dynamic_size, 8);
+
+    w.dyn_entry(DT_HASH, hash_offset);
+    w.dyn_entry(DT_STRTAB, strtab_offset);
+    w.dyn_entry(DT_SYMTAB, symtab_offset);
+    w.dyn_entry(DT_STRSZ, strtab.len());
+    w.dyn_entry(DT_SYMENT, SYM_SIZE);
+    w.dyn_entry(DT_SONAME, soname);
+    w.dyn_entry(DT_NULL, 0);
+
+    w.0.resize(symtab_offset + SYM_SIZE, 0);
+    for (&name, (_, offset)) in names.iter().zip(symbols) {
+        w.u32(name as u32);
+        w.u8((STB_GLOBAL << 4) | STT_FUNC);
+        w.u8(0);
+        // Any defined section will do, there are no section headers.
+        w.u16(1);
+        w.u64((code_offset + offset) as u64);
+        w.u64(0);
+    }
+
+    // A hash table with a single bucket chaining all the symbols.
+    w.u32(1);
+    w.u32(nsyms as u32);
+    w.u32(if nsyms > 1 { 1 } else { 0 });
+    w.u32(0);
+    for index in 1..nsyms {
+        w.u32(if index + 1 < nsyms {
+            index as u32 + 1
+        } else {
+            0
+        });
+    }
+
+    w.0.extend_from_slice(&strtab);
+    w.0.resize(code_offset, 0);
+    w.0.extend_from_slice(code);
+    w.0.resize(size, 0);
+    w.0
+}
@@ -0,0 +1,301 @@
+//! The virtual dynamic shared object (vDSO).
+//!
+//! Every process gets a small shared library mapped by the kernel, whose
+//! functions read the clocks without entering the kernel. They compute the
+//! time from the counter of the CPU and a data page mapped read-only right
+//! below the image. The data page is shared by all processes and refreshed
+//! periodically under a sequence lock.
+//!
+//! The image is built at boot from code assembled into the kernel for each
+//! architecture, see the `.S` files next to this module.
+mod image;
+
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::mem::offset_of;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use core::time::Duration;
+
+use axerrno::{AxError, AxResult};
+use axhal::{mem::virt_to_phys, paging::MappingFlags};
+use axmm::AddrSpace;
+use axsync::Mutex;
+use memory_addr::{PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+use syscalls::Sysno;
+
+use crate::mm::VmAttrs;
+
+/// The auxiliary vector entry with the address of the vDSO.
+pub const AT_SYSINFO_EHDR: u8 = 33;
+
+/// The offset of the code in the image, after the ELF headers and symbols.
+const CODE_OFFSET: usize = 0x800;
+/// The distance from the code back to the data page.
+const DATA_OFFSET: usize = CODE_OFFSET + PAGE_SIZE_4K;
+
+/// The shift of the fixed-point multiplier that converts counter ticks to
+/// nanoseconds.
+const SHIFT: u32 = 24;
+/// How often the data page is refreshed.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(10);
+
+const CLOCK_REALTIME: u32 = 0;
+const CLOCK_MONOTONIC: u32 = 1;
+const CLOCK_MONOTONIC_RAW: u32 = 4;
+const CLOCK_REALTIME_COARSE: u32 = 5;
+const CLOCK_MONOTONIC_COARSE: u32 = 6;
+const CLOCK_BOOTTIME: u32 = 7;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const NANOS_PER_MICROS: u64 = 1_000;
+
+/// The data page read by the vDSO.
+///
+/// The monotonic time is `mono_last + (counter - cycle_last) * mult >>
+/// shift`, and the real time adds `wall_offset`. The coarse clocks skip the
+/// counter and return the time of the last refresh.
+#[repr(C, align(4096))]
+struct VdsoData {
+    /// The sequence count, odd while the page is being updated.
+    seq: AtomicU64,
+    /// The counter at the last refresh.
+    cycle_last: AtomicU64,
+    /// The monotonic time at the last refresh, in nanoseconds.
+    mono_last: AtomicU64,
+    /// The real time minus the monotonic time, in nanoseconds.
+    wall_offset: AtomicU64,
+    mult: AtomicU64,
+    shift: AtomicU64,
+}
+
+// The aarch64 code loads the sequence count with `ldar`, which takes no
+// offset.
+const _: () = assert!(offset_of!(VdsoData, seq) == 0);
+
+static VDSO_DATA: VdsoData = VdsoData {
+    seq: AtomicU64::new(0),
+    cycle_last: AtomicU64::new(0),
+    mono_last: AtomicU64::new(0),
+    wall_offset: AtomicU64::new(0),
+    mult: AtomicU64::new(0),
+    shift: AtomicU64::new(0),
+};
+
+macro_rules! vdso_asm {
+    ($file:literal) => {
+        global_asm!(
+            include_str!($file),
+            data_offset = const DATA_OFFSET,
+            seq = const offset_of!(VdsoData, seq),
+            cycle_last = const offset_of!(VdsoData, cycle_last),
+            mono_last = const offset_of!(VdsoData, mono_last),
+            wall_offset = const offset_of!(VdsoData, wall_offset),
+            mult = const offset_of!(VdsoData, mult),
+            shift = const offset_of!(VdsoData, shift),
+            clock_realtime = const CLOCK_REALTIME,
+            clock_monotonic = const CLOCK_MONOTONIC,
+            clock_monotonic_raw = const CLOCK_MONOTONIC_RAW,
+            clock_realtime_coarse = const CLOCK_REALTIME_COARSE,
+            clock_monotonic_coarse = const CLOCK_MONOTONIC_COARSE,
+            clock_boottime = const CLOCK_BOOTTIME,
+            nsec_per_sec = const NANOS_PER_SEC,
+            nsec_per_usec = const NANOS_PER_MICROS,
+            sys_clock_gettime = const Sysno::clock_gettime as u32,
+            sys_getcpu = const Sysno::getcpu as u32,
+            sys_rt_sigreturn = const Sysno::rt_sigreturn as u32,
+        );
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+vdso_asm!("x86_64.S");
+#[cfg(target_arch = "riscv64")]
+vdso_asm!("riscv64.S");
+#[cfg(target_arch = "aarch64")]
+vdso_asm!("aarch64.S");
+
+extern "C" {
+    fn starry_vdso_start();
+    fn starry_vdso_end();
+    fn starry_vdso_clock_gettime();
+    fn starry_vdso_gettimeofday();
+    fn starry_vdso_time();
+    fn starry_vdso_getcpu();
+    fn starry_vdso_rt_sigreturn();
+}
+
+/// The functions exported by the vDSO and their code.
+fn symbols() -> Vec<(&'static str, usize)> {
+    let clock_gettime = starry_vdso_clock_gettime as *const () as usize;
+    let gettimeofday = starry_vdso_gettimeofday as *const () as usize;
+    let rt_sigreturn = starry_vdso_rt_sigreturn as *const () as usize;
+    #[allow(unused_mut)]
+    let mut symbols = alloc::vec![
+        ("__vdso_clock_gettime", clock_gettime),
+        ("__vdso_gettimeofday", gettimeofday),
+        ("__vdso_time", starry_vdso_time as *const () as usize),
+        ("__vdso_getcpu", starry_vdso_getcpu as *const () as usize),
+        ("__vdso_rt_sigreturn", rt_sigreturn),
+    ];
+    // The names used by the C libraries on aarch64.
+    #[cfg(target_arch = "aarch64")]
+    symbols.extend([
+        ("__kernel_clock_gettime", clock_gettime),
+        ("__kernel_gettimeofday", gettimeofday),
+        ("__kernel_rt_sigreturn", rt_sigreturn),
+    ]);
+    symbols
+}
+
+/// The vDSO image, built by [`init`].
+struct VdsoImage {
+    paddr: PhysAddr,
+    size: usize,
+}
+
+static VDSO_IMAGE: Mutex<Option<VdsoImage>> = Mutex::new(None);
+
+/// Read the counter the vDSO computes the time from.
+fn read_counter() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        let (low, high): (u32, u32);
+        core::arch::asm!("lfence; rdtsc", out("eax") low, out("edx") high);
+        ((high as u64) << 32) | low as u64
+    }
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        let time: u64;
+        core::arch::asm!("rdtime {}", out(reg) time);
+        time
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        let time: u64;
+        core::arch::asm!("isb; mrs {}, cntvct_el0", out(reg) time);
+        time
+    }
+}
+
+/// Allow user mode to read the counter.
+fn enable_user_counter() {
+    // The time stamp counter is readable from user mode by default on x86.
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        // scounteren.TM
+        core::arch::asm!("csrs scounteren, {}", in(reg) 1 << 1);
+    }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        // CNTKCTL_EL1.EL0VCTEN
+        core::arch::asm!(
+            "mrs {tmp}, cntkctl_el1",
+            "orr {tmp}, {tmp}, #2",
+            "msr cntkctl_el1, {tmp}",
+            tmp = out(reg) _,
+        );
+    }
+}
+
+/// Set up the current CPU for the vDSO. This runs on every CPU, as the
+/// access to the counter is controlled per CPU.
+pub fn init_percpu() {
+    enable_user_counter();
+}
+
+/// Refresh the data page.
+///
+/// The real time is taken from the kernel clock at every refresh, so that
+/// the vDSO follows it rather than drifting away at the rate of its own
+/// multiplier.
+fn update() {
+    let data = &VDSO_DATA;
+    let cycles = read_counter();
+    let seq = data.seq.load(Ordering::Relaxed);
+    data.seq.store(seq + 1, Ordering::Relaxed);
+    fence(Ordering::Release);
+    // Advance along the same line as the vDSO, so that the time never goes
+    // backwards across a refresh.
+    let delta = cycles.wrapping_sub(data.cycle_last.load(Ordering::Relaxed));
+    let elapsed = (delta as u128 * data.mult.load(Ordering::Relaxed) as u128) >> SHIFT;
+    let mono = data.mono_last.load(Ordering::Relaxed) + elapsed as u64;
+    data.mono_last.store(mono, Ordering::Relaxed);
+    data.cycle_last.store(cycles, Ordering::Relaxed);
+    data.wall_offset.store(
+        axhal::time::wall_time_nanos().wrapping_sub(mono),
+        Ordering::Relaxed,
+    );
+    data.seq.store(seq + 2, Ordering::Release);
+}
+
+/// Build the vDSO image, initialize the data page and start refreshing it.
+pub fn init() {
+    let data = &VDSO_DATA;
+    let mono = axhal::time::monotonic_time_nanos();
+    data.cycle_last.store(read_counter(), Ordering::Relaxed);
+    data.mono_last.store(mono, Ordering::Relaxed);
+    data.wall_offset.store(
+        axhal::time::wall_time_nanos().wrapping_sub(mono),
+        Ordering::Relaxed,
+    );
+    data.mult
+        .store(axhal::time::ticks_to_nanos(1 << SHIFT), Ordering::Relaxed);
+    data.shift.store(SHIFT as u64, Ordering::Relaxed);
+    fence(Ordering::Release);
+
+    let start = starry_vdso_start as *const () as usize;
+    let code = unsafe {
+        core::slice::from_raw_parts(
+            start as *const u8,
+            starry_vdso_end as *const () as usize - start,
+        )
+    };
+    let symbols: Vec<_> = symbols()
+        .into_iter()
+        .map(|(name, addr)| (name, addr - start))
+        .collect();
+    let image = image::build(code, CODE_OFFSET, &symbols);
+    let pages = image.len() / PAGE_SIZE_4K;
+    let vaddr = axalloc::global_allocator()
+        .alloc_pages(pages, PAGE_SIZE_4K)
+        .expect("failed to allocate the vDSO image");
+    unsafe { core::ptr::copy_nonoverlapping(image.as_ptr(), vaddr as *mut u8, image.len()) };
+    *VDSO_IMAGE.lock() = Some(VdsoImage {
+        paddr: virt_to_phys(vaddr.into()),
+        size: image.len(),
+    });
+
+    axtask::spawn(|| loop {
+        axtask::sleep(UPDATE_INTERVAL);
+        update();
+    });
+}
+
+/// Map the data page and the vDSO image into `aspace`, in a free area of
+/// `limit`. Returns the address of the image.
+pub fn map(
+    aspace: &mut AddrSpace,
+    vm_attrs: &mut VmAttrs,
+    hint: VirtAddr,
+    limit: VirtAddrRange,
+) -> AxResult<VirtAddr> {
+    let image = VDSO_IMAGE.lock();
+    let image = image.as_ref().ok_or(AxError::BadState)?;
+    let start = aspace
+        .find_free_area(hint, PAGE_SIZE_4K + image.size, limit)
+        .ok_or(AxError::NoMemory)?;
+    let data_flags = MappingFlags::READ | MappingFlags::USER;
+    let data_paddr = virt_to_phys(VirtAddr::from(&VDSO_DATA as *const _ as usize));
+    aspace.map_linear(start, data_paddr, PAGE_SIZE_4K, data_flags)?;
+    let data_range = VirtAddrRange::from_start_size(start, PAGE_SIZE_4K);
+    vm_attrs.mapped(data_range, data_flags);
+    vm_attrs.kernel_maps.insert(data_range, ());
+
+    let base = start + PAGE_SIZE_4K;
+    let image_flags = MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER;
+    aspace.map_linear(base, image.paddr, image.size, image_flags)?;
+    let image_range = VirtAddrRange::from_start_size(base, image.size);
+    vm_attrs.mapped(image_range, image_flags);
+    vm_attrs.kernel_maps.insert(image_range, ());
+    Ok(base)
+}
@@ -0,0 +1,308 @@
+//! Path resolution, open directories and the directories that exist only in
+//! the kernel.
+//!
+//! `/dev/shm` holds the tmpfs files of the page cache and `/initrd/bin` the
+//! apps built into the kernel image. They are not in the file system, so
+//! looking them up, listing them and changing into them are served here. The
+//! current directory is kept here while it is one of them. Directories are
+//! opened like memfds: their descriptors refer to `/dev/null` and are bound
+//! to the path of the directory, which `getdents64` lists and the `*at`
+//! system calls resolve relative paths against.
+use alloc::{
+    collections::btree_map::BTreeMap,
+    ffi::CString,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arceos_posix_api::{self as api, ctypes, ctypes::AT_FDCWD};
+use axerrno::{AxResult, LinuxError, LinuxResult};
+use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::mm::page_cache::{self, INITRD_DIR, TMPFS_DIR};
+
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+/// The directory entry type of directories.
+pub const DT_DIR: u8 = 4;
+/// The directory entry type of regular files.
+pub const DT_REG: u8 = 8;
+/// The directory entry type of character devices.
+const DT_CHR: u8 = 2;
+/// The directory entry type of entries whose type is not known.
+const DT_UNKNOWN: u8 = 0;
+
+/// The directory that holds [`INITRD_DIR`].
+const INITRD_ROOT: &str = "/initrd";
+
+/// The current directory if it exists only in the kernel, which the file
+/// system cannot change into.
+static VIRTUAL_CWD: Mutex<Option<String>> = Mutex::new(None);
+
+/// Get the normalized absolute path of the current directory.
+pub fn current_dir() -> AxResult<String> {
+    match VIRTUAL_CWD.lock().clone() {
+        Some(path) => Ok(path),
+        None => axstd::env::current_dir(),
+    }
+}
+
+/// Change the current directory to the normalized absolute `path`.
+pub fn set_current_dir(path: &str) -> LinuxResult {
+    if VirtualDir::at(path).is_some() {
+        *VIRTUAL_CWD.lock() = Some(String::from(path));
+        return Ok(());
+    }
+    if !is_dir(path) {
+        stat(path)?;
+        return Err(LinuxError::ENOTDIR);
+    }
+    axstd::env::set_current_dir(path)?;
+    *VIRTUAL_CWD.lock() = None;
+    Ok(())
+}
+
+/// Turn `path` into a normalized absolute path.
+pub fn absolute_path(path: &str) -> AxResult<String> {
+    let full_path = if path.starts_with('/') {
+        String::from(path)
+    } else {
+        format!("{}/{}", current_dir()?, path)
+    };
+    let mut parts = Vec::new();
+    for part in full_path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    Ok(format!("/{}", parts.join("/")))
+}
+
+/// Turn `path` into a normalized absolute path, resolving relative paths
+/// against the directory open as `dirfd` unless it is `AT_FDCWD`.
+pub fn resolve_at(dirfd: i32, path: &str) -> LinuxResult<String> {
+    if path.is_empty() {
+        return Err(LinuxError::ENOENT);
+    }
+    if path.starts_with('/') || dirfd == AT_FDCWD {
+        return Ok(absolute_path(path)?);
+    }
+    let dir = fd_dir(dirfd).ok_or_else(|| {
+        let mut st: ctypes::stat = unsafe { core::mem::zeroed() };
+        if unsafe { api::sys_fstat(dirfd, &mut st) } == 0 {
+            LinuxError::ENOTDIR
+        } else {
+            LinuxError::EBADF
+        }
+    })?;
+    Ok(absolute_path(&format!("{}/{}", dir.path, path))?)
+}
+
+/// A directory that exists only in the kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum VirtualDir {
+    /// [`TMPFS_DIR`], with the tmpfs files.
+    Tmpfs,
+    /// [`INITRD_ROOT`], with [`INITRD_DIR`].
+    InitrdRoot,
+    /// [`INITRD_DIR`], with the apps built into the kernel image.
+    Initrd,
+}
+
+impl VirtualDir {
+    /// Get the directory at the normalized absolute `path`.
+    fn at(path: &str) -> Option<Self> {
+        match path {
+            TMPFS_DIR => Some(Self::Tmpfs),
+            INITRD_ROOT => Some(Self::InitrdRoot),
+            INITRD_DIR => Some(Self::Initrd),
+            _ => None,
+        }
+    }
+
+    /// The names and types of the entries of the directory.
+    fn entries(self) -> Vec<(String, u8)> {
+        let (names, d_type) = match self {
+            Self::Tmpfs => (page_cache::tmpfs_names(), DT_REG),
+            Self::InitrdRoot => (vec!["bin".to_string()], DT_DIR),
+            Self::Initrd => (page_cache::initrd_names(), DT_REG),
+        };
+        names.into_iter().map(|name| (name, d_type)).collect()
+    }
+
+    /// The permission bits of the directory.
+    fn mode(self) -> u32 {
+        match self {
+            Self::Tmpfs => 0o1777,
+            Self::InitrdRoot | Self::Initrd => 0o555,
+        }
+    }
+}
+
+/// Whether the file or directory at the normalized absolute `path` is on a
+/// read-only file system.
+pub fn is_read_only(path: &str) -> bool {
+    matches!(
+        VirtualDir::at(path),
+        Some(VirtualDir::InitrdRoot | VirtualDir::Initrd)
+    ) || page_cache::in_initrd(path)
+}
+
+/// The directories that exist only in the kernel and are in the directory
+/// at `path` of the file system.
+fn virtual_children(path: &str) -> impl Iterator<Item = (String, u8)> + '_ {
+    [TMPFS_DIR, INITRD_ROOT].into_iter().filter_map(move |dir| {
+        let (parent, name) = dir.rsplit_once('/')?;
+        let parent = if parent.is_empty() { "/" } else { parent };
+        (parent == path).then(|| (name.to_string(), DT_DIR))
+    })
+}
+
+/// A made-up inode number for the file at `path`, as the file system has
+/// none.
+fn ino_of(path: &str) -> u64 {
+    // FNV-1a
+    path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// Whether the normalized absolute `path` names a directory.
+pub fn is_dir(path: &str) -> bool {
+    VirtualDir::at(path).is_some() || axstd::fs::read_dir(path).is_ok()
+}
+
+/// Get the status of the file at the normalized absolute `path`.
+pub fn stat(path: &str) -> LinuxResult<ctypes::stat> {
+    let mut st: ctypes::stat = unsafe { core::mem::zeroed() };
+    if let Some(dir) = VirtualDir::at(path) {
+        st.st_mode = S_IFDIR | dir.mode();
+        st.st_nlink = 2;
+    } else if page_cache::in_tmpfs(path) {
+        let size = page_cache::open_tmpfs(path, false, false)?.size();
+        st.st_mode = S_IFREG | 0o600;
+        st.st_nlink = 1;
+        st.st_size = size as _;
+        st.st_blocks = size.div_ceil(512) as _;
+    } else if page_cache::in_initrd(path) {
+        let size = page_cache::open(path)?.size();
+        st.st_mode = S_IFREG | 0o555;
+        st.st_nlink = 1;
+        st.st_size = size as _;
+        st.st_blocks = size.div_ceil(512) as _;
+    } else {
+        let c_path = CString::new(path).map_err(|_| LinuxError::EINVAL)?;
+        let ret = unsafe { api::sys_stat(c_path.as_ptr(), &mut st) };
+        if ret < 0 {
+            return Err(LinuxError::try_from(-ret).unwrap_or(LinuxError::EIO));
+        }
+        // The page cache may hold data that has not been written back yet.
+        if let Some(file) = page_cache::lookup(path) {
+            let size = file.size();
+            st.st_size = size as _;
+            st.st_blocks = size.div_ceil(512) as _;
+        }
+        return Ok(st);
+    }
+    st.st_ino = ino_of(path);
+    st.st_blksize = PAGE_SIZE_4K as _;
+    Ok(st)
+}
+
+/// Get the names and types of the entries of the directory at the
+/// normalized absolute `path`, including `.` and `..`.
+pub fn read_dir(path: &str) -> LinuxResult<Vec<(String, u8)>> {
+    let mut entries = vec![(".".to_string(), DT_DIR), ("..".to_string(), DT_DIR)];
+    if let Some(dir) = VirtualDir::at(path) {
+        entries.extend(dir.entries());
+        return Ok(entries);
+    }
+    for entry in axstd::fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type();
+        let d_type = if file_type.is_dir() {
+            DT_DIR
+        } else if file_type.is_file() {
+            DT_REG
+        } else if file_type.is_char_device() {
+            DT_CHR
+        } else {
+            DT_UNKNOWN
+        };
+        entries.push((entry.file_name(), d_type));
+    }
+    for child in virtual_children(path) {
+        if entries.iter().all(|(name, _)| *name != child.0) {
+            entries.push(child);
+        }
+    }
+    Ok(entries)
+}
+
+/// A directory opened through a file descriptor.
+pub struct OpenDir {
+    /// The normalized absolute path of the directory.
+    pub path: String,
+    /// The index of the next entry `getdents64` returns.
+    pos: AtomicUsize,
+}
+
+impl OpenDir {
+    /// The index of the next entry to read.
+    pub fn pos(&self) -> usize {
+        self.pos.load(Ordering::Acquire)
+    }
+
+    /// Set the index of the next entry to read.
+    pub fn set_pos(&self, pos: usize) {
+        self.pos.store(pos, Ordering::Release);
+    }
+
+    /// A made-up inode number for the entry `name`.
+    pub fn ino_of(&self, name: &str) -> u64 {
+        ino_of(&format!("{}/{}", self.path, name))
+    }
+}
+
+/// The directories opened through file descriptors. Duplicates of a
+/// descriptor share the position.
+static OPEN_DIRS: Mutex<BTreeMap<i32, Arc<OpenDir>>> = Mutex::new(BTreeMap::new());
+
+/// Make `fd` refer to the directory at the normalized absolute `path`.
+pub fn bind_dir(fd: i32, path: String) {
+    let dir = OpenDir {
+        path,
+        pos: AtomicUsize::new(0),
+    };
+    OPEN_DIRS.lock().insert(fd, Arc::new(dir));
+}
+
+/// Make the duplicate `new_fd` of `fd` refer to the same directory, if `fd`
+/// refers to one.
+pub fn copy_binding(fd: i32, new_fd: i32) {
+    let mut dirs = OPEN_DIRS.lock();
+    dirs.remove(&new_fd);
+    if let Some(dir) = dirs.get(&fd).cloned() {
+        dirs.insert(new_fd, dir);
+    }
+}
+
+/// Stop `fd` from referring to a directory.
+pub fn unbind_fd(fd: i32) {
+    OPEN_DIRS.lock().remove(&fd);
+}
+
+/// Get the directory opened as `fd`, if any.
+pub fn fd_dir(fd: i32) -> Option<Arc<OpenDir>> {
+    OPEN_DIRS.lock().get(&fd).cloned()
+}